@@ -1,15 +1,28 @@
 use std::{fs, path::Path};
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use mmft_board_router::{board_router::route, utils::read_input_from_file};
+use mmft_board_router::{
+    board_router::{route, RoutingBackend},
+    utils::read_input_from_file,
+};
 use walkdir::WalkDir;
 
 const DIR: &str = "./benches/cases/";
 const ONLY_GROUPS: [&str; 0] = [];
 const TARGET_DIR: &str = "./target/criterion/";
+// Comma-separated backends to benchmark, "grid" (the default) and "gridless"; groups of the gridless backend get a "_gridless" suffix
+const BACKEND_VAR: &str = "BACKEND";
 
 fn criterion_benchmark(c: &mut Criterion) {
     let _ = fs::remove_dir_all(Path::new(TARGET_DIR));
+    let backends = match std::env::var(BACKEND_VAR) {
+        Ok(names) => Vec::from_iter(names.split(',').map(|name| match name.trim() {
+            "grid" => RoutingBackend::Grid,
+            "gridless" => RoutingBackend::Gridless,
+            other => panic!("Unknown backend {}", other),
+        })),
+        Err(_) => Vec::from([RoutingBackend::Grid]),
+    };
     for group in WalkDir::new(DIR)
         .min_depth(1)
         .max_depth(1)
@@ -29,27 +42,37 @@ fn criterion_benchmark(c: &mut Criterion) {
             continue
         }
 
-        let mut benchmark_group = c.benchmark_group(group_name);
-        for case in WalkDir::new(group.path())
-            .min_depth(1)
-            .max_depth(1)
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            if case.path().is_file()
-                && case
-                    .path()
-                    .extension()
-                    .unwrap()
-                    .eq_ignore_ascii_case("json")
+        for &backend in backends.iter() {
+            let mut benchmark_group = match backend {
+                RoutingBackend::Grid => c.benchmark_group(group_name.clone()),
+                RoutingBackend::Gridless => c.benchmark_group(format!("{}_gridless", group_name)),
+            };
+            for case in WalkDir::new(group.path())
+                .min_depth(1)
+                .max_depth(1)
+                .into_iter()
+                .filter_map(|e| e.ok())
             {
-                let input = read_input_from_file(case.path()).expect("Error reading configuration");
+                if case.path().is_file()
+                    && case
+                        .path()
+                        .extension()
+                        .unwrap()
+                        .eq_ignore_ascii_case("json")
+                {
+                    let mut input = read_input_from_file(case.path()).expect("Error reading configuration");
+                    input.backend = backend;
 
-                benchmark_group.bench_function(case.path().to_str().unwrap(), |b| {
-                    b.iter(|| {
-                        route(black_box(&input)).expect("No solution found");
-                    })
-                });
+                    benchmark_group.bench_function(case.path().to_str().unwrap(), |b| {
+                        b.iter(|| {
+                            let result = route(black_box(&input));
+                            // The gridless backend does not route every case
+                            if backend == RoutingBackend::Grid {
+                                result.expect("No solution found");
+                            }
+                        })
+                    });
+                }
             }
         }
     }
@@ -5,6 +5,11 @@ use std::{
     path::Path,
 };
 
+use clap::Parser;
+use mmft_board_router::{
+    board_router::{route, BoardRouterOutputError, RoutingBackend, RoutingStatistics},
+    utils::read_input_from_file,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use statistical::{mean, standard_deviation};
@@ -14,6 +19,14 @@ const DIR: &str = "./benches/cases";
 const TARGET_DIR: &str = "./target/criterion";
 const NEW_DIR: &str = "new";
 const ESTIMATE_FILE: &str = "estimates.json";
+const GRIDLESS_SUFFIX: &str = "_gridless";
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[arg(short, long)]
+    statistics: bool, // route the cases of each group again and aggregate their routing statistics
+}
 
 #[derive(Serialize, Deserialize)]
 struct Aggregate {
@@ -29,6 +42,20 @@ struct GroupAggregate {
     min: f64,
     max: f64,
     values: Vec<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    statistics: Option<GroupStatistics>,
+}
+
+// Means over the (partially) routed cases of a group
+#[derive(Serialize, Deserialize)]
+struct GroupStatistics {
+    n: usize,
+    length: f64,
+    bends_45: f64,
+    bends_90: f64,
+    expansions: f64,
+    time_ms: f64,
+    area: f64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -43,7 +70,53 @@ fn get_mean(obj: Value) -> Option<f64> {
     obj.get("mean")?.get("point_estimate")?.as_f64()
 }
 
+fn group_statistics(group_name: &str) -> Option<GroupStatistics> {
+    let (case_group, backend) = match group_name.strip_suffix(GRIDLESS_SUFFIX) {
+        Some(case_group) => (case_group, RoutingBackend::Gridless),
+        None => (group_name, RoutingBackend::Grid),
+    };
+
+    let mut statistics = Vec::new();
+    for case in WalkDir::new(Path::new(DIR).join(case_group))
+        .min_depth(1)
+        .max_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if !case.path().is_file() {
+            continue;
+        }
+        let mut input = match read_input_from_file(case.path()) {
+            Ok(input) => input,
+            Err(_) => continue,
+        };
+        input.backend = backend;
+        match route(&input) {
+            Ok(board) | Err(BoardRouterOutputError::PartialResult(board)) => statistics.push(board.statistics),
+            Err(_) => continue,
+        }
+    }
+
+    if statistics.is_empty() {
+        return None;
+    }
+
+    let mean_of = |value: &dyn Fn(&RoutingStatistics) -> f64| {
+        mean(&statistics.iter().map(value).collect::<Vec<_>>())
+    };
+    Some(GroupStatistics {
+        n: statistics.len(),
+        length: mean_of(&|s| s.length),
+        bends_45: mean_of(&|s| s.bends.bends_45 as f64),
+        bends_90: mean_of(&|s| s.bends.bends_90 as f64),
+        expansions: mean_of(&|s| s.expansions as f64),
+        time_ms: mean_of(&|s| s.time_ms),
+        area: mean_of(&|s| s.area),
+    })
+}
+
 fn main() {
+    let args = Args::parse();
     let mut aggregate = Aggregate {
         groups: HashMap::new(),
     };
@@ -106,6 +179,7 @@ fn main() {
         let min = entries.iter().fold(f64::INFINITY, |a, &b| a.min(b));
         let max = entries.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
 
+        let group_name = group.file_name().to_str().unwrap().to_owned();
         let group_aggregate = GroupAggregate {
             n: entries.len(),
             mean,
@@ -119,12 +193,10 @@ fn main() {
                 max: max / 1000000.,
             },
             values: entries,
+            statistics: if args.statistics { group_statistics(&group_name) } else { None },
         };
 
-        aggregate.groups.insert(
-            group.file_name().to_str().unwrap().to_owned(),
-            group_aggregate,
-        );
+        aggregate.groups.insert(group_name, group_aggregate);
     }
 
     let results = serde_json::to_string_pretty(&aggregate).expect("Could not serialize results");
@@ -5,7 +5,8 @@ use std::{
 };
 
 use mmft_board_router::board_router::{
-    compute_ports, route, ComputePortsInput, ComputePortsOutput, ConnectionID, Layout, RouteInput, RouteInputConnection
+    compute_ports, route, ComputePortsInput, ComputePortsOutput, ConnectionID, Layout, Port, RouteInput,
+    RouteInputConnection,
 };
 use threadpool::ThreadPool;
 
@@ -717,11 +718,7 @@ fn random_port_connections(
             };
             let connection = random_port_connection(ports, &options, &occupied);
             connection.iter().for_each(|c| occupied.push(*c));
-            RouteInputConnection {
-                id: i as ConnectionID, 
-                ports: connection,
-                branch_port: None
-            }
+            route_input_connection(i as ConnectionID, connection)
         })
         .collect()
 }
@@ -745,11 +742,7 @@ fn random_port_connections_incremental(
         };
         for j in 0..tries_per_connection {
             let connection = random_port_connection(ports, &options, &mut occupied);
-            connections.push(RouteInputConnection {
-                id: i as ConnectionID,
-                ports: connection.clone(),
-                branch_port: None,
-            });
+            connections.push(route_input_connection(i as ConnectionID, connection.clone()));
             let is_valid = is_valid(&connections);
             if !is_valid {
                 if j == tries_per_connection - 1 {
@@ -786,6 +779,58 @@ struct RandomGenerationOptions {
     layout: Layout,
 }
 
+fn route_input_connection(id: ConnectionID, ports: Vec<Port>) -> RouteInputConnection {
+    RouteInputConnection {
+        id,
+        ports,
+        branch_port: None,
+        priority: None,
+        channel_width: None,
+        channel_spacing: None,
+        target_resistance: None,
+        serpentine: None,
+        port_candidates: Vec::new(),
+    }
+}
+
+fn route_input(options: &RandomGenerationOptions, connections: Vec<RouteInputConnection>) -> RouteInput {
+    RouteInput {
+        board_width: options.board_width,
+        board_height: options.board_height,
+        pitch: options.pitch,
+        pitch_offset_x: options.pitch_offset_x,
+        pitch_offset_y: options.pitch_offset_y,
+        port_diameter: options.port_diameter,
+        channel_width: options.channel_width,
+        channel_spacing: options.channel_spacing,
+        layout: options.layout,
+        max_ports: MAX_PORTS,
+        connections,
+        rip_up_reroute: None,
+        ordering: Default::default(),
+        layers: None,
+        via_cost: None,
+        multi_port_routing: Default::default(),
+        keep_outs: Vec::new(),
+        blocked_ports: Vec::new(),
+        locked_connections: Vec::new(),
+        length_match_groups: Vec::new(),
+        channel_height: None,
+        viscosity: None,
+        smoothing: None,
+        ports: Vec::new(),
+        edge_ports: Vec::new(),
+        cell_size: None,
+        grid_snapshots: false,
+        backend: Default::default(),
+        bidirectional_search: false,
+        search_limits: Default::default(),
+        cost_model: Default::default(),
+        heuristic: Default::default(),
+        heuristic_bias: None,
+    }
+}
+
 fn random_cases(options: &RandomGenerationOptions) -> Vec<(String, RouteInput)> {
     let &RandomGenerationOptions {
         n_connections,
@@ -795,10 +840,6 @@ fn random_cases(options: &RandomGenerationOptions) -> Vec<(String, RouteInput)>
         pitch,
         pitch_offset_x,
         pitch_offset_y,
-        port_diameter,
-        channel_width,
-        channel_spacing,
-        layout,
         use_incremental,
         silent,
         ..
@@ -817,7 +858,7 @@ fn random_cases(options: &RandomGenerationOptions) -> Vec<(String, RouteInput)>
         pitch_offset_y,
     });
 
-    let options = RandomPortConnectionsOptions {
+    let port_options = RandomPortConnectionsOptions {
         n_connections_2,
         n_connections_3,
         n_connections_4,
@@ -844,21 +885,9 @@ fn random_cases(options: &RandomGenerationOptions) -> Vec<(String, RouteInput)>
 
         let connections = if use_incremental {
             let r = random_port_connections_incremental(
-                &options,
+                &port_options,
                 |connections| {
-                    has_successful_result(&RouteInput {
-                        board_width,
-                        board_height,
-                        pitch,
-                        pitch_offset_x,
-                        pitch_offset_y,
-                        port_diameter,
-                        channel_width,
-                        channel_spacing,
-                        layout,
-                        max_ports: MAX_PORTS,
-                        connections: connections.clone(),
-                    })
+                    has_successful_result(&route_input(options, connections.clone()))
                 },
                 incremental_tries_per_connection,
             );
@@ -867,22 +896,10 @@ fn random_cases(options: &RandomGenerationOptions) -> Vec<(String, RouteInput)>
                 Err(_) => continue,
             }
         } else {
-            random_port_connections(&options)
+            random_port_connections(&port_options)
         };
 
-        let input = RouteInput {
-            board_width,
-            board_height,
-            pitch,
-            pitch_offset_x,
-            pitch_offset_y,
-            port_diameter,
-            channel_width,
-            channel_spacing,
-            layout,
-            max_ports: MAX_PORTS,
-            connections,
-        };
+        let input = route_input(options, connections);
 
         if has_successful_result(&input) {
             cases.push((nanoid!(), input));
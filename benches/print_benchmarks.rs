@@ -59,7 +59,9 @@ fn print_benchmark_for_file(file_name: &str) {
         board_height: input.board_height,
         channel_width: input.channel_width,
         channel_cap: mmft_board_router::dxf::ChannelCap::Butt,
-        port_diameter: Some(input.port_diameter)
+        port_diameter: Some(input.port_diameter),
+        ports: input.ports.clone(),
+        grid: None,
     });
     let mut svg_file_name = PathBuf::from(file);
     svg_file_name.set_extension("svg");
@@ -1,5 +1,8 @@
 use clap::Parser;
-use mmft_board_router::{board_router::route, utils::read_input_from_file};
+use mmft_board_router::{
+    board_router::{route, RoutingBackend},
+    utils::read_input_from_file,
+};
 use walkdir::WalkDir;
 
 const DIR: &str = "./benches/cases";
@@ -9,9 +12,12 @@ const DIR: &str = "./benches/cases";
 struct Args {
     #[arg(short, long)]
     file: Option<String>,
+    #[arg(short, long)]
+    gridless: bool, // route with the gridless backend
 }
 
 fn main() {
+    let args = Args::parse();
     let mut success = 0;
     let mut total = 0;
     let mut ignored = 0;
@@ -25,8 +31,11 @@ fn main() {
         {
             let content = read_input_from_file(entry.path());
             match content {
-                Ok(input) => {
+                Ok(mut input) => {
                     total += 1;
+                    if args.gridless {
+                        input.backend = RoutingBackend::Gridless;
+                    }
                     let result = route(&input);
                     match result {
                         Ok(_) => success += 1,
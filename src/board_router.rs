@@ -1,11 +1,26 @@
+// Partial results are returned as errors, so the error variant of the routing results is as large as the output board
+#![allow(clippy::result_large_err)]
+
 use core::f64;
 use serde::{Deserialize, Serialize};
 use std::{
+    cell::{Cell, RefCell},
     cmp::Ordering,
     collections::{HashMap, HashSet, VecDeque},
 };
 
-use crate::graph_search::{a_star, AStarNode};
+use crate::{
+    graph_search::{a_star_limited, bidirectional_a_star, now_ms, AStarNode, SearchDirection, SearchResult},
+    gridless_router,
+    hydraulics::{channel_length, channel_resistance, rectangular_channel_resistance},
+    port_nomenclature::port_index_to_identifier,
+    smoothing::{fillet_radii, rounded_channel, segments_distance, shortcut, simplify},
+};
+
+#[cfg(not(target_family = "wasm"))]
+use std::sync::mpsc;
+#[cfg(not(target_family = "wasm"))]
+use threadpool::ThreadPool;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RouteInput {
@@ -20,6 +35,317 @@ pub struct RouteInput {
     pub port_diameter: f64,
     pub max_ports: usize,
     pub connections: RouteInputConnections,
+    #[serde(default)]
+    pub rip_up_reroute: Option<RipUpReroute>,
+    #[serde(default)]
+    pub ordering: ConnectionOrdering,
+    #[serde(default)]
+    pub layers: Option<usize>, // number of channel layers connected by vias, a single layer if unset
+    #[serde(default)]
+    pub via_cost: Option<f64>, // cost of a via in cells, DEFAULT_VIA_COST if unset
+    #[serde(default)]
+    pub multi_port_routing: MultiPortRouting,
+    #[serde(default)]
+    pub keep_outs: Vec<KeepOut>, // regions (in board units) no channel may overlap, on all layers
+    #[serde(default)]
+    pub blocked_ports: Vec<Port>, // ports no channel may pass
+    // Fixed channels (e.g., of a previous result) that are emitted unchanged and routed around, on all layers
+    #[serde(default)]
+    pub locked_connections: Vec<BoardRouterOutputConnection>,
+    #[serde(default)]
+    pub length_match_groups: Vec<LengthMatchGroup>,
+    // Together with the viscosity, enables the hydraulic resistances in the output and target resistances
+    #[serde(default)]
+    pub channel_height: Option<f64>,
+    #[serde(default)]
+    pub viscosity: Option<f64>,
+    #[serde(default)]
+    pub smoothing: Option<Smoothing>,
+    #[serde(default)]
+    pub ports: Vec<PortDefinition>, // explicit ports, replacing the pitch grid (and the port diameter) if not empty
+    #[serde(default)]
+    pub edge_ports: Vec<EdgePort>,
+    // Size of the routing grid cells (rounded down to divide the pitch), the channel distance if unset; on a finer
+    // grid, channels keep their clearance by distance
+    #[serde(default)]
+    pub cell_size: Option<f64>,
+    #[serde(default)]
+    pub grid_snapshots: bool, // report the routing grid after setup and after routing in the output
+    #[serde(default)]
+    pub backend: RoutingBackend,
+    // Search long connections (from BIDIRECTIONAL_DISTANCE cells) and joins to Steiner trees from both ends, which can
+    // lead to different paths of the same cost
+    #[serde(default)]
+    pub bidirectional_search: bool,
+    #[serde(default)]
+    pub search_limits: SearchLimits,
+    #[serde(default)]
+    pub cost_model: CostModel,
+    #[serde(default)]
+    pub heuristic: Heuristic,
+    // Weight of the heuristic (weighted A*), above 1 for faster searches that may find longer paths, 1 if unset
+    #[serde(default)]
+    pub heuristic_bias: Option<f64>,
+}
+
+impl RouteInput {
+    // Explicit ports and edge ports, the latter on the board edge with their opening as diameter
+    pub(crate) fn port_definitions(&self) -> Vec<PortDefinition> {
+        Vec::from_iter(self.ports.iter().cloned().chain(
+            self.edge_ports
+                .iter()
+                .map(|edge_port| edge_port.definition(self.board_width, self.board_height)),
+        ))
+    }
+
+    // A port of a connection that is missing from the explicit ports
+    pub(crate) fn undefined_port(&self, port_definitions: &[PortDefinition]) -> Option<(ConnectionID, Port)> {
+        if self.ports.is_empty() {
+            return None;
+        }
+        self.connections.iter().find_map(|input_connection| {
+            let mut ports = input_connection
+                .ports
+                .iter()
+                .chain(input_connection.branch_port.iter())
+                .chain(input_connection.port_candidates.iter().flatten());
+            let port = ports.find(|port| !port_definitions.iter().any(|d| d.port == **port))?;
+            Some((input_connection.id, *port))
+        })
+    }
+
+    // Channel dimensions of the connection, with its width and spacing overrides
+    pub(crate) fn footprint(&self, c_id: ConnectionID) -> Footprint {
+        let default_footprint = Footprint {
+            half_width: self.channel_width / 2.,
+            spacing: self.channel_spacing,
+        };
+        self.connections
+            .iter()
+            .find(|c| c.id == c_id)
+            .map_or(default_footprint, |c| Footprint {
+                half_width: c.channel_width.map_or(default_footprint.half_width, |w| w / 2.),
+                spacing: c.channel_spacing.unwrap_or(default_footprint.spacing),
+            })
+    }
+
+    // The largest channel half width and spacing of all connections
+    pub(crate) fn widest_footprint(&self) -> Footprint {
+        self.connections
+            .iter()
+            .map(|c| self.footprint(c.id))
+            .fold(self.footprint(ConnectionID::MAX), |a, b| Footprint {
+                half_width: a.half_width.max(b.half_width),
+                spacing: a.spacing.max(b.spacing),
+            })
+    }
+}
+
+// Router that computes the channels
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum RoutingBackend {
+    #[default]
+    Grid, // A* on a grid of cells
+    // A* on a visibility graph of the obstacles (ports, keep-outs and channels) with channels along the layout
+    // directions; fails with UnsupportedSetting for more layers, rip-up and reroute, orderings, length matching, target
+    // resistances, serpentines, smoothing, the cost model, a cell size, grid snapshots and the bidirectional search, and
+    // routes fewer connections of dense boards
+    Gridless,
+}
+
+// Port at an explicit board position, referred to by its port index in the connections
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PortDefinition {
+    pub port: Port,
+    pub name: String,
+    pub position: Point,
+    pub diameter: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum BoardSide {
+    Top,
+    Right,
+    Bottom,
+    Left,
+}
+
+// Opening in the board edge to a neighboring block, referred to by its port index in the connections (which must not be
+// used by another port)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EdgePort {
+    pub port: Port,
+    pub side: BoardSide,
+    // Center of the opening along the side, from the left (top and bottom side) or the top (left and right side)
+    pub position: f64,
+    pub width: f64,    // width of the opening
+}
+
+impl EdgePort {
+    // Center of the opening on the board edge
+    pub fn center(&self, board_width: f64, board_height: f64) -> Point {
+        match self.side {
+            BoardSide::Top => [self.position, 0.],
+            BoardSide::Right => [board_width, self.position],
+            BoardSide::Bottom => [self.position, board_height],
+            BoardSide::Left => [0., self.position],
+        }
+    }
+
+    fn definition(&self, board_width: f64, board_height: f64) -> PortDefinition {
+        PortDefinition {
+            port: self.port,
+            name: String::new(),
+            position: self.center(board_width, board_height),
+            diameter: self.width,
+        }
+    }
+}
+
+// Post-routing pass that simplifies the channels and rounds their corners, keeping the new geometry within the
+// clearance reserved on the grid (channels with vias are left as routed)
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct Smoothing {
+    // Radius of the fillet arcs replacing the corners, corners without room for an arc stay sharp
+    pub min_bend_radius: f64,
+    #[serde(default)]
+    pub any_angle: bool, // replaces staircases with straight lines of any angle
+}
+
+// Limits that stop path searches early (e.g., to keep interactive routing responsive), failing with SearchLimitReached;
+// searches are unlimited if unset
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct SearchLimits {
+    #[serde(default)]
+    pub max_expansions: Option<usize>, // nodes expanded by a single path search
+    // Margin (in board units) around the bounding box of the start and the targets a path search stays within
+    #[serde(default)]
+    pub region_margin: Option<f64>,
+    #[serde(default)]
+    pub max_total_expansions: Option<usize>, // nodes expanded by all path searches for channels of a routing
+    #[serde(default)]
+    pub max_time_ms: Option<f64>, // wall-clock time of a routing, after which no path search continues
+}
+
+impl SearchLimits {
+    // Expansion limit of the next path search, with the given expansions left of the total budget
+    pub(crate) fn expansion_limit(&self, expansions_left: Option<usize>) -> Option<usize> {
+        match (self.max_expansions, expansions_left) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+// Penalties (in cells) the path search adds to the length of a path, to prefer channels with fewer bends away from
+// other channels
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct CostModel {
+    #[serde(default)]
+    pub bend_45: f64, // per 45° turn
+    #[serde(default)]
+    pub bend_90: f64, // per 90° turn
+    #[serde(default)]
+    pub direction_change: f64, // per turn of any angle, on top of the penalty of its angle
+    #[serde(default)]
+    pub adjacency: f64, // per step into a cell next to the channel of another connection (right outside its clearance)
+}
+
+// Connections whose channels are lengthened with serpentine detours until they match the longest connection of the
+// group
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LengthMatchGroup {
+    pub connections: Vec<ConnectionID>,
+    pub tolerance: f64, // maximum length difference to the longest connection, in board units
+    #[serde(default)]
+    pub style: Option<Layout>, // style of the detours, the layout of the board if unset
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum KeepOut {
+    Rectangle { position: Point, dimensions: Point }, // position of the top left corner
+    Circle { center: Point, radius: f64 },
+    Polygon(Vec<Point>),
+}
+
+impl KeepOut {
+    // Distance of the point to the keep-out zone, 0 if the point lies inside
+    pub(crate) fn distance(&self, [px, py]: Point) -> f64 {
+        match self {
+            KeepOut::Rectangle {
+                position: [x, y],
+                dimensions: [width, height],
+            } => {
+                let dx = (x - px).max(px - (x + width)).max(0.);
+                let dy = (y - py).max(py - (y + height)).max(0.);
+                f64::hypot(dx, dy)
+            }
+            KeepOut::Circle {
+                center: [cx, cy],
+                radius,
+            } => (f64::hypot(px - cx, py - cy) - radius).max(0.),
+            KeepOut::Polygon(points) => {
+                let n = points.len();
+                let mut inside = false;
+                let mut distance = f64::INFINITY;
+                for i in 0..n {
+                    let [ax, ay] = points[i];
+                    let [bx, by] = points[(i + 1) % n];
+                    // Even-odd rule
+                    if (ay > py) != (by > py) && px < ax + (py - ay) / (by - ay) * (bx - ax) {
+                        inside = !inside;
+                    }
+                    distance = distance.min(segment_distance([px, py], [ax, ay], [bx, by]));
+                }
+                if inside {
+                    0.
+                } else {
+                    distance
+                }
+            }
+        }
+    }
+
+    // Top left and bottom right corner of the keep-out zone
+    pub(crate) fn bounding_box(&self) -> (Point, Point) {
+        match self {
+            KeepOut::Rectangle {
+                position: [x, y],
+                dimensions: [width, height],
+            } => ([*x, *y], [x + width, y + height]),
+            KeepOut::Circle {
+                center: [cx, cy],
+                radius,
+            } => ([cx - radius, cy - radius], [cx + radius, cy + radius]),
+            KeepOut::Polygon(points) => points.iter().fold(
+                ([f64::INFINITY; 2], [f64::NEG_INFINITY; 2]),
+                |([min_x, min_y], [max_x, max_y]), [x, y]| {
+                    ([min_x.min(*x), min_y.min(*y)], [max_x.max(*x), max_y.max(*y)])
+                },
+            ),
+        }
+    }
+}
+
+// Negotiated-congestion routing (PathFinder style): while connections fail, all connections are routed again sharing
+// cells at a present cost that grows from iteration to iteration, the shared cells get a higher history cost, and all
+// connections are ripped up and rerouted around them until everything is routed or max_iterations is reached
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct RipUpReroute {
+    pub max_iterations: usize,
+    pub history_increment: f64,
+}
+
+// Distance of the point p to the line segment between a and b
+pub(crate) fn segment_distance([px, py]: Point, [ax, ay]: Point, [bx, by]: Point) -> f64 {
+    let (dx, dy) = (bx - ax, by - ay);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared > 0. {
+        (((px - ax) * dx + (py - ay) * dy) / length_squared).clamp(0., 1.)
+    } else {
+        0.
+    };
+    f64::hypot(px - (ax + t * dx), py - (ay + t * dy))
 }
 
 pub type ConnectionID = usize;
@@ -29,7 +355,31 @@ pub type RouteInputConnections = Vec<RouteInputConnection>;
 pub struct RouteInputConnection {
     pub id: ConnectionID, 
     pub ports: Vec<Port>,
-    pub branch_port: Option<Port>
+    pub branch_port: Option<Port>,
+    #[serde(default)]
+    pub priority: Option<i64>, // used by ConnectionOrdering::Priority, higher priorities are routed first
+    #[serde(default)]
+    pub channel_width: Option<f64>, // overrides RouteInput.channel_width for this connection
+    #[serde(default)]
+    pub channel_spacing: Option<f64>, // overrides RouteInput.channel_spacing for this connection
+    // Hydraulic resistance the channel is lengthened towards with meanders, only for 2-port connections
+    #[serde(default)]
+    pub target_resistance: Option<f64>,
+    #[serde(default)]
+    pub serpentine: Option<Serpentine>,
+    // Further candidates of each port (aligned with ports), the router assigns the given port or one of them
+    #[serde(default)]
+    pub port_candidates: Vec<Vec<Port>>,
+}
+
+// Passive mixing serpentine inserted into the channel of a connection (for multi-port connections, into the branch to
+// the first port) in the style of the layout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Serpentine {
+    pub amplitude: f64, // distance of the turns to the center line of the channel, in board units
+    pub turns: usize,
+    #[serde(default)]
+    pub segment: Option<usize>, // index of the straight segment of the channel, the longest segment if unset
 }
 pub type Port = (usize, usize);
 
@@ -39,18 +389,281 @@ pub enum Layout {
     Octilinear,
 }
 
+// Estimate of the remaining path length that guides the path searches
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub enum Heuristic {
+    #[default]
+    Euclidean, // straight-line distance, which underestimates paths along the layout directions
+    Layout, // Manhattan distance for rectilinear and octile distance for octilinear layouts
+    Manhattan, // overestimates diagonal paths, so octilinear paths may be longer than necessary
+    Octile, // distance along the octilinear directions, which underestimates rectilinear paths
+}
+
+impl Heuristic {
+    // Estimated path length for the offsets in x and y
+    pub(crate) fn distance(&self, layout: Layout, dx: f64, dy: f64) -> f64 {
+        match (self, layout) {
+            (Heuristic::Euclidean, _) => f64::hypot(dx, dy),
+            (Heuristic::Manhattan, _) | (Heuristic::Layout, Layout::Rectilinear) => dx + dy,
+            (Heuristic::Octile, _) | (Heuristic::Layout, Layout::Octilinear) => {
+                dx.max(dy) + (f64::consts::SQRT_2 - 1.) * dx.min(dy)
+            }
+        }
+    }
+}
+
+// How connections with more than two ports (or a branch_port) are routed
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub enum MultiPortRouting {
+    // Every port is connected to a single join node (the branch_port or the first free cell around the centroid; if
+    // branches fail, the cells with the shortest estimated branches and least congestion are tried)
+    #[default]
+    Star,
+    // Like Star, but the join node is the free cell with the shortest estimated branches and least congestion (if
+    // branches fail, the centroid cell and further cells are tried)
+    OptimizedStar,
+    // The tree grows from the branch_port or the port closest to the centroid, and every further port joins any cell
+    // already on the tree
+    SteinerTree,
+}
+
+// Order in which the connections are routed
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub enum ConnectionOrdering {
+    #[default]
+    Default, // multi-port connections first, then by ascending distance
+    ShortestFirst,
+    LongestFirst,
+    MostConstrainedFirst, // connections whose bounding box overlaps the most other connections first
+    Priority,
+    Random { seed: u64 },
+    // The default order and `restarts` random orders, keeping the best result
+    RandomRestarts { seed: u64, restarts: usize },
+    BestOf(Vec<ConnectionOrdering>), // tries all given orderings and keeps the best result
+}
+
 pub type BoardRouterOutput = Result<BoardRouterOutputBoard, BoardRouterOutputError>;
 
+// Partial results carry the whole output board
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Serialize, Deserialize)]
 pub enum BoardRouterOutputError {
     NoInputConnections,
     PartialResult(BoardRouterOutputBoard),
     NoConnectionsFound,
+    UndefinedPort(ConnectionID, Port), // a port of the connection is missing from the explicit ports
+    InvalidTargetResistance(ConnectionID), // a target resistance is only supported for 2-port connections
+    UnsupportedSetting(RoutingSetting), // a setting of the input the backend does not support
+}
+
+// Settings of the input that not every backend supports
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum RoutingSetting {
+    Layers, // more than one layer
+    RipUpReroute,
+    Ordering, // other than the default ordering
+    LengthMatching,
+    TargetResistance,
+    Serpentine,
+    Smoothing,
+    CostModel, // non-zero penalties
+    CellSize,
+    GridSnapshots,
+    BidirectionalSearch,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BoardRouterOutputBoard {
     pub connections: Vec<BoardRouterOutputConnection>, // this is the output -- a vector of the channel connections on the routing board
+    // Layers and vias of each channel of the connections, only reported for multi-layer routing
+    #[serde(default)]
+    pub layers: Vec<(ConnectionID, Vec<ChannelLayers>)>,
+    // Channel width of the connections that override the global channel width
+    #[serde(default)]
+    pub channel_widths: Vec<(ConnectionID, f64)>,
+    #[serde(default)]
+    pub lengths: Vec<(ConnectionID, f64)>, // total channel length of the connections in length-match groups
+    // Hydraulic resistance of each channel of the connections, if the channel height and viscosity are given
+    #[serde(default)]
+    pub resistances: Vec<(ConnectionID, Vec<f64>)>,
+    // Fillet radius at each point of the channels of the connections (0 at the ends and at sharp corners), only
+    // reported with smoothing
+    #[serde(default)]
+    pub bend_radii: Vec<(ConnectionID, Vec<Vec<f64>>)>,
+    #[serde(default)]
+    pub assigned_ports: Vec<(ConnectionID, Vec<Port>)>, // ports of the connections with port candidates
+    #[serde(default)]
+    pub failures: Vec<RoutingFailure>, // connections and branches that could not be routed
+    // The grid with the port reservations, keep-outs and join nodes, only reported with grid snapshots
+    #[serde(default)]
+    pub setup_grid: Option<GridSnapshot>,
+    #[serde(default)]
+    pub routed_grid: Option<GridSnapshot>, // the grid with the routed channels, only reported with grid snapshots
+    #[serde(default)]
+    pub statistics: RoutingStatistics,
+}
+
+// Metrics of the routing and its channels, the channel lengths in board units
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RoutingStatistics {
+    // The routed (and locked) connections in the order of the output connections
+    pub connections: Vec<ConnectionStatistics>,
+    pub length: f64,
+    pub bends: BendCounts,
+    pub area: f64,
+    pub volume: Option<f64>,
+    // Nodes expanded by all path searches for channels, including those of failed connections (but not the searches
+    // that diagnose the failures)
+    pub expansions: usize,
+    pub time_ms: f64, // wall-clock time of the routing
+    #[serde(default)]
+    pub min_length: f64, // lower bound of the length, see ConnectionStatistics
+    // Heuristic of the path searches, with the bias below, to compare the lengths above their min_length across
+    // settings
+    #[serde(default)]
+    pub heuristic: Heuristic,
+    #[serde(default)]
+    pub heuristic_bias: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ConnectionStatistics {
+    pub connection: ConnectionID,
+    // Position of the connection in the routing order (independently routed clusters follow each other), None if locked
+    pub order: Option<usize>,
+    pub length: f64, // of the center lines, including fillet arcs
+    // Distance between the farthest channel ends (along the layout directions unless any-angle), which no routing of
+    // the connection is shorter than but for fillet arcs
+    #[serde(default)]
+    pub min_length: f64,
+    pub bends: BendCounts,
+    pub area: f64, // channel length times channel width
+    pub volume: Option<f64>, // channel area times channel height, if the channel height is given
+    pub expansions: usize, // nodes expanded by the path searches for the connection (in all routing attempts)
+    pub time_ms: f64, // time spent in these path searches
+}
+
+// Corners of the channels by the angle the direction changes by
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct BendCounts {
+    pub bends_45: usize,
+    pub bends_90: usize,
+    pub other: usize, // e.g., of any-angle channels or the gridless backend
+}
+
+impl BendCounts {
+    fn add(&mut self, other: BendCounts) {
+        self.bends_45 += other.bends_45;
+        self.bends_90 += other.bends_90;
+        self.other += other.other;
+    }
+}
+
+// Bends at the corner points of the channel (fillet arcs are not part of it)
+fn channel_bends(channel: &Channel) -> BendCounts {
+    let mut bends = BendCounts::default();
+    for w in channel.windows(3) {
+        let (a, b) = ([w[1][0] - w[0][0], w[1][1] - w[0][1]], [w[2][0] - w[1][0], w[2][1] - w[1][1]]);
+        let lengths = f64::hypot(a[0], a[1]) * f64::hypot(b[0], b[1]);
+        if lengths == 0. {
+            continue;
+        }
+        let angle = ((a[0] * b[0] + a[1] * b[1]) / lengths).clamp(-1., 1.).acos().to_degrees();
+        if angle < BEND_ANGLE_TOLERANCE {
+            continue;
+        } else if (angle - 45.).abs() < BEND_ANGLE_TOLERANCE {
+            bends.bends_45 += 1;
+        } else if (angle - 90.).abs() < BEND_ANGLE_TOLERANCE {
+            bends.bends_90 += 1;
+        } else {
+            bends.other += 1;
+        }
+    }
+    bends
+}
+
+// Deviation (in degrees) of a bend from the angle it is counted as
+const BEND_ANGLE_TOLERANCE: f64 = 1e-3;
+
+// Number of expanded nodes and time of the path searches for a connection
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SearchEffort {
+    pub expansions: usize,
+    pub time_ms: f64,
+}
+
+// The routing grid: the cell (x, y) of a layer is centered at (cell_offset_x + x * cell_size, cell_offset_y + y *
+// cell_size)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GridSnapshot {
+    pub cell_size: f64,
+    pub cell_offset_x: f64,
+    pub cell_offset_y: f64,
+    pub cells_x: usize,
+    pub cells_y: usize,
+    pub layers: usize,
+    pub cells: Vec<GridNode>, // ordered by layer, then x, then y
+}
+
+impl GridSnapshot {
+    pub fn cell(&self, layer: usize, x: usize, y: usize) -> &GridNode {
+        &self.cells[(layer * self.cells_x + x) * self.cells_y + y]
+    }
+
+    pub fn cell_position(&self, x: usize, y: usize) -> Point {
+        [
+            self.cell_offset_x + x as f64 * self.cell_size,
+            self.cell_offset_y + y as f64 * self.cell_size,
+        ]
+    }
+}
+
+// A connection, or a branch of a multi-port connection, that could not be routed
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RoutingFailure {
+    pub connection: ConnectionID,
+    pub port: Option<Port>, // the port of the failed branch of a multi-port connection
+    pub reason: FailureReason,
+    pub explored: Vec<Point>, // positions of the cells explored by the path search
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum FailureReason {
+    NoJoinNode, // no free cell was found for the join node of the star
+    StartBlocked(Option<ConnectionID>), // the start cell is blocked, or owned by the given connection
+    TargetBlocked(Option<ConnectionID>), // the target cell is blocked, or owned by the given connection
+    Enclosed(Vec<ConnectionID>), // the channels of the given connections enclose the start or the target
+    // Every reachable cell was searched without reaching the target (e.g., keep-outs, locked channels or foreign ports
+    // are in the way)
+    EmptySearchSpace,
+    NoSerpentineSpace, // a path was found, but the serpentine does not fit along it
+    // A path exists on the final board, but not when the branch was routed (e.g., before the branches its Steiner tree
+    // grew from)
+    BlockedWhenRouted,
+    SearchLimitReached, // the path search stopped at one of the search limits before reaching the target
+}
+
+impl BoardRouterOutputBoard {
+    // Channel width of the connection, falling back to the given global channel width
+    pub fn channel_width(&self, c_id: ConnectionID, channel_width: f64) -> f64 {
+        self.channel_widths
+            .iter()
+            .find(|(id, _)| *id == c_id)
+            .map_or(channel_width, |(_, width)| *width)
+    }
+
+    // Center lines of the channels of the connection, including the fillet arcs of smoothed channels
+    pub fn center_lines(&self, c_id: ConnectionID, channels: &[Channel]) -> Vec<Channel> {
+        match self.bend_radii.iter().find(|(id, _)| *id == c_id) {
+            Some((_, radii)) => Vec::from_iter(
+                channels
+                    .iter()
+                    .zip(radii)
+                    .map(|(channel, radii)| rounded_channel(channel, radii)),
+            ),
+            None => channels.to_vec(),
+        }
+    }
 }
 
 pub type BoardRouterOutputConnection = (ConnectionID, Vec<Channel>); // tuple of connection ID (unsigned integer) and channel(s), the channel consists of a vector of points
@@ -59,10 +672,81 @@ pub type Channel = Vec<Point>;
 
 pub type Point = [f64; 2]; // a point in 2D (x, y)
 
-#[derive(Debug)]
-struct GridNode {
-    connection: Option<ConnectionID>,
-    blocked: bool,
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ChannelLayers {
+    pub segments: Vec<usize>, // layer of each channel segment, i.e., of the line between the channel points i and i + 1
+    pub vias: Vec<Via>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Via {
+    pub position: Point,
+    pub from_layer: usize,
+    pub to_layer: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct GridNode {
+    // The connection the cell is reserved for (around its ports and at its join node)
+    pub connection: Option<ConnectionID>,
+    pub blocked: bool,
+    pub channel: Option<ConnectionID>, // the connection whose routed channel occupies this cell
+}
+
+// Cost of sharing a cell with a foreign channel in the first negotiation pass of rip-up and reroute
+const CONGESTION_COST: f64 = 4.;
+
+// Factor the cost of sharing a cell grows by from one rip-up and reroute iteration to the next
+const PRESENT_COST_GROWTH: f64 = 1.5;
+
+// Cost of each congested cell or crossing connection around a join cell, in cells of branch length
+const JOIN_CONGESTION_COST: f64 = 1.;
+
+// Cells around a join cell that count towards its congestion
+const JOIN_CONGESTION_RADIUS: usize = 2;
+
+// Join cells tried for a star whose branches fail, the best first
+const MAX_JOIN_CANDIDATES: usize = 4;
+
+// Number of targets from which the heuristic of a cell is only computed once per search
+const CACHED_HEURISTIC_TARGETS: usize = 16;
+
+// Distance (in cells) from which connections are searched from both ends (with bidirectional search)
+const BIDIRECTIONAL_DISTANCE: f64 = 32.;
+
+// Cost of a via between two adjacent layers, in cells
+const DEFAULT_VIA_COST: f64 = 10.;
+
+// Channel dimensions of a connection: the center lines of two channels are at least half of both widths plus the larger
+// spacing apart
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Footprint {
+    pub(crate) half_width: f64,
+    pub(crate) spacing: f64,
+}
+
+impl Footprint {
+    pub(crate) fn clearance(&self, other: &Footprint) -> f64 {
+        self.half_width + other.half_width + self.spacing.max(other.spacing)
+    }
+}
+
+// Footprints of all connections, only used if some connection overrides the channel width or spacing or the grid is
+// finer than the channel distance
+struct Footprints {
+    cell_size: f64,
+    default: Footprint,
+    overrides: HashMap<ConnectionID, Footprint>,
+    max_reach: f64, // largest clearance between any two connections
+    // Planar cells a connection with a larger footprint additionally has to avoid (foreign ports, keep-outs and locked
+    // channels)
+    obstacles: HashMap<ConnectionID, HashSet<usize>>,
+}
+
+impl Footprints {
+    fn footprint(&self, c_id: ConnectionID) -> Footprint {
+        self.overrides.get(&c_id).copied().unwrap_or(self.default)
+    }
 }
 
 #[inline]
@@ -212,6 +896,117 @@ fn compute_extra_node(
     None
 }
 
+// Bounding box of cells, from the minimum to the maximum cell
+type CellBox = ((usize, usize), (usize, usize));
+
+// Join cells of a star, the best first: cells are scored by the estimated length of all branches and the congestion
+// around them (blocked cells, cells reserved for other connections and bounding boxes of other connections)
+fn join_cell_candidates(
+    nodes: &[GridNode],
+    grid: &RoutingGrid,
+    connection: ConnectionID,
+    ports: &[Port],
+    other_connections: &[CellBox],
+    port_cell: impl Fn(&(usize, usize)) -> (usize, usize),
+) -> Vec<(usize, usize)> {
+    let port_cells = Vec::from_iter(ports.iter().map(&port_cell));
+    let min = port_cells.iter().fold((usize::MAX, usize::MAX), |m, c| (m.0.min(c.0), m.1.min(c.1)));
+    let max = port_cells.iter().fold((0, 0), |m, c| (m.0.max(c.0), m.1.max(c.1)));
+    let n = port_cells.len() as f64;
+    let centroid = (
+        port_cells.iter().map(|c| c.0 as f64).sum::<f64>() / n,
+        port_cells.iter().map(|c| c.1 as f64).sum::<f64>() / n,
+    );
+
+    // Like in compute_extra_node, a join cell and its neighbors must be free
+    let is_free = |(x, y): (usize, usize)| {
+        neighbors((x, y), grid.cells_x, grid.cells_y)
+            .iter()
+            .map(|n| n.0)
+            .chain([(x, y)])
+            .all(|(nx, ny)| {
+                let node = &nodes[grid.cell_id(0, nx, ny)];
+                node.connection.is_none() && !node.blocked
+            })
+    };
+    let branch_length = |(x, y): (usize, usize), (px, py): (usize, usize)| {
+        let (dx, dy) = (x.abs_diff(px) as f64, y.abs_diff(py) as f64);
+        match grid.layout {
+            Layout::Rectilinear => dx + dy,
+            Layout::Octilinear => dx.max(dy) + (f64::consts::SQRT_2 - 1.) * dx.min(dy),
+        }
+    };
+    let congestion = |(x, y): (usize, usize)| {
+        let mut congested = 0;
+        for nx in x.saturating_sub(JOIN_CONGESTION_RADIUS)..(x + JOIN_CONGESTION_RADIUS + 1).min(grid.cells_x) {
+            for ny in y.saturating_sub(JOIN_CONGESTION_RADIUS)..(y + JOIN_CONGESTION_RADIUS + 1).min(grid.cells_y) {
+                let node = &nodes[grid.cell_id(0, nx, ny)];
+                if node.blocked || node.connection.is_some_and(|c| c != connection) {
+                    congested += 1;
+                }
+            }
+        }
+        congested
+            + other_connections
+                .iter()
+                .filter(|(min, max)| min.0 <= x && x <= max.0 && min.1 <= y && y <= max.1)
+                .count()
+    };
+    let score = |cell: (usize, usize)| {
+        port_cells.iter().map(|&p| branch_length(cell, p)).sum::<f64>()
+            + JOIN_CONGESTION_COST * congestion(cell) as f64
+    };
+    let centroid_distance = |(x, y): (usize, usize)| f64::hypot(x as f64 - centroid.0, y as f64 - centroid.1);
+
+    // The shortest branches start inside the bounding box of the ports; cells with the same score are ordered by their
+    // distance to the centroid
+    let mut candidates = Vec::from_iter(
+        (min.0..=max.0)
+            .flat_map(|x| (min.1..=max.1).map(move |y| (x, y)))
+            .filter(|&cell| is_free(cell))
+            .map(|cell| (score(cell), centroid_distance(cell), cell)),
+    );
+    candidates.sort_by(|a, b| f64::total_cmp(&a.0, &b.0).then(f64::total_cmp(&a.1, &b.1)).then(a.2.cmp(&b.2)));
+
+    // Cells right next to a better candidate would most likely fail the same way, so the alternatives are spread out
+    let mut spread = Vec::<(usize, usize)>::new();
+    for (_, _, cell) in candidates {
+        if spread.len() == MAX_JOIN_CANDIDATES {
+            break;
+        }
+        if spread
+            .iter()
+            .all(|c| c.0.abs_diff(cell.0).max(c.1.abs_diff(cell.1)) > JOIN_CONGESTION_RADIUS)
+        {
+            spread.push(cell);
+        }
+    }
+
+    // Without a free cell between the ports, the star joins at the free cell closest to the centroid
+    if spread.is_empty() {
+        spread.extend(compute_extra_node(nodes, ports, grid.cells_x, grid.cells_y, &port_cell));
+    }
+    spread
+}
+
+// Cell of the port closest to the centroid of all ports
+fn centroid_port_cell(
+    ports: &[Port],
+    port_cell: impl Fn(&(usize, usize)) -> (usize, usize),
+) -> (usize, usize) {
+    let cells = Vec::from_iter(ports.iter().map(&port_cell));
+    let n = cells.len() as f64;
+    let center = (
+        cells.iter().map(|c| c.0 as f64).sum::<f64>() / n,
+        cells.iter().map(|c| c.1 as f64).sum::<f64>() / n,
+    );
+    let distance = |c: &(usize, usize)| f64::hypot(c.0 as f64 - center.0, c.1 as f64 - center.1);
+    *cells
+        .iter()
+        .min_by(|a, b| f64::total_cmp(&distance(a), &distance(b)))
+        .unwrap()
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ComputePortsInput {
     pub board_width: f64,
@@ -241,11 +1036,23 @@ pub fn compute_ports(
     return ComputePortsOutput { ports_x, ports_y };
 }
 
-pub fn route(input: &RouteInput) -> BoardRouterOutput {
-    let channel_distance = input.channel_width + input.channel_spacing;
-    let cells_per_pitch = (input.pitch / channel_distance).floor() as usize;
-    let cell_size = input.pitch / (cells_per_pitch as f64);
-    let half_cell_size = cell_size / 2.;
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ComputePortGridInput {
+    pub board_width: f64,
+    pub board_height: f64,
+    pub pitch: f64,
+    pub pitch_offset_x: f64,
+    pub pitch_offset_y: f64,
+    pub port_diameter: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ComputePortGridOutput {
+    pub ports: Vec<PortDefinition>,
+}
+
+// Explicit ports of the regular pitch grid, named like "A1"
+pub fn compute_port_grid(input: ComputePortGridInput) -> ComputePortGridOutput {
     let ComputePortsOutput { ports_x, ports_y } = compute_ports(ComputePortsInput {
         board_width: input.board_width,
         board_height: input.board_height,
@@ -253,82 +1060,2256 @@ pub fn route(input: &RouteInput) -> BoardRouterOutput {
         pitch_offset_x: input.pitch_offset_x,
         pitch_offset_y: input.pitch_offset_y,
     });
-    let main_grid_cells_x = ports_x * cells_per_pitch + (1 - cells_per_pitch % 2);
-    let main_grid_cells_y = ports_y * cells_per_pitch + (1 - cells_per_pitch % 2);
+    let ports = (0..ports_y)
+        .flat_map(|y| (0..ports_x).map(move |x| (x, y)))
+        .map(|port| PortDefinition {
+            port,
+            name: port_index_to_identifier(port),
+            position: [
+                input.pitch_offset_x + port.0 as f64 * input.pitch,
+                input.pitch_offset_y + port.1 as f64 * input.pitch,
+            ],
+            diameter: input.port_diameter,
+        })
+        .collect();
+    ComputePortGridOutput { ports }
+}
 
-    let half_spacing = input.channel_spacing / 2.;
-    let pre_remaining_x = input.pitch_offset_x
-        - ((cells_per_pitch / 2) as f64) * cell_size
-        - half_cell_size
-        - half_spacing;
-    let pre_offset_cells_x = ((pre_remaining_x / cell_size).max(0.)).floor() as usize;
+enum RoutingConnection {
+    PortToPort(PortToPort),
+    StarBranch(StarBranch),
+    SteinerBranch(SteinerBranch),
+}
 
-    let pre_remaining_y = input.pitch_offset_y
-        - ((cells_per_pitch / 2) as f64) * cell_size
-        - half_cell_size
-        - half_spacing;
-    let pre_offset_cells_y = ((pre_remaining_y / cell_size).max(0.)).floor() as usize;
+struct PortToPort {
+    connection: ConnectionID,
+    from_cell: (usize, usize),
+    to_cell: (usize, usize),
+}
 
-    let post_remaining_x = input.pitch_offset_x
-        - ((cells_per_pitch / 2) as f64) * cell_size
-        - half_cell_size
-        - half_spacing;
-    let post_offset_cells_x = ((post_remaining_x / cell_size).max(0.)).floor() as usize;
+// 2-port connections with a branch_port are also regarded as two StarBranch'es
+struct StarBranch {
+    connection: ConnectionID,
+    from_cell: Option<(usize, usize)>,
+    to_cell: (usize, usize),
+    num_branches: usize,
+}
 
-    let post_remaining_y = input.pitch_offset_y
-        - ((cells_per_pitch / 2) as f64) * cell_size
-        - half_cell_size
-        - half_spacing;
-    let post_offset_cells_y = ((post_remaining_y / cell_size).max(0.)).floor() as usize;
+// Connects a port to the Steiner tree of the connection, which initially consists of the root cell only
+struct SteinerBranch {
+    connection: ConnectionID,
+    from_cell: (usize, usize),
+    root_cell: (usize, usize),
+    num_branches: usize,
+}
 
-    let cells_x = main_grid_cells_x + pre_offset_cells_x + post_offset_cells_x;
-    let cells_y = main_grid_cells_y + pre_offset_cells_y + post_offset_cells_y;
+impl RoutingConnection {
+    fn connection(&self) -> ConnectionID {
+        match self {
+            RoutingConnection::PortToPort(port_to_port) => port_to_port.connection,
+            RoutingConnection::StarBranch(star_branch) => star_branch.connection,
+            RoutingConnection::SteinerBranch(steiner_branch) => steiner_branch.connection,
+        }
+    }
 
-    let cell_offset_x = input.pitch_offset_x
-        - ((cells_per_pitch / 2) as f64) * cell_size
-        - pre_offset_cells_x as f64 * cell_size;
-    let cell_offset_y = input.pitch_offset_y
-        - ((cells_per_pitch / 2) as f64) * cell_size
-        - pre_offset_cells_y as f64 * cell_size;
+    fn num_branches(&self) -> usize {
+        match self {
+            RoutingConnection::PortToPort(_) => 1,
+            RoutingConnection::StarBranch(star_branch) => star_branch.num_branches,
+            RoutingConnection::SteinerBranch(steiner_branch) => steiner_branch.num_branches,
+        }
+    }
 
-    let port_cell = |port: &Port| {
-        let cell_x = (cells_per_pitch / 2) + cells_per_pitch * port.0 + pre_offset_cells_x;
-        let cell_y = (cells_per_pitch / 2) + cells_per_pitch * port.1 + pre_offset_cells_y;
-        (cell_x, cell_y)
-    };
+    // Start and target cell of the connection, if the connection can be routed at all
+    fn cells(&self) -> Option<((usize, usize), (usize, usize))> {
+        match self {
+            RoutingConnection::PortToPort(port_to_port) => {
+                Some((port_to_port.from_cell, port_to_port.to_cell))
+            }
+            RoutingConnection::StarBranch(star_branch) => star_branch
+                .from_cell
+                .map(|from_cell| (from_cell, star_branch.to_cell)),
+            RoutingConnection::SteinerBranch(steiner_branch) => {
+                Some((steiner_branch.from_cell, steiner_branch.root_cell))
+            }
+        }
+    }
+}
 
-    let port_radius = input.port_diameter / 2.;
-    let port_influence_radius = port_radius + input.channel_spacing + input.channel_width / 2.;
-    let box_size = (port_influence_radius / cell_size).ceil();
+// Sort connections by multi-connections and direct distance, ascending
+fn cmp_connections(a: &RoutingConnection, b: &RoutingConnection) -> Ordering {
+    match (a, b) {
+        (
+            RoutingConnection::PortToPort(a_port_to_port),
+            RoutingConnection::PortToPort(b_port_to_port),
+        ) => {
+            let adx = usize::abs_diff(a_port_to_port.from_cell.0, a_port_to_port.to_cell.0);
+            let ady = usize::abs_diff(a_port_to_port.from_cell.1, a_port_to_port.to_cell.1);
+            let bdx = usize::abs_diff(b_port_to_port.from_cell.0, b_port_to_port.to_cell.0);
+            let bdy = usize::abs_diff(b_port_to_port.from_cell.1, b_port_to_port.to_cell.1);
 
-    let mut nodes = Vec::<GridNode>::with_capacity(cells_x * cells_y);
+            let al = f64::hypot(adx as f64, ady as f64);
+            let bl = f64::hypot(bdx as f64, bdy as f64);
+            return f64::partial_cmp(&al, &bl).unwrap();
+        }
+        (RoutingConnection::PortToPort(_), RoutingConnection::StarBranch(_)) => {
+            return Ordering::Greater
+        }
+        (RoutingConnection::StarBranch(_), RoutingConnection::PortToPort(_)) => {
+            return Ordering::Less
+        }
+        (
+            RoutingConnection::StarBranch(a_star_branch),
+            RoutingConnection::StarBranch(b_star_branch),
+        ) => {
+            let cmp_num_branches =
+                usize::cmp(&a_star_branch.num_branches, &b_star_branch.num_branches);
+            match cmp_num_branches {
+                Ordering::Equal => {
+                    if a_star_branch.from_cell.is_none() || b_star_branch.from_cell.is_none() {
+                        return Ordering::Equal; // Don't care
+                    }
+                    let adx = usize::abs_diff(
+                        a_star_branch.from_cell.unwrap().0,
+                        a_star_branch.to_cell.0,
+                    );
+                    let ady = usize::abs_diff(
+                        a_star_branch.from_cell.unwrap().1,
+                        a_star_branch.to_cell.1,
+                    );
+                    let bdx = usize::abs_diff(
+                        b_star_branch.from_cell.unwrap().0,
+                        b_star_branch.to_cell.0,
+                    );
+                    let bdy = usize::abs_diff(
+                        b_star_branch.from_cell.unwrap().1,
+                        b_star_branch.to_cell.1,
+                    );
 
-    // Generate routing nodes/cells
-    for _ in 0..cells_x {
-        for _ in 0..cells_y {
-            nodes.push(GridNode {
-                connection: None,
-                blocked: false,
-            });
+                    let al = f64::hypot(adx as f64, ady as f64);
+                    let bl = f64::hypot(bdx as f64, bdy as f64);
+                    return f64::partial_cmp(&al, &bl).unwrap();
+                }
+                o => return o,
+            }
+        }
+        (RoutingConnection::PortToPort(_), _) => Ordering::Greater,
+        (_, RoutingConnection::PortToPort(_)) => Ordering::Less,
+        // Steiner branches are sorted like star branches, so the ports closest to the root join the tree first
+        (a, b) => {
+            let distance = |c: &RoutingConnection| {
+                c.cells().map_or(0., |((ax, ay), (bx, by))| {
+                    f64::hypot(ax.abs_diff(bx) as f64, ay.abs_diff(by) as f64)
+                })
+            };
+            usize::cmp(&a.num_branches(), &b.num_branches())
+                .then_with(|| f64::total_cmp(&distance(a), &distance(b)))
         }
     }
+}
 
-    let input_connections = input
-        .connections
-        .to_vec()
-        .into_iter()
-        .collect::<Vec<RouteInputConnection>>();
+// Dimensions of the (layered) routing grid and the settings shared by all path searches
+struct RoutingGrid {
+    cells_x: usize,
+    cells_y: usize,
+    layers: usize,
+    layout: Layout,
+    via_cost: f64,
+    bidirectional_search: bool,
+    search_limits: SearchLimits,
+    region_margin: Option<usize>, // of the search limits, in cells
+    expansions_left: Cell<Option<usize>>, // of the total budget
+    deadline: Option<f64>, // of the path searches, see now_ms
+    cost_model: CostModel,
+    heuristic: Heuristic,
+    heuristic_bias: Option<f64>,
+    efforts: RefCell<HashMap<ConnectionID, SearchEffort>>, // of the path searches by connection
+    footprints: Option<Footprints>,
+    // Serpentines by the index of the routing connection they are inserted into
+    serpentines: HashMap<usize, SerpentineCells>,
+}
 
-    // Reserve cells at and around used ports for the corresponding connection only (prevent other connections from crossing foreign ports)
-    for input_connection in input_connections.iter() {
-        let RouteInputConnection { id: c_id, ports, .. } = input_connection;
-        for port in ports {
-            let (cell_x, cell_y) = port_cell(port);
+impl RoutingGrid {
+    fn cells_per_layer(&self) -> usize {
+        self.cells_x * self.cells_y
+    }
+
+    // Cells of the first layer keep the ids of a single-layer grid
+    fn cell_id(&self, layer: usize, x: usize, y: usize) -> usize {
+        (layer * self.cells_x + x) * self.cells_y + y
+    }
+
+    // (layer, x, y) of the cell
+    fn cell(&self, cell_id: usize) -> (usize, usize, usize) {
+        let planar = cell_id % self.cells_per_layer();
+        (
+            cell_id / self.cells_per_layer(),
+            planar / self.cells_y,
+            planar % self.cells_y,
+        )
+    }
+
+    // Estimated path length between cells the given number of cells apart in x and y
+    fn distance(&self, dx: usize, dy: usize) -> f64 {
+        self.heuristic.distance(self.layout, dx as f64, dy as f64)
+    }
+
+    // Penalty of the turn at the current cell between the steps from the previous and to the next cell (none across
+    // vias)
+    fn turn_cost(&self, previous: usize, current: usize, next: usize) -> f64 {
+        let CostModel { bend_45, bend_90, direction_change, .. } = self.cost_model;
+        if bend_45 == 0. && bend_90 == 0. && direction_change == 0. {
+            return 0.;
+        }
+        let (pl, px, py) = self.cell(previous);
+        let (cl, cx, cy) = self.cell(current);
+        let (nl, nx, ny) = self.cell(next);
+        if pl != cl || nl != cl {
+            return 0.;
+        }
+        let (ix, iy) = (cx as isize - px as isize, cy as isize - py as isize);
+        let (ox, oy) = (nx as isize - cx as isize, ny as isize - cy as isize);
+        let dot = ix * ox + iy * oy;
+        if ix * oy == iy * ox && dot > 0 {
+            return 0.;
+        }
+        direction_change + if dot > 0 { bend_45 } else { bend_90 }
+    }
+}
+
+// Everything the path search needs to know about the grid and the connection to be routed
+struct PathSearch<'a> {
+    nodes: &'a [GridNode],
+    grid: &'a RoutingGrid,
+    connection: ConnectionID,
+    history: Option<&'a [f64]>, // Accumulated congestion cost per cell (rip-up and reroute)
+    congestion_cost: Option<f64>, // If set, channels of other connections can be crossed at this cost
+    // If set, the path ends at any of these cells (e.g., the Steiner tree of the connection) instead of the target cell
+    targets: Option<&'a HashSet<usize>>,
+}
+
+impl PathSearch<'_> {
+    // Additional cost of entering the cell, or None if the cell cannot be entered
+    fn cell_cost(&self, cell_id: usize) -> Option<f64> {
+        let cost = self.passing_cost(cell_id)?;
+        // The relaxed search for congested cells ignores the footprints
+        if self.congestion_cost.is_none() && !self.keeps_clearance(cell_id) {
+            return None;
+        }
+        Some(cost)
+    }
+
+    // Cost of the cell without regard to the footprint of the connection, which also applies to the cells beside a
+    // diagonal step
+    fn passing_cost(&self, cell_id: usize) -> Option<f64> {
+        let node = &self.nodes[cell_id];
+        if let Some(c) = node.connection {
+            if c != self.connection {
+                return None;
+            }
+        }
+        let history = self.history.map_or(0., |history| history[cell_id]);
+        if self.targets.is_some_and(|targets| targets.contains(&cell_id)) {
+            return Some(history);
+        }
+        if node.blocked {
+            return match (self.congestion_cost, node.channel) {
+                (Some(congestion_cost), Some(c)) if c != self.connection => {
+                    Some(congestion_cost + history)
+                }
+                _ => None,
+            };
+        }
+        Some(history)
+    }
+
+    // Penalty of a step into the cell if a foreign channel runs next to it
+    fn adjacency_cost(&self, cell_id: usize) -> f64 {
+        let grid = self.grid;
+        let penalty = grid.cost_model.adjacency;
+        if penalty == 0. {
+            return 0.;
+        }
+        // On a finer grid, the closest foreign channels are as far as the clearance (rounded up to cells)
+        let reach = grid.footprints.as_ref().map_or(1, |footprints| {
+            ((footprints.max_reach / footprints.cell_size).ceil() as usize).max(1)
+        });
+        let (layer, x, y) = grid.cell(cell_id);
+        for nx in x.saturating_sub(reach)..(x + reach + 1).min(grid.cells_x) {
+            for ny in y.saturating_sub(reach)..(y + reach + 1).min(grid.cells_y) {
+                if self.nodes[grid.cell_id(layer, nx, ny)]
+                    .channel
+                    .is_some_and(|c| c != self.connection)
+                {
+                    return penalty;
+                }
+            }
+        }
+        0.
+    }
+
+    // Whether a channel through the cell keeps the clearance of its footprint to foreign channels and obstacles
+    fn keeps_clearance(&self, cell_id: usize) -> bool {
+        let grid = self.grid;
+        let Some(footprints) = &grid.footprints else {
+            return true;
+        };
+        let (layer, x, y) = grid.cell(cell_id);
+        if footprints
+            .obstacles
+            .get(&self.connection)
+            .is_some_and(|obstacles| obstacles.contains(&grid.cell_id(0, x, y)))
+        {
+            return false;
+        }
+        self.clears_foreign_channels(layer, (x, y), (x, y))
+    }
+
+    // Whether a step between the cells keeps the clearance of the footprint to foreign channels. On a finer grid, a
+    // diagonal step can pass a foreign channel closer than the cells at its ends, while a straight step cannot.
+    fn keeps_step_clearance(&self, from: usize, to: usize) -> bool {
+        let (layer, ax, ay) = self.grid.cell(from);
+        let (_, bx, by) = self.grid.cell(to);
+        if self.congestion_cost.is_some() || ax == bx || ay == by {
+            return true;
+        }
+        self.clears_foreign_channels(layer, (ax, ay), (bx, by))
+    }
+
+    // Whether the segment between the cells (or the cell, if both are the same) keeps the clearance of the footprint
+    // to the segments between adjacent cells of foreign channels
+    fn clears_foreign_channels(&self, layer: usize, (ax, ay): (usize, usize), (bx, by): (usize, usize)) -> bool {
+        let grid = self.grid;
+        let Some(footprints) = &grid.footprints else {
+            return true;
+        };
+        let point = |x: usize, y: usize| [x as f64 * footprints.cell_size, y as f64 * footprints.cell_size];
+        let footprint = footprints.footprint(self.connection);
+        // Foreign segments within the clearance have a cell at most one more cell away
+        let reach = (footprints.max_reach / footprints.cell_size).ceil() as usize + 1;
+        for x in ax.min(bx).saturating_sub(reach)..(ax.max(bx) + reach + 1).min(grid.cells_x) {
+            for y in ay.min(by).saturating_sub(reach)..(ay.max(by) + reach + 1).min(grid.cells_y) {
+                let Some(c) = self.nodes[grid.cell_id(layer, x, y)].channel else {
+                    continue;
+                };
+                if c == self.connection {
+                    continue;
+                }
+                // Tolerate rounding errors if the clearance is exactly the cell size
+                let clearance = footprint.clearance(&footprints.footprint(c)) * (1. - 1e-9);
+                // The cell itself and the segments to its neighbors of the same channel, each pair of cells once
+                let neighbors = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1), (x.wrapping_sub(1), y + 1)];
+                for (nx, ny) in neighbors {
+                    if nx >= grid.cells_x || ny >= grid.cells_y {
+                        continue;
+                    }
+                    if (nx, ny) != (x, y) && self.nodes[grid.cell_id(layer, nx, ny)].channel != Some(c) {
+                        continue;
+                    }
+                    if segments_distance(point(ax, ay), point(bx, by), point(x, y), point(nx, ny)) < clearance {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    fn can_start_at(&self, cell_id: usize) -> bool {
+        let node = &self.nodes[cell_id];
+        !node.blocked || (node.connection.is_some() && node.connection.unwrap() == self.connection)
+    }
+
+    // Cells of other connections' channels the given path crosses (including diagonal passages between them)
+    fn crossed_channel_cells(&self, path: &[usize]) -> Vec<usize> {
+        let grid = self.grid;
+        let foreign_channel = |cell_id: usize| {
+            let node = &self.nodes[cell_id];
+            node.channel.is_some() && node.channel.unwrap() != self.connection
+        };
+        let mut cells = Vec::new();
+        for (i, cell_id) in path.iter().copied().enumerate() {
+            if foreign_channel(cell_id) {
+                cells.push(cell_id);
+            }
+            if i > 0 {
+                let (pl, pix, piy) = grid.cell(path[i - 1]);
+                let (nl, nix, niy) = grid.cell(cell_id);
+                if pl == nl && pix != nix && piy != niy {
+                    for side in [grid.cell_id(nl, pix, niy), grid.cell_id(nl, nix, piy)] {
+                        if foreign_channel(side) {
+                            cells.push(side);
+                        }
+                    }
+                }
+            }
+        }
+        cells
+    }
+}
+
+fn find_path(search: &PathSearch, from_cell: (usize, usize), to_cell: (usize, usize)) -> Option<Vec<usize>> {
+    explore_path(search, from_cell, to_cell, false).0
+}
+
+// Moves from a search node (the cell and the cell before it) to neighboring cells (x, y) on its layer, with their length
+type Moves<'a> = dyn Fn(&(usize, Option<usize>)) -> Vec<Option<((usize, usize), f64)>> + 'a;
+
+// Path search that (if explore is set) also returns the cells explored by A*, and whether it stopped at a search limit
+// without a path
+fn explore_path(
+    search: &PathSearch,
+    (ax, ay): (usize, usize),
+    (bx, by): (usize, usize),
+    explore: bool,
+) -> (Option<Vec<usize>>, Vec<usize>, bool) {
+    let grid = search.grid;
+    let cells_x = grid.cells_x;
+    let cells_y = grid.cells_y;
+    let (target_node_ix, target_node_iy) = (bx, by);
+
+    // The search region is the bounding box of the start and the targets, grown by the margin
+    let region = grid.region_margin.map(|margin| {
+        let target_cells = match search.targets {
+            Some(targets) => Vec::from_iter(targets.iter().map(|&cell_id| {
+                let (_, x, y) = grid.cell(cell_id);
+                (x, y)
+            })),
+            None => Vec::from([(bx, by)]),
+        };
+        let (min_x, max_x, min_y, max_y) = target_cells
+            .into_iter()
+            .fold((ax, ax, ay, ay), |(min_x, max_x, min_y, max_y), (x, y)| {
+                (min_x.min(x), max_x.max(x), min_y.min(y), max_y.max(y))
+            });
+        (min_x.saturating_sub(margin)..=max_x + margin, min_y.saturating_sub(margin)..=max_y + margin)
+    });
+    // Moves out of the region are dropped, which makes a failed search one that stopped at a limit
+    let clipped = Cell::new(false);
+    let in_region = |x: usize, y: usize| -> bool {
+        let Some((xs, ys)) = &region else {
+            return true;
+        };
+        let inside = xs.contains(&x) && ys.contains(&y);
+        if !inside {
+            clipped.set(true);
+        }
+        inside
+    };
+
+    // Vias to the adjacent layers; a via never leads back to the cell the path came from
+    let vias = |a: &AStarNode<(usize, Option<usize>)>| -> Vec<((usize, Option<usize>), f64)> {
+        let current = a.node.0;
+        let (layer, nix, niy) = grid.cell(current);
+        [layer.checked_sub(1), Some(layer + 1).filter(|&l| l < grid.layers)]
+            .into_iter()
+            .flatten()
+            .map(|l| grid.cell_id(l, nix, niy))
+            .filter(|&cell_id| a.node.1 != Some(cell_id))
+            .filter_map(|cell_id| {
+                let cell_cost = search.cell_cost(cell_id)? + search.adjacency_cost(cell_id);
+                Some(((cell_id, Some(current)), grid.via_cost + cell_cost))
+            })
+            .collect()
+    };
+
+    // Setup move functions for rectilinear, octilinear, etc; Since they capture some variables (e.g., cells_x/y), they
+    // need to be defined here.
+    let rectilinear = |node: &(usize, Option<usize>)| -> Vec<Option<((usize, usize), f64)>> {
+        let (layer, nix, niy) = grid.cell(node.0);
+        // The direction of travel only matters within a layer, there is none after a via
+        let previous = node.1.map(|previous| grid.cell(previous)).filter(|p| p.0 == layer);
+        match previous {
+            Some((_, pix, piy)) => {
+                if pix < nix {
+                    Vec::from([
+                        up_if_exists((nix, niy)),
+                        down_if_exists((nix, niy), cells_y),
+                        right_if_exists((nix, niy), cells_x),
+                    ])
+                } else if pix > nix {
+                    Vec::from([
+                        up_if_exists((nix, niy)),
+                        down_if_exists((nix, niy), cells_y),
+                        left_if_exists((nix, niy)),
+                    ])
+                } else if piy < niy {
+                    Vec::from([
+                        down_if_exists((nix, niy), cells_y),
+                        left_if_exists((nix, niy)),
+                        right_if_exists((nix, niy), cells_x),
+                    ])
+                } else if piy > niy {
+                    Vec::from([
+                        up_if_exists((nix, niy)),
+                        left_if_exists((nix, niy)),
+                        right_if_exists((nix, niy), cells_x),
+                    ])
+                } else {
+                    panic!()
+                }
+            }
+            None => Vec::from([
+                down_if_exists((nix, niy), cells_y),
+                up_if_exists((nix, niy)),
+                left_if_exists((nix, niy)),
+                right_if_exists((nix, niy), cells_x),
+            ]),
+        }
+    };
+
+    let octilinear = |node: &(usize, Option<usize>)| -> Vec<Option<((usize, usize), f64)>> {
+        let (layer, nix, niy) = grid.cell(node.0);
+        // The direction of travel only matters within a layer, there is none after a via
+        let previous = node.1.map(|previous| grid.cell(previous)).filter(|p| p.0 == layer);
+        match previous {
+            Some((_, pix, piy)) => {
+                if pix < nix {
+                    if piy < niy {
+                        Vec::from([
+                            down_if_exists((nix, niy), cells_y),
+                            right_down_if_exists((nix, niy), cells_x, cells_y),
+                            right_if_exists((nix, niy), cells_x),
+                        ])
+                    } else if piy > niy {
+                        Vec::from([
+                            up_if_exists((nix, niy)),
+                            right_up_if_exists((nix, niy), cells_x),
+                            right_if_exists((nix, niy), cells_x),
+                        ])
+                    } else {
+                        Vec::from([
+                            right_up_if_exists((nix, niy), cells_x),
+                            right_down_if_exists((nix, niy), cells_x, cells_y),
+                            right_if_exists((nix, niy), cells_x),
+                        ])
+                    }
+                } else if pix > nix {
+                    if piy < niy {
+                        Vec::from([
+                            down_if_exists((nix, niy), cells_y),
+                            left_down_if_exists((nix, niy), cells_y),
+                            left_if_exists((nix, niy)),
+                        ])
+                    } else if piy > niy {
+                        Vec::from([
+                            up_if_exists((nix, niy)),
+                            left_up_if_exists((nix, niy)),
+                            left_if_exists((nix, niy)),
+                        ])
+                    } else {
+                        Vec::from([
+                            left_up_if_exists((nix, niy)),
+                            left_down_if_exists((nix, niy), cells_y),
+                            left_if_exists((nix, niy)),
+                        ])
+                    }
+                } else if piy < niy {
+                    Vec::from([
+                        down_if_exists((nix, niy), cells_y),
+                        left_down_if_exists((nix, niy), cells_y),
+                        right_down_if_exists((nix, niy), cells_x, cells_y),
+                    ])
+                } else if piy > niy {
+                    Vec::from([
+                        up_if_exists((nix, niy)),
+                        left_up_if_exists((nix, niy)),
+                        right_up_if_exists((nix, niy), cells_x),
+                    ])
+                } else {
+                    panic!()
+                }
+            }
+            None => Vec::from([
+                down_if_exists((nix, niy), cells_y),
+                left_down_if_exists((nix, niy), cells_y),
+                right_down_if_exists((nix, niy), cells_x, cells_y),
+                up_if_exists((nix, niy)),
+                left_up_if_exists((nix, niy)),
+                right_up_if_exists((nix, niy), cells_x),
+                left_if_exists((nix, niy)),
+                right_if_exists((nix, niy), cells_x),
+            ]),
+        }
+    };
+
+    // Set move function depending on input layout
+    let moves: &Moves = match grid.layout {
+        Layout::Rectilinear => &rectilinear,
+        Layout::Octilinear => &octilinear,
+    };
+
+    // Moves to cells that can be reached (are unblocked, and for diagonal moves, diagonal neighbors are unblocked)
+    let successors = |a: &AStarNode<(usize, Option<usize>)>| -> Vec<((usize, Option<usize>), f64)> {
+        let current = a.node.0;
+        let (layer, nix, niy) = grid.cell(current);
+        moves(&a.node)
+            .into_iter()
+            .flatten()
+            .filter(|&((x, y), _)| in_region(x, y))
+            .filter_map(|((x, y), c)| {
+                let cell_id = grid.cell_id(layer, x, y);
+                let cell_cost = search.cell_cost(cell_id)?;
+                if nix != x && niy != y {
+                    search.passing_cost(grid.cell_id(layer, nix, y))?;
+                    search.passing_cost(grid.cell_id(layer, x, niy))?;
+                    search.keeps_step_clearance(current, cell_id).then_some(())?;
+                }
+                let turn_cost = a.node.1.map_or(0., |previous| grid.turn_cost(previous, current, cell_id));
+                Some(((cell_id, Some(current)), c + cell_cost + turn_cost + search.adjacency_cost(cell_id)))
+            })
+            .chain(vias(a))
+            .collect()
+    };
+
+    // The target port can be reached on any layer
+    let is_target = |n: &(usize, Option<usize>)| -> bool {
+        match search.targets {
+            Some(targets) => targets.contains(&n.0),
+            None => {
+                let (_, nix, niy) = grid.cell(n.0);
+                (nix, niy) == (bx, by)
+            }
+        }
+    };
+
+    // Set the distance (to the closest target) of the chosen heuristic as heuristic
+    let target_positions = match search.targets {
+        Some(targets) => targets
+            .iter()
+            .map(|&cell_id| {
+                let (_, x, y) = grid.cell(cell_id);
+                (x, y)
+            })
+            .collect::<HashSet<(usize, usize)>>(),
+        None => HashSet::from([(target_node_ix, target_node_iy)]),
+    };
+    let distance = |nix: usize, niy: usize| -> f64 {
+        target_positions
+            .iter()
+            .map(|&(tx, ty)| grid.distance(nix.abs_diff(tx), niy.abs_diff(ty)))
+            .fold(f64::INFINITY, f64::min)
+    };
+    // Distances to many targets (e.g., the cells of a Steiner tree) are computed once per cell
+    let distances = RefCell::new(HashMap::<(usize, usize), f64>::new());
+    let heuristic = |i: &(usize, Option<usize>)| -> f64 {
+        let (_, nix, niy) = grid.cell(i.0);
+        if target_positions.len() < CACHED_HEURISTIC_TARGETS {
+            return distance(nix, niy);
+        }
+        *distances
+            .borrow_mut()
+            .entry((nix, niy))
+            .or_insert_with(|| distance(nix, niy))
+    };
+
+    // The path can start on any layer
+    let start: Vec<_> = (0..grid.layers)
+        .map(|layer| grid.cell_id(layer, ax, ay))
+        .filter(|&cell_id| search.can_start_at(cell_id))
+        .map(|cell_id| (cell_id, None))
+        .collect();
+
+    // Expanded nodes count against the total budget and towards the effort of the connection, except for the searches
+    // that only diagnose a failure or find the channels a failed connection would cross
+    let counted = !explore && search.congestion_cost.is_none();
+    let max_expansions = grid
+        .search_limits
+        .expansion_limit(grid.expansions_left.get().filter(|_| counted));
+    let search_start = now_ms();
+    let search_result = |result: SearchResult<(usize, Option<usize>)>| {
+        if counted {
+            if let Some(left) = grid.expansions_left.get() {
+                grid.expansions_left.set(Some(left.saturating_sub(result.explored.len())));
+            }
+            let mut efforts = grid.efforts.borrow_mut();
+            let effort = efforts.entry(search.connection).or_default();
+            effort.expansions += result.explored.len();
+            effort.time_ms += now_ms() - search_start;
+        }
+        let limit_reached = result.path.is_none() && (result.limit_reached || clipped.get());
+        let explored = if explore {
+            Vec::from_iter(result.explored.into_iter().map(|n| n.0))
+        } else {
+            Vec::new()
+        };
+        (result.path.map(|path| path.into_iter().map(|n| n.0).collect()), explored, limit_reached)
+    };
+
+    // Long connections and joins to a tree are also searched backward from the targets
+    let long = f64::hypot(ax.abs_diff(bx) as f64, ay.abs_diff(by) as f64) >= BIDIRECTIONAL_DISTANCE;
+    if grid.bidirectional_search && !explore && (long || search.targets.is_some()) {
+        let is_start = |cell_id: usize| start.iter().any(|s| s.0 == cell_id);
+        let targets = match search.targets {
+            Some(targets) => Vec::from_iter(targets.iter().copied()),
+            None => Vec::from_iter((0..grid.layers).map(|layer| grid.cell_id(layer, bx, by))),
+        };
+        let backward_start = Vec::from_iter(
+            targets
+                .into_iter()
+                .filter(|&cell_id| search.cell_cost(cell_id).is_some() || is_start(cell_id))
+                .map(|cell_id| (cell_id, None)),
+        );
+        let start_heuristic = |i: &(usize, Option<usize>)| -> f64 {
+            let (_, nix, niy) = grid.cell(i.0);
+            grid.distance(nix.abs_diff(ax), niy.abs_diff(ay))
+        };
+        // Backward steps from the next cell of the path (the node's second cell) to the cells a path can come from, at
+        // the cost of the forward step (with the turn at the cell the step leads to)
+        let predecessors = |a: &AStarNode<(usize, Option<usize>)>| -> Vec<((usize, Option<usize>), f64)> {
+            let current = a.node.0;
+            let (layer, nix, niy) = grid.cell(current);
+            let Some(cell_cost) = search.cell_cost(current) else {
+                return Vec::new();
+            };
+            let leaving_cost = cell_cost + search.adjacency_cost(current);
+            let can_come_from = |cell_id: usize| search.cell_cost(cell_id).is_some() || is_start(cell_id);
+            let vias = [layer.checked_sub(1), Some(layer + 1).filter(|&l| l < grid.layers)]
+                .into_iter()
+                .flatten()
+                .map(|l| grid.cell_id(l, nix, niy))
+                .filter(|&cell_id| a.node.1 != Some(cell_id) && can_come_from(cell_id))
+                .map(|cell_id| ((cell_id, Some(current)), grid.via_cost + leaving_cost));
+            moves(&a.node)
+                .into_iter()
+                .flatten()
+                .filter(|&((x, y), _)| in_region(x, y))
+                .filter_map(|((x, y), c)| {
+                    let cell_id = grid.cell_id(layer, x, y);
+                    if !can_come_from(cell_id) {
+                        return None;
+                    }
+                    if nix != x && niy != y {
+                        search.passing_cost(grid.cell_id(layer, nix, y))?;
+                        search.passing_cost(grid.cell_id(layer, x, niy))?;
+                        search.keeps_step_clearance(cell_id, current).then_some(())?;
+                    }
+                    let turn_cost = a.node.1.map_or(0., |next| grid.turn_cost(cell_id, current, next));
+                    Some(((cell_id, Some(current)), c + leaving_cost + turn_cost))
+                })
+                .chain(vias)
+                .collect()
+        };
+        // The paths meet at a cell if they continue in a direction the layout allows, at the cost of the turn there
+        let join_cost = |f: &(usize, Option<usize>), b: &(usize, Option<usize>)| -> Option<f64> {
+            let (Some(previous), Some(next)) = (f.1, b.1) else {
+                return Some(0.);
+            };
+            let (pl, px, py) = grid.cell(previous);
+            let (cl, cx, cy) = grid.cell(f.0);
+            let (nl, nx, ny) = grid.cell(next);
+            if previous == next {
+                return None;
+            }
+            if pl != cl || nl != cl {
+                return Some(0.);
+            }
+            let dot = (cx as isize - px as isize) * (nx as isize - cx as isize)
+                + (cy as isize - py as isize) * (ny as isize - cy as isize);
+            let allowed = match grid.layout {
+                Layout::Rectilinear => dot >= 0,
+                Layout::Octilinear => dot > 0,
+            };
+            allowed.then(|| grid.turn_cost(previous, f.0, next))
+        };
+        let result = bidirectional_a_star(
+            SearchDirection {
+                start: start.clone(),
+                heuristic: &heuristic,
+                successors: &successors,
+            },
+            SearchDirection {
+                start: backward_start,
+                heuristic: &start_heuristic,
+                successors: &predecessors,
+            },
+            &|n| n.0,
+            &join_cost,
+            grid.heuristic_bias,
+            max_expansions,
+            grid.deadline,
+        );
+        return search_result(result);
+    }
+
+    search_result(a_star_limited(
+        start,
+        &heuristic,
+        &successors,
+        &is_target,
+        grid.heuristic_bias,
+        max_expansions,
+        grid.deadline,
+    ))
+}
+
+// Copy of the grid with the cells of the routed paths occupied by their connections
+fn with_paths(nodes: &[GridNode], routing_connections: &[RoutingConnection], paths: &RoutedPaths) -> Vec<GridNode> {
+    let mut routed_nodes = nodes.to_vec();
+    for (i, path) in paths.iter().enumerate() {
+        for &cell_id in path.iter().flatten() {
+            routed_nodes[cell_id].blocked = true;
+            routed_nodes[cell_id].channel = Some(routing_connections[i].connection());
+        }
+    }
+    routed_nodes
+}
+
+// Cells a Steiner branch can join: the root cell (on all layers) and all channels routed for the connection so far
+fn steiner_tree(
+    nodes: &[GridNode],
+    grid: &RoutingGrid,
+    routing_connection: &RoutingConnection,
+) -> Option<HashSet<usize>> {
+    let RoutingConnection::SteinerBranch(steiner_branch) = routing_connection else {
+        return None;
+    };
+    let (root_x, root_y) = steiner_branch.root_cell;
+    Some(
+        (0..grid.layers)
+            .map(|layer| grid.cell_id(layer, root_x, root_y))
+            .chain(
+                (0..nodes.len())
+                    .filter(|&cell_id| nodes[cell_id].channel == Some(steiner_branch.connection)),
+            )
+            .collect(),
+    )
+}
+
+// Join node (cell) of each multi-port connection
+type JoinNodes = HashMap<ConnectionID, (usize, usize)>;
+
+// Path cells of each routing connection, None if the connection could not be routed
+type RoutedPaths = Vec<Option<Vec<usize>>>;
+
+// Routes the connections sequentially in the given order
+fn route_sequentially(
+    nodes: &mut [GridNode],
+    grid: &RoutingGrid,
+    routing_connections: &[RoutingConnection],
+    order: &[usize],
+    history: Option<&[f64]>,
+) -> RoutedPaths {
+    let mut paths = Vec::from_iter(routing_connections.iter().map(|_| None));
+
+    for &i in order {
+        let routing_connection = &routing_connections[i];
+        let Some((from_cell, to_cell)) = routing_connection.cells() else {
+            continue;
+        };
+        let c_id = routing_connection.connection();
+        let tree = steiner_tree(nodes, grid, routing_connection);
+
+        let result = find_path(
+            &PathSearch {
+                nodes,
+                grid,
+                connection: c_id,
+                history,
+                congestion_cost: None,
+                targets: tree.as_ref(),
+            },
+            from_cell,
+            to_cell,
+        );
+
+        // Block the cells of the resulting path so that no subsequent routings can interfere with it.
+        if let Some(mut path) = result {
+            let previous = Vec::from_iter(path.iter().map(|&cell_id| nodes[cell_id].clone()));
+            for &cell_id in path.iter() {
+                let node = &mut nodes[cell_id];
+                node.blocked = true;
+                node.channel = Some(c_id);
+            }
+            // The serpentine is inserted right away, so subsequent routings go around it; without space for it, the
+            // connection fails
+            if let Some(serpentine) = grid.serpentines.get(&i) {
+                let fixed = HashSet::new();
+                if !Detours::new(nodes, grid, c_id, &fixed).add_serpentine(&mut path, serpentine, grid.layout) {
+                    for (&cell_id, node) in path.iter().zip(previous) {
+                        nodes[cell_id] = node;
+                    }
+                    continue;
+                }
+            }
+            paths[i] = Some(path);
+        }
+    }
+
+    paths
+}
+
+// Planar length of the path in cells (vias do not count)
+fn path_length(grid: &RoutingGrid, path: &[usize]) -> f64 {
+    path.windows(2)
+        .map(|w| {
+            let (_, ax, ay) = grid.cell(w[0]);
+            let (_, bx, by) = grid.cell(w[1]);
+            f64::hypot(ax.abs_diff(bx) as f64, ay.abs_diff(by) as f64)
+        })
+        .sum()
+}
+
+// The eight directions of travel, each rotated by 45 degrees against the previous one
+const DIRECTIONS: [(isize, isize); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+// Turns (in multiples of 45 degrees, relative to the straight run) of the steps of a meander of the given height, which
+// is `gap` steps wide at the top
+fn meander_turns(style: Layout, height: usize, gap: usize) -> Vec<isize> {
+    match style {
+        Layout::Rectilinear => std::iter::repeat_n(2, height)
+            .chain(std::iter::repeat_n(0, gap))
+            .chain(std::iter::repeat_n(-2, height))
+            .collect(),
+        // Turns of at most 45 degrees: flanks at 45 degrees and legs perpendicular to the run
+        Layout::Octilinear => std::iter::once(1)
+            .chain(std::iter::repeat_n(2, height.saturating_sub(2)))
+            .chain((height > 1).then_some(1))
+            .chain(std::iter::repeat_n(0, gap))
+            .chain((height > 1).then_some(-1))
+            .chain(std::iter::repeat_n(-2, height.saturating_sub(2)))
+            .chain(std::iter::once(-1))
+            .collect(),
+    }
+}
+
+// Length (in cells) a meander of the given height adds to a straight run in the given direction
+fn meander_extra(style: Layout, height: usize, gap: usize, direction: usize) -> f64 {
+    let (dx, dy) = DIRECTIONS[direction];
+    let (length, (x, y)) = meander_turns(style, height, gap)
+        .into_iter()
+        .map(|turn| DIRECTIONS[(direction as isize + turn).rem_euclid(8) as usize])
+        .fold((0., (0, 0)), |(length, sum), (x, y)| {
+            (length + f64::hypot(x as f64, y as f64), (sum.0 + x, sum.1 + y))
+        });
+    let run = if dx != 0 { x / dx } else { y / dy };
+    length - run as f64 * f64::hypot(dx as f64, dy as f64)
+}
+
+// Height of the highest meander that adds at most the given length to a straight run in the given direction, 0 if none
+// does; from a height of 2 on, every further cell of height adds the same length
+fn max_meander_height(style: Layout, gap: usize, direction: usize, max_extra: f64) -> usize {
+    let extra = |height| meander_extra(style, height, gap, direction);
+    if extra(1) > max_extra {
+        return 0;
+    }
+    let (base, slope) = (extra(2), extra(3) - extra(2));
+    if base > max_extra {
+        return 1;
+    }
+    // Heights that exceed the length by a rounding error are left to the exact check of the caller
+    2 + ((max_extra - base) / slope + 1e-9).floor() as usize
+}
+
+// Serpentine in cells
+struct SerpentineCells {
+    height: usize,
+    turns: usize,
+    segment: Option<usize>,
+}
+
+// Length (in cells) to be added to a path with meanders of the given style
+struct MeanderTarget {
+    length: f64,
+    tolerance: f64,
+    style: Layout,
+}
+
+// Inserts detours (meanders and serpentines) into straight runs of a routed path of the connection
+struct Detours<'a> {
+    nodes: &'a mut [GridNode],
+    grid: &'a RoutingGrid,
+    connection: ConnectionID,
+    fixed: &'a HashSet<usize>, // cells that must stay on the path (e.g., where other channels of the connection join)
+    gap: usize, // distance (in cells) between neighboring legs of the channel
+}
+
+impl<'a> Detours<'a> {
+    fn new(
+        nodes: &'a mut [GridNode],
+        grid: &'a RoutingGrid,
+        connection: ConnectionID,
+        fixed: &'a HashSet<usize>,
+    ) -> Self {
+        // Neighboring legs of the channel keep its clearance
+        let gap = grid
+            .footprints
+            .as_ref()
+            .map_or(1, |footprints| {
+                let footprint = footprints.footprint(connection);
+                (footprint.clearance(&footprint) / footprints.cell_size * (1. - 1e-9)).ceil() as usize
+            })
+            .max(1);
+        Detours {
+            nodes,
+            grid,
+            connection,
+            fixed,
+            gap,
+        }
+    }
+
+    // Direction of the step between two cells on the same layer
+    fn direction(&self, from: usize, to: usize) -> Option<usize> {
+        let (from_layer, fx, fy) = self.grid.cell(from);
+        let (to_layer, tx, ty) = self.grid.cell(to);
+        let step = (tx as isize - fx as isize, ty as isize - fy as isize);
+        (from_layer == to_layer).then(|| DIRECTIONS.iter().position(|&d| d == step))?
+    }
+
+    // Steps of the detour along the path from cell i on, and the number of path steps it replaces
+    fn steps(&self, path: &[usize], i: usize, turns: &[isize]) -> Option<(Vec<(isize, isize)>, usize)> {
+        let direction = self.direction(path[i], *path.get(i + 1)?)?;
+        let (dx, dy) = DIRECTIONS[direction];
+        let steps = Vec::from_iter(
+            turns
+                .iter()
+                .map(|turn| DIRECTIONS[(direction as isize + turn).rem_euclid(8) as usize]),
+        );
+        let sum = steps.iter().fold((0, 0), |a, s| (a.0 + s.0, a.1 + s.1));
+        let run = if dx != 0 { sum.0 / dx } else { sum.1 / dy };
+        (run > 0).then_some((steps, run as usize))
+    }
+
+    // Replaces the straight run of the path from cell i on with the detour, if the run is long enough and the detour is
+    // free
+    fn try_insert(&mut self, path: &mut Vec<usize>, i: usize, turns: &[isize]) -> bool {
+        let grid = self.grid;
+        let Some((steps, run)) = self.steps(path, i, turns) else {
+            return false;
+        };
+        let end = i + run;
+        let direction = self.direction(path[i], path[i + 1]);
+
+        // The run is straight, also `gap` steps before and after the detour
+        if end >= path.len()
+            || !(i.saturating_sub(self.gap)..(end + self.gap).min(path.len() - 1))
+                .all(|j| self.direction(path[j], path[j + 1]) == direction)
+            || path[i + 1..end].iter().any(|cell_id| self.fixed.contains(cell_id))
+        {
+            return false;
+        }
+
+        // Free the cells of the run for the detour
+        for &cell_id in path[i + 1..end].iter() {
+            self.nodes[cell_id].blocked = false;
+            self.nodes[cell_id].channel = None;
+        }
+        let search = PathSearch {
+            nodes: self.nodes,
+            grid,
+            connection: self.connection,
+            history: None,
+            congestion_cost: None,
+            targets: None,
+        };
+        let in_grid = |(x, y): (isize, isize)| {
+            0 <= x && x < grid.cells_x as isize && 0 <= y && y < grid.cells_y as isize
+        };
+        let (layer, sx, sy) = grid.cell(path[i]);
+        let mut position = (sx as isize, sy as isize);
+        let mut cells = Vec::new();
+        let free = steps.iter().all(|&(x, y)| {
+            let previous = position;
+            position = (previous.0 + x, previous.1 + y);
+            if !in_grid(position) {
+                return false;
+            }
+            let cell_id = grid.cell_id(layer, position.0 as usize, position.1 as usize);
+            let diagonal_sides = [(previous.0, position.1), (position.0, previous.1)];
+            cells.push(cell_id);
+            // The detour ends on the run
+            cell_id == path[end]
+                || (!cells[..cells.len() - 1].contains(&cell_id)
+                    && search.cell_cost(cell_id).is_some()
+                    && (x == 0
+                        || y == 0
+                        || (diagonal_sides.iter().all(|&(x, y)| {
+                            search
+                                .passing_cost(grid.cell_id(layer, x as usize, y as usize))
+                                .is_some()
+                        }) && search.keeps_step_clearance(
+                            grid.cell_id(layer, previous.0 as usize, previous.1 as usize),
+                            cell_id,
+                        ))))
+        });
+
+        let inserted = free && cells.last() == Some(&path[end]);
+        if inserted {
+            cells.pop();
+            path.splice(i + 1..end, cells);
+        }
+        let run_cells = if inserted { i + 1..i + steps.len() } else { i + 1..end };
+        for &cell_id in path[run_cells].iter() {
+            self.nodes[cell_id].blocked = true;
+            self.nodes[cell_id].channel = Some(self.connection);
+        }
+        inserted
+    }
+
+    // Inserts meanders until the path is longer by the target length within the tolerance, and returns the added length
+    fn add_meanders(
+        &mut self,
+        path: &mut Vec<usize>,
+        MeanderTarget {
+            length,
+            tolerance,
+            style,
+        }: MeanderTarget,
+    ) -> f64 {
+        let mut added = 0.;
+        let mut start = 0;
+        while length - added > tolerance {
+            let Some((i, extra)) = self.add_meander(path, start, length - added + tolerance, style) else {
+                break;
+            };
+            added += extra;
+            // Runs before the meander have no room for another one
+            start = i;
+        }
+        added
+    }
+
+    // Inserts the highest meander that adds at most the given length into the first run from path index `start` on
+    // with room for it, and returns its path index and the added length
+    fn add_meander(
+        &mut self,
+        path: &mut Vec<usize>,
+        start: usize,
+        max_extra: f64,
+        style: Layout,
+    ) -> Option<(usize, f64)> {
+        let max_height = self.grid.cells_x.max(self.grid.cells_y);
+        for i in start..path.len().saturating_sub(1) {
+            let Some(direction) = self.direction(path[i], path[i + 1]) else {
+                continue;
+            };
+            let fitting_height = max_meander_height(style, self.gap, direction, max_extra).min(max_height);
+            for side in [1, -1] {
+                for height in (1..=fitting_height).rev() {
+                    let turns = Vec::from_iter(
+                        meander_turns(style, height, self.gap).into_iter().map(|turn| side * turn),
+                    );
+                    let extra = meander_extra(style, height, self.gap, direction);
+                    if extra <= max_extra && self.try_insert(path, i, &turns) {
+                        return Some((i, extra));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // Inserts the serpentine into the given straight segment of the path (or the longest one), as close to its center
+    // as possible
+    fn add_serpentine(&mut self, path: &mut Vec<usize>, serpentine: &SerpentineCells, style: Layout) -> bool {
+        // Straight segments (first and last cell index) of the path
+        let mut segments = Vec::<(usize, usize)>::new();
+        for j in 0..path.len().saturating_sub(1) {
+            match segments.last_mut() {
+                Some(segment)
+                    if segment.1 == j
+                        && self.direction(path[j], path[j + 1])
+                            == self.direction(path[segment.0], path[segment.0 + 1]) =>
+                {
+                    segment.1 = j + 1
+                }
+                _ => segments.push((j, j + 1)),
+            }
+        }
+        let segment = match serpentine.segment {
+            Some(segment) => segments.get(segment).copied(),
+            None => segments.iter().copied().max_by_key(|(first, last)| last - first),
+        };
+        let Some((first, last)) = segment else {
+            return false;
+        };
+
+        for side in [1, -1] {
+            // Bumps to alternating sides form the serpentine
+            let turns = Vec::from_iter((0..serpentine.turns).flat_map(|k| {
+                let bump_side = if k % 2 == 0 { side } else { -side };
+                meander_turns(style, serpentine.height, self.gap)
+                    .into_iter()
+                    .map(move |turn| bump_side * turn)
+            }));
+            let Some((_, run)) = self.steps(path, first, &turns) else {
+                return false;
+            };
+            let Some(free) = (last - first).checked_sub(run) else {
+                return false;
+            };
+            let mut starts = Vec::from_iter(first..=first + free);
+            starts.sort_by_key(|&i| (2 * i + run).abs_diff(first + last));
+            for i in starts {
+                if self.try_insert(path, i, &turns) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+// Result of routing all connections in a given order
+struct Routing {
+    order: Vec<usize>,
+    paths: RoutedPaths,
+}
+
+impl Routing {
+    fn n_routed(&self) -> usize {
+        self.paths.iter().filter(|p| p.is_some()).count()
+    }
+
+    // Total length of all routed paths in cells
+    fn length(&self, grid: &RoutingGrid) -> f64 {
+        self.paths.iter().flatten().map(|path| path_length(grid, path)).sum()
+    }
+
+    // More routed connections are better; ties are broken by the shorter total length
+    fn is_better_than(&self, other: &Routing, grid: &RoutingGrid) -> bool {
+        match self.n_routed().cmp(&other.n_routed()) {
+            Ordering::Greater => true,
+            Ordering::Less => false,
+            Ordering::Equal => self.length(grid) < other.length(grid),
+        }
+    }
+}
+
+// Routes the connections on a copy of the given grid, either once in the given order or iteratively with rip-up and
+// reroute
+fn route_connections(
+    nodes: &[GridNode],
+    grid: &RoutingGrid,
+    routing_connections: &[RoutingConnection],
+    mut order: Vec<usize>,
+    rip_up_reroute: Option<RipUpReroute>,
+) -> Routing {
+    let Some(rip_up_reroute) = rip_up_reroute else {
+        let mut nodes = nodes.to_vec();
+        let paths = route_sequentially(
+            &mut nodes,
+            grid,
+            routing_connections,
+            &order,
+            None,
+        );
+        return Routing { order, paths };
+    };
+
+    let mut history = vec![0.; nodes.len()];
+    let mut present_cost = CONGESTION_COST;
+    let mut best: Option<Routing> = None;
+
+    for _ in 0..rip_up_reroute.max_iterations.max(1) {
+        // Every iteration rips up all channels and reroutes them on the initial grid
+        let mut iteration_nodes = nodes.to_vec();
+        let paths = route_sequentially(
+            &mut iteration_nodes,
+            grid,
+            routing_connections,
+            &order,
+            Some(&history),
+        );
+        let failed = Vec::from_iter(order.iter().copied().filter(|&i| paths[i].is_none()));
+
+        let routing = Routing {
+            order: order.clone(),
+            paths,
+        };
+        if best
+            .as_ref()
+            .is_none_or(|best| routing.is_better_than(best, grid))
+        {
+            best = Some(routing);
+        }
+        if failed.is_empty() {
+            break;
+        }
+
+        // Let the connections negotiate: the cells they share when they are all routed at the present cost of sharing
+        // become more expensive for good, and sharing becomes more expensive in the next iteration
+        for cell_id in shared_cells(nodes, grid, routing_connections, &order, &history, present_cost) {
+            history[cell_id] += rip_up_reroute.history_increment;
+        }
+        present_cost *= PRESENT_COST_GROWTH;
+
+        // Failed connections are routed first in the next iteration
+        order = failed
+            .iter()
+            .copied()
+            .chain(order.iter().copied().filter(|i| !failed.contains(i)))
+            .collect();
+    }
+
+    best.unwrap()
+}
+
+// Negotiation pass of rip-up and reroute: routes the connections in the given order on a copy of the given grid, where
+// each may cross the channels of the connections before it at the present cost, and returns the cells they share
+fn shared_cells(
+    nodes: &[GridNode],
+    grid: &RoutingGrid,
+    routing_connections: &[RoutingConnection],
+    order: &[usize],
+    history: &[f64],
+    present_cost: f64,
+) -> Vec<usize> {
+    let mut nodes = nodes.to_vec();
+    let mut shared = Vec::new();
+    for &i in order {
+        let routing_connection = &routing_connections[i];
+        let Some((from_cell, to_cell)) = routing_connection.cells() else {
+            continue;
+        };
+        let c_id = routing_connection.connection();
+        let tree = steiner_tree(&nodes, grid, routing_connection);
+        let search = PathSearch {
+            nodes: &nodes,
+            grid,
+            connection: c_id,
+            history: Some(history),
+            congestion_cost: Some(present_cost),
+            targets: tree.as_ref(),
+        };
+        let Some(path) = find_path(&search, from_cell, to_cell) else {
+            continue;
+        };
+        shared.append(&mut search.crossed_channel_cells(&path));
+        // Shared cells stay with the channel that occupied them first
+        for cell_id in path {
+            let node = &mut nodes[cell_id];
+            if node.channel.is_none() {
+                node.blocked = true;
+                node.channel = Some(c_id);
+            }
+        }
+    }
+    shared
+}
+
+// Candidate routing orders (indices into routing_connections) for the given ordering strategy
+fn connection_orders(
+    ordering: &ConnectionOrdering,
+    input_connections: &[RouteInputConnection],
+    routing_connections: &[RoutingConnection],
+) -> Vec<Vec<usize>> {
+    let mut order = Vec::from_iter(0..routing_connections.len());
+    let distance = |i: usize| {
+        routing_connections[i]
+            .cells()
+            .map_or(0., |((ax, ay), (bx, by))| {
+                f64::hypot(ax.abs_diff(bx) as f64, ay.abs_diff(by) as f64)
+            })
+    };
+
+    match ordering {
+        ConnectionOrdering::Default => {
+            order.sort_by(|&a, &b| cmp_connections(&routing_connections[a], &routing_connections[b]));
+        }
+        ConnectionOrdering::ShortestFirst => {
+            order.sort_by(|&a, &b| f64::total_cmp(&distance(a), &distance(b)));
+        }
+        ConnectionOrdering::LongestFirst => {
+            order.sort_by(|&a, &b| f64::total_cmp(&distance(b), &distance(a)));
+        }
+        ConnectionOrdering::MostConstrainedFirst => {
+            // Bounding boxes (in cells) of all connections
+            let mut boxes = HashMap::<ConnectionID, ((usize, usize), (usize, usize))>::new();
+            for routing_connection in routing_connections {
+                if let Some((a, b)) = routing_connection.cells() {
+                    let bounding_box = boxes
+                        .entry(routing_connection.connection())
+                        .or_insert((a, a));
+                    for (x, y) in [a, b] {
+                        bounding_box.0 = (bounding_box.0 .0.min(x), bounding_box.0 .1.min(y));
+                        bounding_box.1 = (bounding_box.1 .0.max(x), bounding_box.1 .1.max(y));
+                    }
+                }
+            }
+            let overlaps = |c_id: ConnectionID| {
+                let Some((min, max)) = boxes.get(&c_id) else {
+                    return 0;
+                };
+                boxes
+                    .iter()
+                    .filter(|(other, (other_min, other_max))| {
+                        **other != c_id
+                            && min.0 <= other_max.0
+                            && other_min.0 <= max.0
+                            && min.1 <= other_max.1
+                            && other_min.1 <= max.1
+                    })
+                    .count()
+            };
+            order.sort_by(|&a, &b| {
+                usize::cmp(
+                    &overlaps(routing_connections[b].connection()),
+                    &overlaps(routing_connections[a].connection()),
+                )
+                .then_with(|| f64::total_cmp(&distance(a), &distance(b)))
+            });
+        }
+        ConnectionOrdering::Priority => {
+            let priority = |i: usize| {
+                let c_id = routing_connections[i].connection();
+                input_connections
+                    .iter()
+                    .find(|c| c.id == c_id)
+                    .and_then(|c| c.priority)
+                    .unwrap_or(0)
+            };
+            order.sort_by(|&a, &b| {
+                i64::cmp(&priority(b), &priority(a)).then_with(|| {
+                    cmp_connections(&routing_connections[a], &routing_connections[b])
+                })
+            });
+        }
+        ConnectionOrdering::Random { seed } => {
+            shuffle(&mut order, *seed);
+        }
+        ConnectionOrdering::RandomRestarts { seed, restarts } => {
+            return connection_orders(&ConnectionOrdering::Default, input_connections, routing_connections)
+                .into_iter()
+                .chain((0..*restarts).map(|i| {
+                    let mut order = order.clone();
+                    shuffle(&mut order, seed.wrapping_add(i as u64));
+                    order
+                }))
+                .collect();
+        }
+        ConnectionOrdering::BestOf(orderings) => {
+            return orderings
+                .iter()
+                .flat_map(|ordering| connection_orders(ordering, input_connections, routing_connections))
+                .collect();
+        }
+    }
+
+    Vec::from([order])
+}
+
+// Deterministic Fisher-Yates shuffle driven by a SplitMix64 generator (rand is not available for WebAssembly)
+fn shuffle(order: &mut [usize], seed: u64) {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    };
+    for i in (1..order.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        order.swap(i, j);
+    }
+}
+
+// Candidate ports tried for each endpoint when assigning ports, the closest first
+const MAX_PORT_CANDIDATES: usize = 8;
+
+// Routings of candidate assignments tried in addition to the initial assignment
+const MAX_PORT_REROUTES: usize = 16;
+
+// Passes over all endpoints with candidates, as long as a pass improves the assignment
+const MAX_PORT_ASSIGNMENT_PASSES: usize = 2;
+
+pub fn route(input: &RouteInput) -> BoardRouterOutput {
+    let start = now_ms();
+    // The target resistance of a connection is not split across the branches of stars and trees
+    if let Some(c) = input.connections.iter().find(|c| {
+        c.target_resistance.is_some() && (c.ports.len() != 2 || c.branch_port.is_some())
+    }) {
+        return Err(BoardRouterOutputError::InvalidTargetResistance(c.id));
+    }
+    if input.backend == RoutingBackend::Gridless {
+        if let Some(setting) = gridless_router::unsupported_setting(input) {
+            return Err(BoardRouterOutputError::UnsupportedSetting(setting));
+        }
+    }
+    let deadline = input.search_limits.max_time_ms.map(|time| start + time);
+    let mut output = if input
+        .connections
+        .iter()
+        .all(|c| c.port_candidates.iter().all(|candidates| candidates.is_empty()))
+    {
+        route_assigned(input, deadline)
+    } else {
+        assign_ports(input, deadline)
+    };
+    if let Ok(board) | Err(BoardRouterOutputError::PartialResult(board)) = &mut output {
+        board.statistics.time_ms = now_ms() - start;
+    }
+    output
+}
+
+// Board position of a port
+pub(crate) fn port_point(input: &RouteInput, port: &Port) -> Point {
+    if let Some(definition) = input.ports.iter().find(|d| d.port == *port) {
+        definition.position
+    } else if let Some(edge_port) = input.edge_ports.iter().find(|e| e.port == *port) {
+        edge_port.center(input.board_width, input.board_height)
+    } else {
+        [
+            input.pitch_offset_x + port.0 as f64 * input.pitch,
+            input.pitch_offset_y + port.1 as f64 * input.pitch,
+        ]
+    }
+}
+
+// Picks a port of every candidate set by coordinate descent: each endpoint in turn tries its closest free candidates
+// (within a budget of reroutes), and the assignment with the most routed channels and then the shortest total length is
+// kept
+fn assign_ports(input: &RouteInput, deadline: Option<f64>) -> BoardRouterOutput {
+    let score = |output: &BoardRouterOutput| match output {
+        Ok(board) | Err(BoardRouterOutputError::PartialResult(board)) => {
+            let channels = board.connections.iter().flat_map(|(_, channels)| channels);
+            (
+                channels.clone().count(),
+                -channels.map(channel_length).sum::<f64>(),
+            )
+        }
+        _ => (0, 0.),
+    };
+    let is_better = |a: (usize, f64), b: (usize, f64)| a.0 > b.0 || (a.0 == b.0 && a.1 > b.1);
+
+    // Endpoints with candidates as (connection index, port index, candidate ports)
+    let endpoints = Vec::from_iter(input.connections.iter().enumerate().flat_map(|(i, c)| {
+        c.port_candidates
+            .iter()
+            .enumerate()
+            .filter(|(j, candidates)| !candidates.is_empty() && *j < c.ports.len())
+            .map(move |(j, candidates)| {
+                let others = Vec::from_iter(
+                    (0..c.ports.len())
+                        .filter(|&k| k != j)
+                        .map(|k| port_point(input, &c.ports[k])),
+                );
+                let distance = |port: &Port| {
+                    let [x, y] = port_point(input, port);
+                    others.iter().map(|p| f64::hypot(p[0] - x, p[1] - y)).sum::<f64>()
+                };
+                let mut candidates = Vec::from_iter(
+                    candidates.iter().copied().filter(|port| *port != c.ports[j]),
+                );
+                candidates.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+                candidates.insert(0, c.ports[j]);
+                (i, j, candidates)
+            })
+    }));
+    // Whether no other endpoint than the given one takes the port
+    let is_free = |connections: &[RouteInputConnection], port: &Port, (i, j): (usize, usize)| {
+        !input.blocked_ports.contains(port)
+            && connections.iter().enumerate().all(|(k, c)| {
+                c.branch_port != Some(*port)
+                    && c.ports.iter().enumerate().all(|(l, p)| p != port || (k, l) == (i, j))
+            })
+    };
+
+    // Start from the given ports, moving endpoints whose port is taken to the closest free candidate
+    let mut assigned = input.clone();
+    for (i, j, candidates) in endpoints.iter() {
+        let given = assigned.connections[*i].ports[*j];
+        let port = candidates
+            .iter()
+            .find(|port| is_free(&assigned.connections, port, (*i, *j)))
+            .unwrap_or(&given);
+        assigned.connections[*i].ports[*j] = *port;
+    }
+    let mut best = route_assigned(&assigned, deadline);
+    let mut best_score = score(&best);
+
+    let mut reroutes = 0;
+    'search: for _ in 0..MAX_PORT_ASSIGNMENT_PASSES {
+        let mut improved = false;
+        for (i, j, candidates) in endpoints.iter() {
+            for candidate in candidates.iter().take(MAX_PORT_CANDIDATES) {
+                if *candidate == assigned.connections[*i].ports[*j]
+                    || !is_free(&assigned.connections, candidate, (*i, *j))
+                {
+                    continue;
+                }
+                if reroutes == MAX_PORT_REROUTES {
+                    break 'search;
+                }
+                reroutes += 1;
+                let mut trial = assigned.clone();
+                trial.connections[*i].ports[*j] = *candidate;
+                let output = route_assigned(&trial, deadline);
+                let trial_score = score(&output);
+                if is_better(trial_score, best_score) {
+                    (assigned, best, best_score) = (trial, output, trial_score);
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+
+    // Report the chosen ports of the connections with candidates
+    if let Ok(board) | Err(BoardRouterOutputError::PartialResult(board)) = &mut best {
+        board.assigned_ports = Vec::from_iter(
+            assigned
+                .connections
+                .iter()
+                .filter(|c| c.port_candidates.iter().any(|candidates| !candidates.is_empty()))
+                .map(|c| (c.id, c.ports.clone())),
+        );
+    }
+    best
+}
+
+// Routes independent clusters of connections separately if possible, and the whole board otherwise; no path search
+// continues after the deadline (in ms, see now_ms)
+fn route_assigned(input: &RouteInput, deadline: Option<f64>) -> BoardRouterOutput {
+    let clusters = connection_clusters(input);
+    // The total search budget is shared by the connections of the whole board
+    let shared_budget = input.search_limits.max_total_expansions.is_some();
+    // Random orders and the best of several orders are drawn and compared for the connections of the whole board, so
+    // the same seed gives the same result with and without clusters
+    let board_ordering = matches!(
+        input.ordering,
+        ConnectionOrdering::Random { .. } | ConnectionOrdering::RandomRestarts { .. } | ConnectionOrdering::BestOf(_)
+    );
+    if clusters.len() > 1 && !input.grid_snapshots && !shared_budget && !board_ordering {
+        if let Some(output) = route_clusters(input, &clusters, deadline) {
+            return output;
+        }
+    }
+    route_board(input, None, deadline)
+}
+
+// Routes the board, on the grid within the window if given
+fn route_board(input: &RouteInput, window: Option<(Point, Point)>, deadline: Option<f64>) -> BoardRouterOutput {
+    match input.backend {
+        RoutingBackend::Grid => route_on_grid(input, window, deadline),
+        RoutingBackend::Gridless => gridless_router::route(input, deadline),
+    }
+}
+
+// Room (in pitches) around the ports of a connection for its channels when routed within a cluster
+const CLUSTER_MARGIN: f64 = 2.;
+
+// Connections (by index) whose channels are expected to stay within the region
+struct Cluster {
+    connections: Vec<usize>,
+    region: (Point, Point), // the bounding box of the ports grown by the margin
+}
+
+// Coarse grid of the board (cells of a pitch) for the reachability of ports around keep-outs and locked channels
+struct Reachability {
+    step: f64,
+    cells_x: usize,
+    cells_y: usize,
+    blocked: Vec<bool>, // cells a channel may not be able to pass, by x * cells_y + y
+}
+
+impl Reachability {
+    fn new(input: &RouteInput) -> Self {
+        let step = input.pitch.max(input.channel_width + input.channel_spacing);
+        let cells_x = ((input.board_width / step).ceil() as usize).max(1);
+        let cells_y = ((input.board_height / step).ceil() as usize).max(1);
+        let port_radius = |port: &Port| {
+            input.ports.iter().find(|d| d.port == *port).map_or(input.port_diameter, |d| d.diameter) / 2.
+        };
+        let keep_outs = Vec::from_iter(input.keep_outs.iter().cloned().chain(input.blocked_ports.iter().map(|port| {
+            KeepOut::Circle {
+                center: port_point(input, port),
+                radius: port_radius(port),
+            }
+        })));
+        let widest = input.widest_footprint();
+        let locked_segments = Vec::from_iter(input.locked_connections.iter().flat_map(|(c_id, channels)| {
+            let clearance = input.footprint(*c_id).clearance(&widest);
+            channels.iter().flat_map(move |channel| channel.windows(2).map(move |w| (clearance, w[0], w[1])))
+        }));
+        // A cell is blocked if an obstacle comes close to any of its points
+        let keep_out_clearance = widest.spacing + widest.half_width + step * f64::consts::FRAC_1_SQRT_2;
+        let blocked = Vec::from_iter((0..cells_x * cells_y).map(|i| {
+            let center = [((i / cells_y) as f64 + 0.5) * step, ((i % cells_y) as f64 + 0.5) * step];
+            keep_outs.iter().any(|keep_out| keep_out.distance(center) < keep_out_clearance)
+                || locked_segments.iter().any(|(clearance, a, b)| {
+                    segment_distance(center, *a, *b) < clearance + step * f64::consts::FRAC_1_SQRT_2
+                })
+        }));
+        Reachability {
+            step,
+            cells_x,
+            cells_y,
+            blocked,
+        }
+    }
+
+    fn cell(&self, [x, y]: Point) -> usize {
+        let coordinate = |p: f64, cells: usize| ((p / self.step).floor().max(0.) as usize).min(cells - 1);
+        coordinate(x, self.cells_x) * self.cells_y + coordinate(y, self.cells_y)
+    }
+
+    // Cell centers on the paths from the first point to the others, empty if the points are connected within the
+    // region, or None if they are not connected at all
+    fn detour(&self, points: &[Point], (min, max): (Point, Point)) -> Option<Vec<Point>> {
+        let cells = Vec::from_iter(points.iter().map(|p| self.cell(*p)));
+        let center = |i: usize| [((i / self.cells_y) as f64 + 0.5) * self.step, ((i % self.cells_y) as f64 + 0.5) * self.step];
+        let inside = |i: usize| {
+            let [x, y] = center(i);
+            min[0] <= x && x <= max[0] && min[1] <= y && y <= max[1]
+        };
+        let search = |within_region: bool| {
+            let mut previous = vec![None; self.blocked.len()];
+            previous[cells[0]] = Some(cells[0]);
+            let mut open = VecDeque::from([cells[0]]);
+            while let Some(i) = open.pop_front() {
+                let (x, y) = (i / self.cells_y, i % self.cells_y);
+                let next = [
+                    (x > 0).then(|| i - self.cells_y),
+                    (x + 1 < self.cells_x).then(|| i + self.cells_y),
+                    (y > 0).then(|| i - 1),
+                    (y + 1 < self.cells_y).then(|| i + 1),
+                ];
+                for j in next.into_iter().flatten() {
+                    if previous[j].is_none()
+                        && (!self.blocked[j] || cells.contains(&j))
+                        && (!within_region || inside(j) || cells.contains(&j))
+                    {
+                        previous[j] = Some(i);
+                        open.push_back(j);
+                    }
+                }
+            }
+            previous
+        };
+        if search(true).iter().enumerate().all(|(i, p)| p.is_some() || !cells.contains(&i)) {
+            return Some(Vec::new());
+        }
+        let previous = search(false);
+        let mut detour = Vec::new();
+        for &cell in cells.iter().skip(1) {
+            let mut i = cell;
+            while i != cells[0] {
+                detour.push(center(i));
+                i = previous[i]?;
+            }
+        }
+        Some(detour)
+    }
+}
+
+// Clusters of connections with overlapping regions (the bounding boxes of their ports, grown by the margin, a port
+// radius and the largest clearance), in the order of their first connection; a length-match group is always in a single
+// cluster
+// Regions grow by the detour around keep-outs and locked channels between the ports, and without a path between the
+// ports, nothing is clustered
+fn connection_clusters(input: &RouteInput) -> Vec<Cluster> {
+    let port_radius = input
+        .port_definitions()
+        .iter()
+        .map(|d| d.diameter / 2.)
+        .fold(input.port_diameter / 2., f64::max);
+    let widest = input.widest_footprint();
+    let margin = CLUSTER_MARGIN * input.pitch + port_radius + widest.clearance(&widest);
+
+    // Connections without ports are left to the router of the whole board
+    if input.connections.iter().any(|c| c.ports.is_empty()) {
+        return Vec::new();
+    }
+    let is_locked = |c_id: ConnectionID| input.locked_connections.iter().any(|(id, _)| *id == c_id);
+    let mut clusters = Vec::from_iter(
+        input
+            .connections
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !is_locked(c.id))
+            .map(|(i, c)| {
+                let points = Vec::from_iter(c.ports.iter().chain(c.branch_port.iter()).map(|p| port_point(input, p)));
+                let min = points.iter().fold([f64::INFINITY; 2], |m, p| [m[0].min(p[0]), m[1].min(p[1])]);
+                let max = points.iter().fold([f64::NEG_INFINITY; 2], |m, p| [m[0].max(p[0]), m[1].max(p[1])]);
+                Cluster {
+                    connections: Vec::from([i]),
+                    region: ([min[0] - margin, min[1] - margin], [max[0] + margin, max[1] + margin]),
+                }
+            }),
+    );
+    if !input.keep_outs.is_empty() || !input.blocked_ports.is_empty() || !input.locked_connections.is_empty() {
+        let reachability = Reachability::new(input);
+        for cluster in clusters.iter_mut() {
+            let c = &input.connections[cluster.connections[0]];
+            let points = Vec::from_iter(c.ports.iter().chain(c.branch_port.iter()).map(|p| port_point(input, p)));
+            let Some(detour) = reachability.detour(&points, cluster.region) else {
+                return Vec::new();
+            };
+            let (min, max) = &mut cluster.region;
+            for p in detour {
+                *min = [min[0].min(p[0] - margin), min[1].min(p[1] - margin)];
+                *max = [max[0].max(p[0] + margin), max[1].max(p[1] + margin)];
+            }
+        }
+    }
+
+    let in_group = |a: &Cluster, b: &Cluster| {
+        input.length_match_groups.iter().any(|group| {
+            let contains = |cluster: &Cluster| {
+                cluster
+                    .connections
+                    .iter()
+                    .any(|&i| group.connections.contains(&input.connections[i].id))
+            };
+            contains(a) && contains(b)
+        })
+    };
+    let overlap = |a: &Cluster, b: &Cluster| {
+        let ((a_min, a_max), (b_min, b_max)) = (a.region, b.region);
+        a_min[0] <= b_max[0] && b_min[0] <= a_max[0] && a_min[1] <= b_max[1] && b_min[1] <= a_max[1]
+    };
+
+    // Merge clusters until no two overlap
+    let mut merged = true;
+    while merged {
+        merged = false;
+        'search: for i in 0..clusters.len() {
+            for j in i + 1..clusters.len() {
+                if overlap(&clusters[i], &clusters[j]) || in_group(&clusters[i], &clusters[j]) {
+                    let other = clusters.remove(j);
+                    let cluster = &mut clusters[i];
+                    cluster.connections.extend(other.connections);
+                    cluster.connections.sort();
+                    let ((a_min, a_max), (b_min, b_max)) = (cluster.region, other.region);
+                    cluster.region = (
+                        [a_min[0].min(b_min[0]), a_min[1].min(b_min[1])],
+                        [a_max[0].max(b_max[0]), a_max[1].max(b_max[1])],
+                    );
+                    merged = true;
+                    break 'search;
+                }
+            }
+        }
+    }
+    clusters
+}
+
+// Routes the clusters on their own grid cells (concurrently on native targets) and merges their channels, or None if no
+// cluster is completely routed or a worker failed
+// Channels that keep half the clearance inside their region cannot come too close to the channels and ports of other
+// clusters; the connections of the other clusters are rerouted together on the grid of the whole board, around the
+// merged channels, so the output only depends on the result of each cluster and not on which cluster finishes first
+fn route_clusters(input: &RouteInput, clusters: &[Cluster], deadline: Option<f64>) -> Option<BoardRouterOutput> {
+    let cluster_inputs = Vec::from_iter(clusters.iter().map(|cluster| {
+        let connections = Vec::from_iter(cluster.connections.iter().map(|&i| input.connections[i].clone()));
+        let length_match_groups = Vec::from_iter(
+            input
+                .length_match_groups
+                .iter()
+                .filter(|group| connections.iter().any(|c| group.connections.contains(&c.id)))
+                .cloned(),
+        );
+        RouteInput {
+            connections,
+            length_match_groups,
+            ..input.clone()
+        }
+    }));
+    let widest = input.widest_footprint();
+    let inset = widest.half_width + widest.spacing / 2.;
+    // Channel centers on the cells within the inset region keep half the clearance to the region border
+    let windows = Vec::from_iter(clusters.iter().map(|Cluster { region: (min, max), .. }| {
+        ([min[0] + inset, min[1] + inset], [max[0] - inset, max[1] - inset])
+    }));
+    let outputs = route_concurrently(cluster_inputs.into_iter().zip(windows).collect(), deadline)?;
+    let routed = Vec::from_iter(clusters.iter().zip(outputs).map(|(cluster, output)| {
+        let board = output.ok()?;
+        let (min, max) = cluster.region;
+        let inside = |p: &Point| {
+            p[0] >= min[0] + inset && p[0] <= max[0] - inset && p[1] >= min[1] + inset && p[1] <= max[1] - inset
+        };
+        let channels = board.connections[input.locked_connections.len()..].iter().flat_map(|(_, channels)| channels);
+        channels.flatten().all(inside).then_some(board)
+    }));
+    if routed.iter().all(Option::is_none) {
+        return None;
+    }
+
+    let mut merged = BoardRouterOutputBoard {
+        connections: input.locked_connections.clone(),
+        layers: Vec::new(),
+        channel_widths: Vec::new(),
+        lengths: Vec::new(),
+        resistances: Vec::new(),
+        bend_radii: Vec::new(),
+        assigned_ports: Vec::new(),
+        failures: Vec::new(),
+        setup_grid: None,
+        routed_grid: None,
+        statistics: RoutingStatistics::default(),
+    };
+    // The channels of a board follow its locked connections
+    let merge = |merged: &mut BoardRouterOutputBoard, board: BoardRouterOutputBoard, n_locked: usize| {
+        merged.connections.extend(board.connections.into_iter().skip(n_locked));
+        merged.layers.extend(board.layers);
+        merged.bend_radii.extend(board.bend_radii);
+        merged.failures.extend(board.failures);
+        // The order positions of the board follow those of the boards before
+        let offset = merged.statistics.connections.iter().filter_map(|c| c.order).max().map_or(0, |o| o + 1);
+        merged.statistics.expansions += board.statistics.expansions;
+        merged.statistics.connections.extend(
+            board.statistics.connections.into_iter().filter(|c| c.order.is_some()).map(|c| ConnectionStatistics {
+                order: c.order.map(|o| o + offset),
+                ..c
+            }),
+        );
+    };
+    let mut rerouted = Vec::new();
+    for (cluster, board) in clusters.iter().zip(routed) {
+        match board {
+            Some(board) => merge(&mut merged, board, input.locked_connections.len()),
+            None => rerouted.extend(cluster.connections.iter().map(|&i| input.connections[i].id)),
+        }
+    }
+    let mut complete = true;
+    if !rerouted.is_empty() {
+        // The merged channels are locked, and the connections keep their channel width and spacing
+        let reroute_input = RouteInput {
+            length_match_groups: Vec::from_iter(
+                input
+                    .length_match_groups
+                    .iter()
+                    .filter(|group| group.connections.iter().any(|c_id| rerouted.contains(c_id)))
+                    .cloned(),
+            ),
+            locked_connections: merged.connections.clone(),
+            ..input.clone()
+        };
+        let n_locked = reroute_input.locked_connections.len();
+        match route_board(&reroute_input, None, deadline) {
+            Ok(board) => merge(&mut merged, board, n_locked),
+            Err(BoardRouterOutputError::PartialResult(board)) => {
+                merge(&mut merged, board, n_locked);
+                complete = false;
+            }
+            Err(error) => return Some(Err(error)),
+        }
+    }
+    report_connections(input, &mut merged);
+    Some(if complete {
+        Ok(merged)
+    } else {
+        Err(BoardRouterOutputError::PartialResult(merged))
+    })
+}
+
+// Routes the boards within their windows on a pool of threads, returning the outputs in the order of the inputs, or
+// None if a worker failed
+#[cfg(not(target_family = "wasm"))]
+fn route_concurrently(inputs: Vec<(RouteInput, (Point, Point))>, deadline: Option<f64>) -> Option<Vec<BoardRouterOutput>> {
+    let n_inputs = inputs.len();
+    let n_workers = std::thread::available_parallelism().map_or(1, |n| n.get()).min(n_inputs);
+    let pool = ThreadPool::new(n_workers);
+    let (sender, receiver) = mpsc::channel();
+    for (i, (input, window)) in inputs.into_iter().enumerate() {
+        let sender = sender.clone();
+        pool.execute(move || {
+            let _ = sender.send((i, route_board(&input, Some(window), deadline)));
+        });
+    }
+    drop(sender);
+    let mut outputs = Vec::from_iter(receiver.iter());
+    if outputs.len() != n_inputs {
+        return None;
+    }
+    outputs.sort_by_key(|(i, _)| *i);
+    Some(Vec::from_iter(outputs.into_iter().map(|(_, output)| output)))
+}
+
+// Threads are not available in the browser, so the boards are routed one after the other
+#[cfg(target_family = "wasm")]
+fn route_concurrently(inputs: Vec<(RouteInput, (Point, Point))>, deadline: Option<f64>) -> Option<Vec<BoardRouterOutput>> {
+    Some(Vec::from_iter(inputs.iter().map(|(input, window)| route_board(input, Some(*window), deadline))))
+}
+
+// Channel width overrides, lengths of the connections in length-match groups and, if the channel height and viscosity
+// are given, resistances of all channels of the output connections; lengths and resistances follow the fillet arcs of
+// smoothed channels
+pub(crate) fn report_connections(input: &RouteInput, output: &mut BoardRouterOutputBoard) {
+    output.channel_widths = output
+        .connections
+        .iter()
+        .filter_map(|(c_id, _)| {
+            let input_connection = input.connections.iter().find(|c| c.id == *c_id)?;
+            Some((*c_id, input_connection.channel_width?))
+        })
+        .collect();
+    output.lengths = output
+        .connections
+        .iter()
+        .filter(|(c_id, _)| {
+            input
+                .length_match_groups
+                .iter()
+                .any(|group| group.connections.contains(c_id))
+        })
+        .map(|(c_id, channels)| {
+            let center_lines = output.center_lines(*c_id, channels);
+            (*c_id, center_lines.iter().map(channel_length).sum())
+        })
+        .collect();
+    if let Some((channel_height, viscosity)) = input.channel_height.zip(input.viscosity) {
+        output.resistances = output
+            .connections
+            .iter()
+            .map(|(c_id, channels)| {
+                let width = input.footprint(*c_id).half_width * 2.;
+                (
+                    *c_id,
+                    output
+                        .center_lines(*c_id, channels)
+                        .iter()
+                        .map(|channel| channel_resistance(channel, width, channel_height, viscosity))
+                        .collect(),
+                )
+            })
+            .collect();
+    }
+
+    // Statistics of the channels, with the routing order and search effort the router recorded for the connections
+    let recorded = std::mem::take(&mut output.statistics.connections);
+    let mut statistics = RoutingStatistics {
+        volume: input.channel_height.map(|_| 0.),
+        expansions: output.statistics.expansions,
+        time_ms: output.statistics.time_ms,
+        heuristic: input.heuristic,
+        heuristic_bias: input.heuristic_bias,
+        ..Default::default()
+    };
+    let min_distance = if input.smoothing.is_some_and(|smoothing| smoothing.any_angle) {
+        Heuristic::Euclidean
+    } else {
+        Heuristic::Layout
+    };
+    for (c_id, channels) in output.connections.iter() {
+        let recorded = recorded.iter().find(|c| c.connection == *c_id);
+        let length = output
+            .center_lines(*c_id, channels)
+            .iter()
+            .map(channel_length)
+            .sum::<f64>();
+        let mut bends = BendCounts::default();
+        for channel in channels.iter() {
+            bends.add(channel_bends(channel));
+        }
+        let ends = Vec::from_iter(channels.iter().flat_map(|channel| channel.first().into_iter().chain(channel.last())));
+        let min_length = ends
+            .iter()
+            .flat_map(|a| ends.iter().map(|b| min_distance.distance(input.layout, (a[0] - b[0]).abs(), (a[1] - b[1]).abs())))
+            .fold(0., f64::max);
+        let area = length * input.footprint(*c_id).half_width * 2.;
+        let volume = input.channel_height.map(|channel_height| area * channel_height);
+        statistics.length += length;
+        statistics.min_length += min_length;
+        statistics.bends.add(bends);
+        statistics.area += area;
+        statistics.volume = statistics.volume.zip(volume).map(|(a, b)| a + b);
+        statistics.connections.push(ConnectionStatistics {
+            connection: *c_id,
+            order: recorded.and_then(|c| c.order),
+            length,
+            min_length,
+            bends,
+            area,
+            volume,
+            expansions: recorded.map_or(0, |c| c.expansions),
+            time_ms: recorded.map_or(0., |c| c.time_ms),
+        });
+    }
+    output.statistics = statistics;
+}
+
+fn route_on_grid(input: &RouteInput, window: Option<(Point, Point)>, deadline: Option<f64>) -> BoardRouterOutput {
+    let channel_distance = input.channel_width + input.channel_spacing;
+    let explicit_ports = !input.ports.is_empty();
+    let port_definitions = input.port_definitions();
+    if let Some((c_id, port)) = input.undefined_port(&port_definitions) {
+        return Err(BoardRouterOutputError::UndefinedPort(c_id, port));
+    }
+
+    // Grid ports lie on the cell centers of a grid aligned with the pitch
+    let grid_cell_size = input.cell_size.filter(|&size| size > 0.).unwrap_or(channel_distance);
+    let cells_per_pitch = ((input.pitch / grid_cell_size).floor() as usize).max(1);
+    let mut cell_size = input.pitch / (cells_per_pitch as f64);
+    let half_cell_size = cell_size / 2.;
+    let ComputePortsOutput { ports_x, ports_y } = if explicit_ports {
+        ComputePortsOutput { ports_x: 0, ports_y: 0 }
+    } else {
+        compute_ports(ComputePortsInput {
+            board_width: input.board_width,
+            board_height: input.board_height,
+            pitch: input.pitch,
+            pitch_offset_x: input.pitch_offset_x,
+            pitch_offset_y: input.pitch_offset_y,
+        })
+    };
+    let main_grid_cells_x = ports_x * cells_per_pitch + (1 - cells_per_pitch % 2);
+    let main_grid_cells_y = ports_y * cells_per_pitch + (1 - cells_per_pitch % 2);
+
+    let half_spacing = input.channel_spacing / 2.;
+    let pre_remaining_x = input.pitch_offset_x
+        - ((cells_per_pitch / 2) as f64) * cell_size
+        - half_cell_size
+        - half_spacing;
+    let pre_offset_cells_x = ((pre_remaining_x / cell_size).max(0.)).floor() as usize;
+
+    let pre_remaining_y = input.pitch_offset_y
+        - ((cells_per_pitch / 2) as f64) * cell_size
+        - half_cell_size
+        - half_spacing;
+    let pre_offset_cells_y = ((pre_remaining_y / cell_size).max(0.)).floor() as usize;
+
+    let post_remaining_x = input.pitch_offset_x
+        - ((cells_per_pitch / 2) as f64) * cell_size
+        - half_cell_size
+        - half_spacing;
+    let post_offset_cells_x = ((post_remaining_x / cell_size).max(0.)).floor() as usize;
+
+    let post_remaining_y = input.pitch_offset_y
+        - ((cells_per_pitch / 2) as f64) * cell_size
+        - half_cell_size
+        - half_spacing;
+    let post_offset_cells_y = ((post_remaining_y / cell_size).max(0.)).floor() as usize;
+
+    let mut cells_x = main_grid_cells_x + pre_offset_cells_x + post_offset_cells_x;
+    let mut cells_y = main_grid_cells_y + pre_offset_cells_y + post_offset_cells_y;
+
+    let mut cell_offset_x = input.pitch_offset_x
+        - ((cells_per_pitch / 2) as f64) * cell_size
+        - pre_offset_cells_x as f64 * cell_size;
+    let mut cell_offset_y = input.pitch_offset_y
+        - ((cells_per_pitch / 2) as f64) * cell_size
+        - pre_offset_cells_y as f64 * cell_size;
+
+    // Explicit ports lie anywhere on a grid of cells one channel distance apart, centered on the board
+    if explicit_ports {
+        let cells = |length: f64| ((length - grid_cell_size) / grid_cell_size).floor().max(0.) as usize + 1;
+        cell_size = grid_cell_size;
+        cells_x = cells(input.board_width);
+        cells_y = cells(input.board_height);
+        cell_offset_x = (input.board_width - (cells_x - 1) as f64 * cell_size) / 2.;
+        cell_offset_y = (input.board_height - (cells_y - 1) as f64 * cell_size) / 2.;
+    }
+
+    // Clusters are routed on the cells within their window only
+    let mut window_cells = (0, 0);
+    if let Some(([min_x, min_y], [max_x, max_y])) = window {
+        let cell_range = |from: f64, to: f64, offset: f64, cells: usize| {
+            let first = (((from - offset) / cell_size).ceil().max(0.) as usize).min(cells - 1);
+            let last = (((to - offset) / cell_size).floor().max(0.) as usize).clamp(first, cells - 1);
+            (first, last - first + 1)
+        };
+        let (first_x, window_cells_x) = cell_range(min_x, max_x, cell_offset_x, cells_x);
+        let (first_y, window_cells_y) = cell_range(min_y, max_y, cell_offset_y, cells_y);
+        cell_offset_x += first_x as f64 * cell_size;
+        cell_offset_y += first_y as f64 * cell_size;
+        (cells_x, cells_y) = (window_cells_x, window_cells_y);
+        window_cells = (first_x, first_y);
+    }
+
+    let mut grid = RoutingGrid {
+        cells_x,
+        cells_y,
+        layers: input.layers.unwrap_or(1).max(1),
+        layout: input.layout,
+        via_cost: input.via_cost.unwrap_or(DEFAULT_VIA_COST),
+        bidirectional_search: input.bidirectional_search,
+        search_limits: input.search_limits,
+        region_margin: input.search_limits.region_margin.map(|margin| (margin / cell_size).ceil().max(0.) as usize),
+        expansions_left: Cell::new(input.search_limits.max_total_expansions),
+        deadline,
+        cost_model: input.cost_model,
+        heuristic: input.heuristic,
+        heuristic_bias: input.heuristic_bias,
+        efforts: RefCell::new(HashMap::new()),
+        footprints: None,
+        serpentines: HashMap::new(),
+    };
+
+    let port_definition = |port: &Port| port_definitions.iter().find(|d| d.port == *port);
+    // Cell of a pitch port on the grid of the whole board
+    let board_cell = |port: &Port| {
+        (
+            (cells_per_pitch / 2) + cells_per_pitch * port.0 + pre_offset_cells_x,
+            (cells_per_pitch / 2) + cells_per_pitch * port.1 + pre_offset_cells_y,
+        )
+    };
+    // Cells of the connection ports, which lie within the window
+    let port_cell = |port: &Port| match port_definition(port) {
+        Some(PortDefinition { position: [x, y], .. }) => {
+            let nearest = |p: f64, offset: f64, cells: usize| {
+                (((p - offset) / cell_size).round().max(0.) as usize).min(cells - 1)
+            };
+            (nearest(*x, cell_offset_x, cells_x), nearest(*y, cell_offset_y, cells_y))
+        }
+        None => {
+            let (cell_x, cell_y) = board_cell(port);
+            (
+                cell_x.saturating_sub(window_cells.0).min(cells_x - 1),
+                cell_y.saturating_sub(window_cells.1).min(cells_y - 1),
+            )
+        }
+    };
+    let cell_position = |x: usize, y: usize| -> Point {
+        [cell_offset_x + x as f64 * cell_size, cell_offset_y + y as f64 * cell_size]
+    };
+    // Positions are not clamped to the window, so the keep-outs of ports outside it only block the cells they reach
+    let port_position = |port: &Port| match port_definition(port) {
+        Some(definition) => definition.position,
+        None => {
+            let (cell_x, cell_y) = board_cell(port);
+            [
+                cell_offset_x + (cell_x as f64 - window_cells.0 as f64) * cell_size,
+                cell_offset_y + (cell_y as f64 - window_cells.1 as f64) * cell_size,
+            ]
+        }
+    };
+    let port_radius = |port: &Port| port_definition(port).map_or(input.port_diameter, |d| d.diameter) / 2.;
+
+    let mut nodes = Vec::<GridNode>::with_capacity(grid.layers * cells_x * cells_y);
+
+    // Generate routing nodes/cells
+    for _ in 0..grid.layers {
+        for _ in 0..cells_x {
+            for _ in 0..cells_y {
+                nodes.push(GridNode {
+                    connection: None,
+                    blocked: false,
+                    channel: None,
+                });
+            }
+        }
+    }
 
-            let node_position = (
-                cell_offset_x + cell_x as f64 * cell_size,
-                cell_offset_y + cell_y as f64 * cell_size,
-            );
+    let input_connections = input
+        .connections
+        .to_vec()
+        .into_iter()
+        .collect::<Vec<RouteInputConnection>>();
+
+    // Reserve cells at and around used ports for the corresponding connection only (prevent other connections from crossing foreign ports)
+    for input_connection in input_connections.iter() {
+        let RouteInputConnection { id: c_id, ports, .. } = input_connection;
+        for port in ports {
+            let (cell_x, cell_y) = port_cell(port);
+            let [position_x, position_y] = port_position(port);
+            let port_influence_radius =
+                port_radius(port) + input.channel_spacing + input.channel_width / 2.;
+            let box_size = (port_influence_radius / cell_size).ceil();
 
             for box_x in usize::saturating_sub(cell_x, box_size as usize)
                 ..(cell_x + 1 + box_size as usize).clamp(0, cells_x)
@@ -341,18 +3322,21 @@ pub fn route(input: &RouteInput) -> BoardRouterOutput {
                         cell_offset_y + box_y as f64 * cell_size,
                     );
                     let distance = f64::hypot(
-                        box_node_position.0 - node_position.0,
-                        box_node_position.1 - node_position.1,
+                        box_node_position.0 - position_x,
+                        box_node_position.1 - position_y,
                     );
                     if distance < port_influence_radius {
-                        let node = &mut nodes[box_x * cells_y + box_y];
-                        // If the cell is already reserved for another connection (e.g., ports close to each other), no connection can be routed through this cell
-                        if node.connection.is_none()
-                            || (node.connection.is_some() && node.connection.unwrap() == *c_id)
-                        {
-                            node.connection = Some(*c_id);
-                        } else {
-                            nodes[box_x * cells_y + box_y].blocked = true;
+                        // Ports are through-holes, so they are reserved on all layers
+                        for layer in 0..grid.layers {
+                            let node = &mut nodes[grid.cell_id(layer, box_x, box_y)];
+                            // If the cell is already reserved for another connection (e.g., ports close to each other), no connection can be routed through this cell
+                            if node.connection.is_none()
+                                || (node.connection.is_some() && node.connection.unwrap() == *c_id)
+                            {
+                                node.connection = Some(*c_id);
+                            } else {
+                                node.blocked = true;
+                            }
                         }
                     }
                 }
@@ -360,71 +3344,234 @@ pub fn route(input: &RouteInput) -> BoardRouterOutput {
         }
     }
 
-    let mut join_nodes = HashMap::<ConnectionID, (usize, usize)>::new();
+    // Cells (x, y) closer than the clearance to a geometry with the given bounding box and distance function
+    let cells_near = |([min_x, min_y], [max_x, max_y]): (Point, Point),
+                      clearance: f64,
+                      distance: &dyn Fn(Point) -> f64| {
+        let cell_range = |from: f64, to: f64, offset: f64, cells: usize| {
+            let first = ((from - clearance - offset) / cell_size).floor().max(0.) as usize;
+            let last = ((to + clearance - offset) / cell_size).ceil();
+            // Geometry before the first cell (e.g., outside the window of a cluster) is skipped
+            if last < 0. {
+                return 0..0;
+            }
+            first..(last as usize + 1).min(cells)
+        };
+        let mut cells = Vec::new();
+        for x in cell_range(min_x, max_x, cell_offset_x, cells_x) {
+            for y in cell_range(min_y, max_y, cell_offset_y, cells_y) {
+                if distance(cell_position(x, y)) < clearance {
+                    cells.push((x, y));
+                }
+            }
+        }
+        cells
+    };
+
+    let default_footprint = Footprint {
+        half_width: input.channel_width / 2.,
+        spacing: input.channel_spacing,
+    };
+    let footprint = |c_id: ConnectionID| input.footprint(c_id);
+
+    // Keep-out zones and blocked ports as well as ports of other connections
+    let port_keep_out = |port: &Port| KeepOut::Circle {
+        center: port_position(port),
+        radius: port_radius(port),
+    };
+    let keep_outs = Vec::from_iter(
+        input
+            .keep_outs
+            .iter()
+            .cloned()
+            .chain(input.blocked_ports.iter().map(port_keep_out)),
+    );
+
+    // Block all cells whose channel would overlap a keep-out zone or a blocked port
+    let keep_out_clearance = input.channel_spacing + input.channel_width / 2.;
+    for keep_out in keep_outs.iter() {
+        for (x, y) in cells_near(keep_out.bounding_box(), keep_out_clearance, &|p| keep_out.distance(p)) {
+            for layer in 0..grid.layers {
+                nodes[grid.cell_id(layer, x, y)].blocked = true;
+            }
+        }
+    }
+
+    // Segments of the locked channels with their footprint
+    let locked_segments = Vec::from_iter(input.locked_connections.iter().flat_map(|(c_id, channels)| {
+        channels.iter().flat_map(move |channel| {
+            channel
+                .windows(2)
+                .map(move |segment| (footprint(*c_id), segment[0], segment[1]))
+        })
+    }));
+    let segment_bounding_box = |[ax, ay]: Point, [bx, by]: Point| -> (Point, Point) {
+        ([ax.min(bx), ay.min(by)], [ax.max(bx), ay.max(by)])
+    };
+
+    // Block the cells of locked channels, leaving space for channels in the neighboring cells
+    for (locked_footprint, a, b) in locked_segments.iter() {
+        let locked_clearance = locked_footprint.clearance(&default_footprint);
+        for (x, y) in cells_near(segment_bounding_box(*a, *b), locked_clearance, &|p| segment_distance(p, *a, *b)) {
+            for layer in 0..grid.layers {
+                nodes[grid.cell_id(layer, x, y)].blocked = true;
+            }
+        }
+    }
+
+    // Connections with a width or spacing override keep their own clearance to channels, and connections with a larger
+    // footprint also to foreign ports, keep-outs and locked channels; on a grid finer than the channel distance, all
+    // connections keep their clearance
+    if cell_size < channel_distance * (1. - 1e-9)
+        || input_connections
+            .iter()
+            .any(|c| c.channel_width.is_some() || c.channel_spacing.is_some())
+    {
+        let overrides = HashMap::<ConnectionID, Footprint>::from_iter(
+            input_connections
+                .iter()
+                .filter(|c| c.channel_width.is_some() || c.channel_spacing.is_some())
+                .map(|c| (c.id, footprint(c.id))),
+        );
+        let max_half_width = overrides
+            .values()
+            .map(|f| f.half_width)
+            .fold(default_footprint.half_width, f64::max);
+        let max_spacing = overrides
+            .values()
+            .map(|f| f.spacing)
+            .fold(default_footprint.spacing, f64::max);
+
+        let mut obstacles = HashMap::<ConnectionID, HashSet<usize>>::new();
+        for input_connection in input_connections.iter() {
+            let c_footprint = footprint(input_connection.id);
+            let clearance = c_footprint.half_width + c_footprint.spacing;
+            if clearance <= keep_out_clearance {
+                continue;
+            }
+            let foreign_ports = input_connections
+                .iter()
+                .filter(|c| c.id != input_connection.id)
+                .flat_map(|c| c.ports.iter().map(port_keep_out));
+            let mut cells = HashSet::new();
+            for keep_out in keep_outs.iter().cloned().chain(foreign_ports) {
+                cells.extend(cells_near(keep_out.bounding_box(), clearance, &|p| keep_out.distance(p)));
+            }
+            for (locked_footprint, a, b) in locked_segments.iter() {
+                let locked_clearance = locked_footprint.clearance(&c_footprint);
+                cells.extend(cells_near(segment_bounding_box(*a, *b), locked_clearance, &|p| {
+                    segment_distance(p, *a, *b)
+                }));
+            }
+            obstacles.insert(
+                input_connection.id,
+                cells.into_iter().map(|(x, y)| grid.cell_id(0, x, y)).collect(),
+            );
+        }
+
+        grid.footprints = Some(Footprints {
+            cell_size,
+            default: default_footprint,
+            overrides,
+            max_reach: 2. * max_half_width + max_spacing,
+            obstacles,
+        });
+    }
+    let is_locked = |c_id: &ConnectionID| input.locked_connections.iter().any(|(id, _)| id == c_id);
+
+    let mut join_nodes = JoinNodes::new();
+    // Further join cells of stars without a branch_port, tried if their branches fail
+    // (scored when the first branch fails, unless the join cells are optimised)
+    let mut join_alternatives = HashMap::<ConnectionID, Vec<(usize, usize)>>::new();
+
+    // Bounding boxes of the port cells of all connections
+    let port_boxes = Vec::from_iter(input_connections.iter().map(|c| {
+        let cells = Vec::from_iter(c.ports.iter().map(port_cell));
+        let min = cells.iter().fold((usize::MAX, usize::MAX), |m, p| (m.0.min(p.0), m.1.min(p.1)));
+        let max = cells.iter().fold((0, 0), |m, p| (m.0.max(p.0), m.1.max(p.1)));
+        (c.id, (min, max))
+    }));
 
     for input_connection in input_connections.iter() {
-        let RouteInputConnection { id: c_id, ports, branch_port } = input_connection;
+        let RouteInputConnection { id: c_id, ports, branch_port, .. } = input_connection;
+        if is_locked(c_id) {
+            continue;
+        }
         if ports.len() > 2 {
             // there are more than 2 nodes connected, so we connect them in a star like structure
             // define the center node
             let center_node = if branch_port.is_some() {
                 Some(port_cell(&branch_port.unwrap()))
             } else {
-                compute_extra_node(&nodes, ports, cells_x, cells_y, port_cell)
+                match input.multi_port_routing {
+                    MultiPortRouting::Star => compute_extra_node(&nodes, ports, cells_x, cells_y, port_cell),
+                    MultiPortRouting::OptimizedStar => {
+                        let other_connections = Vec::from_iter(
+                            port_boxes.iter().filter(|(id, _)| id != c_id).map(|(_, b)| *b),
+                        );
+                        let mut candidates =
+                            join_cell_candidates(&nodes, &grid, *c_id, ports, &other_connections, port_cell);
+                        // The free cell near the centroid is the first alternative
+                        let centroid = compute_extra_node(&nodes, ports, cells_x, cells_y, port_cell);
+                        let best = (!candidates.is_empty()).then(|| candidates.remove(0));
+                        candidates.retain(|&cell| Some(cell) != centroid);
+                        candidates.splice(0..0, centroid.filter(|&cell| Some(cell) != best));
+                        join_alternatives.insert(*c_id, candidates);
+                        best
+                    }
+                    MultiPortRouting::SteinerTree => Some(centroid_port_cell(ports, port_cell)),
+                }
             };
 
             if let Some(node) = center_node {
-                let id = node.0 * cells_y + node.1;
-                nodes[id].connection = Some(*c_id);
+                for layer in 0..grid.layers {
+                    nodes[grid.cell_id(layer, node.0, node.1)].connection = Some(*c_id);
+                }
                 join_nodes.insert(*c_id, node);
             }
         } else {
             if branch_port.is_some() {
                 let node = port_cell(&branch_port.unwrap());
-                let id = node.0 * cells_y + node.1;
-                nodes[id].connection = Some(*c_id);
+                for layer in 0..grid.layers {
+                    nodes[grid.cell_id(layer, node.0, node.1)].connection = Some(*c_id);
+                }
                 join_nodes.insert(*c_id, node);
             }
         }
     }
 
-    enum RoutingConnection {
-        PortToPort(PortToPort),
-        StarBranch(StarBranch),
-    }
-
-    struct PortToPort {
-        connection: ConnectionID,
-        from_cell: (usize, usize),
-        to_cell: (usize, usize),
-    }
-
-    // 2-port connections with a branch_port are also regarded as two StarBranch'es
-    struct StarBranch {
-        connection: ConnectionID,
-        from_cell: Option<(usize, usize)>,
-        to_cell: (usize, usize),
-        num_branches: usize,
-    }
+    // Branch of a multi-port connection from its join node to the port (star) or from the port into the tree growing
+    // from the join node (Steiner tree)
+    let branch = |c_id: ConnectionID, port: &Port, num_branches: usize| match input.multi_port_routing {
+        MultiPortRouting::Star | MultiPortRouting::OptimizedStar => Some(RoutingConnection::StarBranch(StarBranch {
+            connection: c_id,
+            from_cell: join_nodes.get(&c_id).copied(),
+            to_cell: port_cell(port),
+            num_branches,
+        })),
+        MultiPortRouting::SteinerTree => {
+            let root_cell = *join_nodes.get(&c_id)?;
+            (port_cell(port) != root_cell).then(|| {
+                RoutingConnection::SteinerBranch(SteinerBranch {
+                    connection: c_id,
+                    from_cell: port_cell(port),
+                    root_cell,
+                    num_branches,
+                })
+            })
+        }
+    };
 
     let mut routing_connections = Vec::new();
     for input_connection in input_connections.iter() {
         let RouteInputConnection { id: c_id, ports, .. } = input_connection;
+        if is_locked(c_id) {
+            continue;
+        }
         if ports.len() == 2 {
             if join_nodes.contains_key(c_id) {
-                routing_connections.push(RoutingConnection::StarBranch(StarBranch {
-                    connection: *c_id,
-                    from_cell: join_nodes.get(&c_id).copied(),
-                    to_cell: port_cell(&ports[0]),
-                    num_branches: ports.len(),
-                }));
-
-                routing_connections.push(RoutingConnection::StarBranch(StarBranch {
-                    connection: *c_id,
-                    from_cell: join_nodes.get(&c_id).copied(),
-                    to_cell: port_cell(&ports[1]),
-                    num_branches: ports.len(),
-                }));
+                routing_connections.extend(branch(*c_id, &ports[0], ports.len()));
+                routing_connections.extend(branch(*c_id, &ports[1], ports.len()));
             } else {
                 routing_connections.push(RoutingConnection::PortToPort(PortToPort {
                     connection: *c_id,
@@ -434,12 +3581,7 @@ pub fn route(input: &RouteInput) -> BoardRouterOutput {
             }
         } else if ports.len() > 2 {
             for port in ports.iter() {
-                routing_connections.push(RoutingConnection::StarBranch(StarBranch {
-                    connection: *c_id,
-                    from_cell: join_nodes.get(&c_id).copied(),
-                    to_cell: port_cell(port),
-                    num_branches: ports.len(),
-                }));
+                routing_connections.extend(branch(*c_id, port, ports.len()));
             }
         } else {
             panic!()
@@ -447,357 +3589,568 @@ pub fn route(input: &RouteInput) -> BoardRouterOutput {
     }
     let n_routing_connections = routing_connections.len();
 
-    // Sort connections by multi-connections and direct distance, ascending
-    fn cmp_connections(a: &RoutingConnection, b: &RoutingConnection) -> Ordering {
-        match (a, b) {
-            (
-                RoutingConnection::PortToPort(a_port_to_port),
-                RoutingConnection::PortToPort(b_port_to_port),
-            ) => {
-                let adx = usize::abs_diff(a_port_to_port.from_cell.0, a_port_to_port.to_cell.0);
-                let ady = usize::abs_diff(a_port_to_port.from_cell.1, a_port_to_port.to_cell.1);
-                let bdx = usize::abs_diff(b_port_to_port.from_cell.0, b_port_to_port.to_cell.0);
-                let bdy = usize::abs_diff(b_port_to_port.from_cell.1, b_port_to_port.to_cell.1);
+    // Serpentines go into the first routing connection of their connection
+    for input_connection in input_connections.iter() {
+        let Some(serpentine) = &input_connection.serpentine else {
+            continue;
+        };
+        if let Some(i) = routing_connections
+            .iter()
+            .position(|r| r.connection() == input_connection.id)
+        {
+            grid.serpentines.insert(
+                i,
+                SerpentineCells {
+                    height: ((serpentine.amplitude / cell_size).round() as usize).max(1),
+                    turns: serpentine.turns,
+                    segment: serpentine.segment,
+                },
+            );
+        }
+    }
 
-                let al = f64::hypot(adx as f64, ady as f64);
-                let bl = f64::hypot(bdx as f64, bdy as f64);
-                return f64::partial_cmp(&al, &bl).unwrap();
-            }
-            (RoutingConnection::PortToPort(_), RoutingConnection::StarBranch(_)) => {
-                return Ordering::Greater
+    if routing_connections.is_empty() && input.locked_connections.is_empty() {
+        return Err(BoardRouterOutputError::NoInputConnections);
+    }
+
+    // Moves join nodes of stars to other cells, on the grid and in their branches (all old cells are released first, so
+    // the join nodes may swap cells)
+    let move_join_nodes = |nodes: &mut [GridNode],
+                           routing_connections: &mut [RoutingConnection],
+                           join_nodes: &mut JoinNodes,
+                           moves: &[(ConnectionID, (usize, usize))]| {
+        for (c_id, _) in moves {
+            if let Some(&(x, y)) = join_nodes.get(c_id) {
+                for layer in 0..grid.layers {
+                    nodes[grid.cell_id(layer, x, y)].connection = None;
+                }
             }
-            (RoutingConnection::StarBranch(_), RoutingConnection::PortToPort(_)) => {
-                return Ordering::Less
+        }
+        for &(c_id, (x, y)) in moves {
+            join_nodes.insert(c_id, (x, y));
+            for layer in 0..grid.layers {
+                nodes[grid.cell_id(layer, x, y)].connection = Some(c_id);
             }
-            (
-                RoutingConnection::StarBranch(a_star_branch),
-                RoutingConnection::StarBranch(b_star_branch),
-            ) => {
-                let cmp_num_branches =
-                    usize::cmp(&a_star_branch.num_branches, &b_star_branch.num_branches);
-                match cmp_num_branches {
-                    Ordering::Equal => {
-                        if a_star_branch.from_cell.is_none() || b_star_branch.from_cell.is_none() {
-                            return Ordering::Equal; // Don't care
-                        }
-                        let adx = usize::abs_diff(
-                            a_star_branch.from_cell.unwrap().0,
-                            a_star_branch.to_cell.0,
-                        );
-                        let ady = usize::abs_diff(
-                            a_star_branch.from_cell.unwrap().1,
-                            a_star_branch.to_cell.1,
-                        );
-                        let bdx = usize::abs_diff(
-                            b_star_branch.from_cell.unwrap().0,
-                            b_star_branch.to_cell.0,
-                        );
-                        let bdy = usize::abs_diff(
-                            b_star_branch.from_cell.unwrap().1,
-                            b_star_branch.to_cell.1,
-                        );
-
-                        let al = f64::hypot(adx as f64, ady as f64);
-                        let bl = f64::hypot(bdx as f64, bdy as f64);
-                        return f64::partial_cmp(&al, &bl).unwrap();
+            for routing_connection in routing_connections.iter_mut() {
+                if let RoutingConnection::StarBranch(star_branch) = routing_connection {
+                    if star_branch.connection == c_id {
+                        star_branch.from_cell = Some((x, y));
                     }
-                    o => return o,
                 }
             }
         }
-    }
-
-    routing_connections.sort_by(&cmp_connections);
-    let mut output_connections = Vec::<BoardRouterOutputConnection>::new();
-
-    if routing_connections.len() == 0 {
-        return Err(BoardRouterOutputError::NoInputConnections);
-    }
-
-    let mut succesful_routings = 0;
+    };
 
-    // Route connections sequentially
-    for routing_connection in routing_connections {
-        let (c_id, ax, ay, bx, by);
+    // Route in every candidate order and keep the best result; if branches of stars without a branch_port fail, these
+    // stars are routed again with their next join cell
+    let mut best: Option<(Routing, JoinNodes)> = None;
+    loop {
+        let mut attempt: Option<Routing> = None;
+        for order in connection_orders(&input.ordering, &input.connections, &routing_connections) {
+            let routing = route_connections(
+                &nodes,
+                &grid,
+                &routing_connections,
+                order,
+                input.rip_up_reroute,
+            );
+            if attempt
+                .as_ref()
+                .is_none_or(|attempt| routing.is_better_than(attempt, &grid))
+            {
+                attempt = Some(routing);
+            }
+        }
+        let attempt = attempt.unwrap();
+        let failed = Vec::from_iter(
+            (0..attempt.paths.len())
+                .filter(|&i| attempt.paths[i].is_none())
+                .map(|i| routing_connections[i].connection()),
+        );
+        if best
+            .as_ref()
+            .is_none_or(|(best, _)| attempt.is_better_than(best, &grid))
+        {
+            best = Some((attempt, join_nodes.clone()));
+        }
 
-        match &routing_connection {
-            RoutingConnection::PortToPort(port_to_port) => {
-                c_id = port_to_port.connection;
-                (ax, ay) = port_to_port.from_cell;
-                (bx, by) = port_to_port.to_cell;
-            }
-            RoutingConnection::StarBranch(star_branch) => {
-                c_id = star_branch.connection;
-                if star_branch.from_cell.is_some() {
-                    (ax, ay) = star_branch.from_cell.unwrap();
-                    (bx, by) = star_branch.to_cell;
-                } else {
-                    continue;
+        let mut moved = false;
+        for input_connection in input_connections.iter() {
+            let RouteInputConnection { id: c_id, ports, branch_port, .. } = input_connection;
+            let c_id = *c_id;
+            if !failed.contains(&c_id)
+                || ports.len() <= 2
+                || branch_port.is_some()
+                || matches!(input.multi_port_routing, MultiPortRouting::SteinerTree)
+                || !join_nodes.contains_key(&c_id)
+            {
+                continue;
+            }
+            let alternatives = join_alternatives.entry(c_id).or_insert_with(|| {
+                let other_connections =
+                    Vec::from_iter(port_boxes.iter().filter(|(id, _)| *id != c_id).map(|(_, b)| *b));
+                join_cell_candidates(&nodes, &grid, c_id, ports, &other_connections, port_cell)
+            });
+            // Join nodes chosen for other connections may have taken the surroundings of an alternative
+            while !alternatives.is_empty() {
+                let (x, y) = alternatives.remove(0);
+                let free = join_nodes.get(&c_id) != Some(&(x, y))
+                    && neighbors((x, y), cells_x, cells_y)
+                        .iter()
+                        .map(|n| n.0)
+                        .chain([(x, y)])
+                        .all(|(nx, ny)| {
+                            let node = &nodes[grid.cell_id(0, nx, ny)];
+                            !node.blocked
+                                && (node.connection.is_none() || join_nodes.get(&c_id) == Some(&(nx, ny)))
+                        });
+                if free {
+                    move_join_nodes(&mut nodes, &mut routing_connections, &mut join_nodes, &[(c_id, (x, y))]);
+                    moved = true;
+                    break;
                 }
             }
         }
+        if !moved {
+            break;
+        }
+    }
+    let (Routing { order, mut paths }, best_join_nodes) = best.unwrap();
+    let moves = Vec::from_iter(
+        best_join_nodes
+            .into_iter()
+            .filter(|(c_id, cell)| join_nodes.get(c_id) != Some(cell)),
+    );
+    move_join_nodes(&mut nodes, &mut routing_connections, &mut join_nodes, &moves);
 
-        let start_node_id = ax * cells_y + ay;
-        let target_node_id = bx * cells_y + by;
-        let (target_node_ix, target_node_iy) = (bx, by);
+    // Diagnose the failed connections and branches on the board with all routed channels
+    let mut failures = Vec::<RoutingFailure>::new();
+    if paths.iter().any(|path| path.is_none()) {
+        let routed_nodes = with_paths(&nodes, &routing_connections, &paths);
+        // The connection owning a cell that is not available to the given connection
+        let owner = |c_id: ConnectionID, (x, y): (usize, usize)| {
+            let node = &routed_nodes[grid.cell_id(0, x, y)];
+            node.connection
+                .filter(|&c| c != c_id)
+                .or(node.channel.filter(|&c| c != c_id))
+        };
+        let grid = &grid;
+        let layer_cells = |(x, y): (usize, usize)| (0..grid.layers).map(move |layer| grid.cell_id(layer, x, y));
 
-        // Setup successor cell functions for rectilinear, octilinear, etc; Since they capture some variables (e.g., cells_x/y), they need to be defined here.
-        let rectilinear = |a: &AStarNode<(usize, Option<usize>)>| -> Vec<((usize, Option<usize>), f64)> {
-            let current = a.node.0;
-            let (nix, niy) = (current / cells_y, current % cells_y);
-            let options = match a.node.1 {
-                Some(previous) => {
-                    let (pix, piy) = (previous / cells_y, previous % cells_y);
-                    if pix < nix {
-                        Vec::from([
-                            up_if_exists((nix, niy)),
-                            down_if_exists((nix, niy), cells_y),
-                            right_if_exists((nix, niy), cells_x),
-                        ])
-                    } else if pix > nix {
-                        Vec::from([
-                            up_if_exists((nix, niy)),
-                            down_if_exists((nix, niy), cells_y),
-                            left_if_exists((nix, niy)),
-                        ])
-                    } else if piy < niy {
-                        Vec::from([
-                            down_if_exists((nix, niy), cells_y),
-                            left_if_exists((nix, niy)),
-                            right_if_exists((nix, niy), cells_x),
-                        ])
-                    } else if piy > niy {
-                        Vec::from([
-                            up_if_exists((nix, niy)),
-                            left_if_exists((nix, niy)),
-                            right_if_exists((nix, niy), cells_x),
-                        ])
-                    } else {
-                        panic!()
-                    }
-                }
-                None => Vec::from([
-                    down_if_exists((nix, niy), cells_y),
-                    up_if_exists((nix, niy)),
-                    left_if_exists((nix, niy)),
-                    right_if_exists((nix, niy), cells_x),
-                ]),
+        for (i, routing_connection) in routing_connections.iter().enumerate() {
+            if paths[i].is_some() {
+                continue;
+            }
+            let c_id = routing_connection.connection();
+            let branch_cell = match routing_connection {
+                RoutingConnection::PortToPort(_) => None,
+                RoutingConnection::StarBranch(star_branch) => Some(star_branch.to_cell),
+                RoutingConnection::SteinerBranch(steiner_branch) => Some(steiner_branch.from_cell),
+            };
+            let port = branch_cell.and_then(|cell| {
+                let input_connection = input_connections.iter().find(|c| c.id == c_id)?;
+                input_connection.ports.iter().copied().find(|port| port_cell(port) == cell)
+            });
+            let mut failure = RoutingFailure {
+                connection: c_id,
+                port,
+                reason: FailureReason::NoJoinNode,
+                explored: Vec::new(),
             };
 
-            // Check whether the cell can be reached (is unblocked)
-            options
-                .into_iter()
-                .filter_map(|o| -> Option<((usize, Option<usize>), f64)> {
-                    match o {
-                        Some(((x, y), c)) => {
-                            let cell_id = x * cells_y + y;
-                            let node = &nodes[cell_id];
-                            if node.blocked {
-                                return None;
-                            }
-                            if let Some(c) = node.connection {
-                                if c != c_id {
-                                    return None;
+            let Some((from_cell, to_cell)) = routing_connection.cells() else {
+                failures.push(failure);
+                continue;
+            };
+            let tree = steiner_tree(&routed_nodes, grid, routing_connection);
+            let search = PathSearch {
+                nodes: &routed_nodes,
+                grid,
+                connection: c_id,
+                history: None,
+                congestion_cost: None,
+                targets: tree.as_ref(),
+            };
+            if !layer_cells(from_cell).any(|cell_id| search.can_start_at(cell_id)) {
+                failure.reason = FailureReason::StartBlocked(owner(c_id, from_cell));
+                failures.push(failure);
+                continue;
+            }
+            if tree.is_none() && layer_cells(to_cell).all(|cell_id| search.cell_cost(cell_id).is_none()) {
+                failure.reason = FailureReason::TargetBlocked(owner(c_id, to_cell));
+                failures.push(failure);
+                continue;
+            }
+
+            let (path, explored, limit_reached) = explore_path(&search, from_cell, to_cell, true);
+            let explored_cells = HashSet::<usize>::from_iter(explored.iter().copied());
+            // Foreign channels next to the explored cells (or close enough to keep the connection out with their
+            // clearance) enclose the search
+            let reach = grid
+                .footprints
+                .as_ref()
+                .map_or(0, |footprints| (footprints.max_reach / footprints.cell_size).ceil() as usize);
+            let mut enclosing = Vec::<ConnectionID>::new();
+            for &cell_id in explored.iter() {
+                let (layer, x, y) = grid.cell(cell_id);
+                for (neighbor, _) in neighbors((x, y), grid.cells_x, grid.cells_y) {
+                    if explored_cells.contains(&grid.cell_id(layer, neighbor.0, neighbor.1)) {
+                        continue;
+                    }
+                    for nx in neighbor.0.saturating_sub(reach)..(neighbor.0 + reach + 1).min(grid.cells_x) {
+                        for ny in neighbor.1.saturating_sub(reach)..(neighbor.1 + reach + 1).min(grid.cells_y) {
+                            if let Some(c) = routed_nodes[grid.cell_id(layer, nx, ny)].channel {
+                                if c != c_id && !enclosing.contains(&c) {
+                                    enclosing.push(c);
                                 }
                             }
-                            Some(((cell_id, Some(current)), c))
                         }
-                        _ => None,
                     }
-                })
-                .collect()
-        };
+                }
+            }
+            enclosing.sort();
 
-        let octilinear = |a: &AStarNode<(usize, Option<usize>)>| -> Vec<((usize, Option<usize>), f64)> {
-            let current = a.node.0;
-            let (nix, niy) = (current / cells_y, current % cells_y);
-            let options = match a.node.1 {
-                Some(previous) => {
-                    let (pix, piy) = (previous / cells_y, previous % cells_y);
-                    if pix < nix {
-                        if piy < niy {
-                            Vec::from([
-                                down_if_exists((nix, niy), cells_y),
-                                right_down_if_exists((nix, niy), cells_x, cells_y),
-                                right_if_exists((nix, niy), cells_x),
-                            ])
-                        } else if piy > niy {
-                            Vec::from([
-                                up_if_exists((nix, niy)),
-                                right_up_if_exists((nix, niy), cells_x),
-                                right_if_exists((nix, niy), cells_x),
-                            ])
-                        } else {
-                            Vec::from([
-                                right_up_if_exists((nix, niy), cells_x),
-                                right_down_if_exists((nix, niy), cells_x, cells_y),
-                                right_if_exists((nix, niy), cells_x),
-                            ])
-                        }
-                    } else if pix > nix {
-                        if piy < niy {
-                            Vec::from([
-                                down_if_exists((nix, niy), cells_y),
-                                left_down_if_exists((nix, niy), cells_y),
-                                left_if_exists((nix, niy)),
-                            ])
-                        } else if piy > niy {
-                            Vec::from([
-                                up_if_exists((nix, niy)),
-                                left_up_if_exists((nix, niy)),
-                                left_if_exists((nix, niy)),
-                            ])
-                        } else {
-                            Vec::from([
-                                left_up_if_exists((nix, niy)),
-                                left_down_if_exists((nix, niy), cells_y),
-                                left_if_exists((nix, niy)),
-                            ])
-                        }
-                    } else if piy < niy {
-                        Vec::from([
-                            down_if_exists((nix, niy), cells_y),
-                            left_down_if_exists((nix, niy), cells_y),
-                            right_down_if_exists((nix, niy), cells_x, cells_y),
-                        ])
-                    } else if piy > niy {
-                        Vec::from([
-                            up_if_exists((nix, niy)),
-                            left_up_if_exists((nix, niy)),
-                            right_up_if_exists((nix, niy), cells_x),
-                        ])
-                    } else {
-                        panic!()
-                    }
+            // Once the total budget is spent, a connection with a path on the routed board failed at the budget
+            let budget_spent = grid.expansions_left.get() == Some(0);
+            failure.reason = if path.is_some() {
+                if budget_spent {
+                    FailureReason::SearchLimitReached
+                } else if grid.serpentines.contains_key(&i) {
+                    FailureReason::NoSerpentineSpace
+                } else {
+                    FailureReason::BlockedWhenRouted
                 }
-                None => Vec::from([
-                    down_if_exists((nix, niy), cells_y),
-                    left_down_if_exists((nix, niy), cells_y),
-                    right_down_if_exists((nix, niy), cells_x, cells_y),
-                    up_if_exists((nix, niy)),
-                    left_up_if_exists((nix, niy)),
-                    right_up_if_exists((nix, niy), cells_x),
-                    left_if_exists((nix, niy)),
-                    right_if_exists((nix, niy), cells_x),
-                ]),
+            } else if limit_reached {
+                FailureReason::SearchLimitReached
+            } else if !enclosing.is_empty() {
+                FailureReason::Enclosed(enclosing)
+            } else {
+                FailureReason::EmptySearchSpace
             };
+            // Cells explored on several layers are reported once
+            let mut explored_positions = HashSet::<(usize, usize)>::new();
+            for &cell_id in explored.iter() {
+                let (_, x, y) = grid.cell(cell_id);
+                if explored_positions.insert((x, y)) {
+                    failure.explored.push(cell_position(x, y));
+                }
+            }
+            failures.push(failure);
+        }
+    }
 
-            // Check whether the cell can be reached (is unblocked, and for diagonal connections, diagonal neighbors are unblocked)
-            options
-                .into_iter()
-                .filter_map(|o| -> Option<((usize, Option<usize>), f64)> {
-                    match o {
-                        Some(((x, y), c)) => {
-                            let cell_id = x * cells_y + y;
-                            let node = &nodes[cell_id];
-                            if node.blocked {
-                                return None;
-                            }
-                            if let Some(c) = node.connection {
-                                if c != c_id {
-                                    return None;
-                                }
-                            }
-                            if nix != x && niy != y {
-                                let block_a_id = nix * cells_y + y;
-                                let node_a = &nodes[block_a_id];
-                                if node_a.blocked {
-                                    return None;
-                                }
-                                if let Some(c) = node_a.connection {
-                                    if c != c_id {
-                                        return None;
-                                    }
-                                }
-
-                                let block_b_id = x * cells_y + niy;
-                                let node_b = &nodes[block_b_id];
-                                if node_b.blocked {
-                                    return None;
-                                }
-                                if let Some(c) = node_b.connection {
-                                    if c != c_id {
-                                        return None;
-                                    }
-                                }
-                            }
-                            Some(((cell_id, Some(current)), c))
-                        }
-                        _ => None,
-                    }
-                })
-                .collect()
-        };
+    let hydraulics = input.channel_height.zip(input.viscosity);
+    let target_resistances = Vec::from_iter(
+        input_connections
+            .iter()
+            .filter_map(|c| Some((c.id, c.target_resistance?)))
+            .filter(|_| hydraulics.is_some()),
+    );
 
-        // Set successor function depending on input layout
-        let successors: &dyn Fn(&AStarNode<(usize, Option<usize>)>) -> Vec<((usize, Option<usize>), f64)> = match input.layout {
-            Layout::Rectilinear => &rectilinear,
-            Layout::Octilinear => &octilinear,
-        };
+    // Lengthen the shorter connections of each length-match group and the connections with a target resistance with
+    // meanders
+    if !input.length_match_groups.is_empty() || !target_resistances.is_empty() {
+        let mut routed_nodes = with_paths(&nodes, &routing_connections, &paths);
+        for group in input.length_match_groups.iter() {
+            let connection_paths = |paths: &RoutedPaths, c_id: ConnectionID| {
+                Vec::from_iter((0..paths.len()).filter(|&i| {
+                    routing_connections[i].connection() == c_id && paths[i].is_some()
+                }))
+            };
+            let connection_length = |paths: &RoutedPaths, c_id: ConnectionID| {
+                connection_paths(paths, c_id)
+                    .iter()
+                    .map(|&i| path_length(&grid, paths[i].as_ref().unwrap()))
+                    .sum::<f64>()
+            };
+            let target_length = group
+                .connections
+                .iter()
+                .map(|&c_id| connection_length(&paths, c_id))
+                .fold(0., f64::max);
+            for &c_id in group.connections.iter() {
+                let mut missing = target_length - connection_length(&paths, c_id);
+                let member_paths = connection_paths(&paths, c_id);
+                for &i in member_paths.iter() {
+                    let fixed = HashSet::<usize>::from_iter(
+                        member_paths
+                            .iter()
+                            .copied()
+                            .filter(|&j| j != i)
+                            .flat_map(|j| paths[j].iter().flatten().copied()),
+                    );
+                    missing -= Detours::new(&mut routed_nodes, &grid, c_id, &fixed).add_meanders(
+                        paths[i].as_mut().unwrap(),
+                        MeanderTarget {
+                            length: missing,
+                            tolerance: group.tolerance / cell_size,
+                            style: group.style.unwrap_or(input.layout),
+                        },
+                    );
+                }
+            }
+        }
+        for &(c_id, target_resistance) in target_resistances.iter() {
+            let (channel_height, viscosity) = hydraulics.unwrap();
+            let width = footprint(c_id).half_width * 2.;
+            let cell_resistance =
+                rectangular_channel_resistance(cell_size, width, channel_height, viscosity);
+            for i in 0..paths.len() {
+                if routing_connections[i].connection() != c_id {
+                    continue;
+                }
+                let fixed = HashSet::<usize>::from_iter(
+                    (0..paths.len())
+                        .filter(|&j| j != i && routing_connections[j].connection() == c_id)
+                        .flat_map(|j| paths[j].iter().flatten().copied()),
+                );
+                let Some(path) = paths[i].as_mut() else {
+                    continue;
+                };
+                // Never exceed the target resistance
+                let length = target_resistance / cell_resistance - path_length(&grid, path);
+                Detours::new(&mut routed_nodes, &grid, c_id, &fixed).add_meanders(
+                    path,
+                    MeanderTarget {
+                        length,
+                        tolerance: 0.,
+                        style: input.layout,
+                    },
+                );
+            }
+        }
+    }
 
-        let is_target = |n: &(usize, Option<usize>)| -> bool { n.0 == target_node_id };
+    let mut output_connections = Vec::<BoardRouterOutputConnection>::new();
+    let mut output_layers = Vec::<(ConnectionID, Vec<ChannelLayers>)>::new();
+    let mut succesful_routings = 0;
 
-        // Set euclidean distance as heuristic
-        let heuristic = |i: &(usize, Option<usize>)| -> f64 {
-            let (nix, niy) = (i.0 / cells_y, i.0 % cells_y);
-            let dx = nix.abs_diff(target_node_ix);
-            let dy = niy.abs_diff(target_node_iy);
-            f64::hypot(dx as f64, dy as f64)
+    for &i in order.iter() {
+        let Some(path) = &paths[i] else {
+            continue;
         };
+        succesful_routings += 1;
+        // Steiner branches are searched from the port to the tree, but like star branches, channels start at the join
+        // point
+        let path = match &routing_connections[i] {
+            RoutingConnection::SteinerBranch(_) => path.iter().rev().copied().collect(),
+            _ => path.clone(),
+        };
+        let mut channel = Channel::new();
+        let mut channel_layers = ChannelLayers::default();
+        for (j, &cell_id) in path.iter().enumerate() {
+            let (layer, nix, niy) = grid.cell(cell_id);
+            if j > 0 {
+                let (previous_layer, pix, piy) = grid.cell(path[j - 1]);
+                // A via keeps the position, so it does not add a channel point
+                if (pix, piy) == (nix, niy) {
+                    channel_layers.vias.push(Via {
+                        position: cell_position(nix, niy),
+                        from_layer: previous_layer,
+                        to_layer: layer,
+                    });
+                    continue;
+                }
+                channel_layers.segments.push(layer);
+            }
+            channel.push(cell_position(nix, niy));
+        }
+        let connection = routing_connections[i].connection();
+        // Channels end exactly at explicit and edge ports, which need not lie on a cell center
+        if !port_definitions.is_empty() {
+            let input_connection = input_connections.iter().find(|c| c.id == connection).unwrap();
+            let ports = Vec::from_iter(
+                input_connection
+                    .ports
+                    .iter()
+                    .chain(input_connection.branch_port.iter()),
+            );
+            let exact_position = |cell_id: usize| {
+                let (_, x, y) = grid.cell(cell_id);
+                let port = ports.iter().find(|port| port_cell(port) == (x, y))?;
+                Some(port_position(port)).filter(|&position| position != cell_position(x, y))
+            };
+            if let Some(position) = exact_position(path[path.len() - 1]) {
+                channel.push(position);
+                channel_layers.segments.push(grid.cell(path[path.len() - 1]).0);
+            }
+            if let Some(position) = exact_position(path[0]) {
+                channel.insert(0, position);
+                channel_layers.segments.insert(0, grid.cell(path[0]).0);
+            }
+        }
+        match output_connections.iter().position(|o| o.0 == connection) {
+            Some(index) => {
+                output_connections[index].1.push(channel);
+                output_layers[index].1.push(channel_layers);
+            }
+            None => {
+                output_connections.push((connection, Vec::from([channel])));
+                output_layers.push((connection, Vec::from([channel_layers])));
+            }
+        }
+    }
 
-        let result = a_star(
-            if !nodes[start_node_id].blocked || (nodes[start_node_id].connection.is_some() && nodes[start_node_id].connection.unwrap() == c_id) {
-                Vec::from([(start_node_id, None)])
-            } else {
-                Vec::new()
-            },
-            &heuristic,
-            &successors,
-            &is_target,
-            None,
-        );
-
-        // Block the cells of the resulting path so that no subsequent routings can interfere with it.
-        match result {
-            Some(path) => {
-                succesful_routings += 1;
-                let channel = path
-                    .into_iter()
-                    .map(|n| {
-                        let node = &mut nodes[n.0];
-                        node.blocked = true;
-                        let (n0ix, n0iy) = (n.0 / cells_y, n.0 % cells_y);
-                        [cell_offset_x + n0ix as f64 * cell_size, cell_offset_y + n0iy as f64 * cell_size]
-                    })
-                    .collect::<Channel>();
-                match &routing_connection {
-                    RoutingConnection::PortToPort(port_to_port) => {
-                        output_connections.push((port_to_port.connection, Vec::from([channel])));
-                    }
-                    RoutingConnection::StarBranch(star_branch) => {
-                        let output_connection_option = output_connections
-                            .iter_mut()
-                            .find(|o| o.0 == star_branch.connection);
-                        if let Some(output_connection) = output_connection_option {
-                            output_connection.1.push(channel);
-                        } else {
-                            output_connections.push((star_branch.connection, Vec::from([channel])));
+    // Simplify and round the channels, checking every new line against the other channels and the cells no channel of
+    // the connection may use
+    let mut bend_radii = Vec::<(ConnectionID, Vec<Vec<f64>>)>::new();
+    if let Some(smoothing) = input.smoothing {
+        let tolerance = 1e-9 * cell_size;
+        let clear = |connections: &[BoardRouterOutputConnection],
+                     layers: &[(ConnectionID, Vec<ChannelLayers>)],
+                     c_id: ConnectionID,
+                     layer: usize,
+                     a: Point,
+                     b: Point| {
+            let bbox = (a[0].min(b[0]), a[1].min(b[1]), a[0].max(b[0]), a[1].max(b[1]));
+            for ((other, channels), (_, channel_layers)) in connections.iter().zip(layers) {
+                if *other == c_id {
+                    continue;
+                }
+                let clearance = footprint(c_id).clearance(&footprint(*other));
+                for (channel, channel_layers) in channels.iter().zip(channel_layers) {
+                    for (k, w) in channel.windows(2).enumerate() {
+                        let far = w[0][0].min(w[1][0]) > bbox.2 + clearance
+                            || w[0][0].max(w[1][0]) < bbox.0 - clearance
+                            || w[0][1].min(w[1][1]) > bbox.3 + clearance
+                            || w[0][1].max(w[1][1]) < bbox.1 - clearance;
+                        if far || channel_layers.segments.get(k).is_some_and(|&l| l != layer) {
+                            continue;
+                        }
+                        if segments_distance(a, b, w[0], w[1]) < clearance - tolerance {
+                            return false;
                         }
                     }
                 }
             }
-            _ => (),
+            // Grid cells of obstacles are at least a cell away from any channel point
+            let cell_range = |low: f64, high: f64, offset: f64, cells: usize| {
+                let first = ((low - offset) / cell_size).floor() - 1.;
+                let last = ((high - offset) / cell_size).ceil() + 1.;
+                first.max(0.) as usize..=(last.max(0.) as usize).min(cells - 1)
+            };
+            for x in cell_range(bbox.0, bbox.2, cell_offset_x, grid.cells_x) {
+                for y in cell_range(bbox.1, bbox.3, cell_offset_y, grid.cells_y) {
+                    let node = &nodes[grid.cell_id(layer, x, y)];
+                    let obstacle = node.blocked
+                        || node.connection.is_some_and(|other| other != c_id)
+                        || grid.footprints.as_ref().is_some_and(|footprints| {
+                            footprints
+                                .obstacles
+                                .get(&c_id)
+                                .is_some_and(|obstacles| obstacles.contains(&grid.cell_id(0, x, y)))
+                        });
+                    if obstacle && segment_distance(cell_position(x, y), a, b) < cell_size - tolerance {
+                        return false;
+                    }
+                }
+            }
+            true
+        };
+        for index in 0..output_connections.len() {
+            let c_id = output_connections[index].0;
+            let mut connection_radii = Vec::new();
+            for k in 0..output_connections[index].1.len() {
+                let channel = &output_connections[index].1[k];
+                let channel_layers = &output_layers[index].1[k];
+                if !channel_layers.vias.is_empty() || channel.len() < 3 {
+                    connection_radii.push(vec![0.; channel.len()]);
+                    continue;
+                }
+                let layer = channel_layers.segments[0];
+                let mut smoothed = simplify(channel);
+                // Meanders and serpentines keep the length they were inserted for
+                let lengthened = input
+                    .length_match_groups
+                    .iter()
+                    .any(|group| group.connections.contains(&c_id))
+                    || target_resistances.iter().any(|(id, _)| *id == c_id)
+                    || input_connections
+                        .iter()
+                        .any(|c| c.id == c_id && c.serpentine.is_some());
+                if smoothing.any_angle && !lengthened {
+                    // Staircases deviate at most half a cell diagonal from their shortcut
+                    smoothed = shortcut(&smoothed, cell_size / f64::consts::SQRT_2 + tolerance, |a, b| {
+                        clear(&output_connections, &output_layers, c_id, layer, a, b)
+                    });
+                }
+                connection_radii.push(fillet_radii(&smoothed, smoothing.min_bend_radius, |a, b| {
+                    clear(&output_connections, &output_layers, c_id, layer, a, b)
+                }));
+                output_layers[index].1[k].segments = vec![layer; smoothed.len() - 1];
+                output_connections[index].1[k] = smoothed;
+            }
+            bend_radii.push((c_id, connection_radii));
         }
     }
 
-    let output = BoardRouterOutputBoard {
+    let snapshot = |nodes: &[GridNode]| GridSnapshot {
+        cell_size,
+        cell_offset_x,
+        cell_offset_y,
+        cells_x,
+        cells_y,
+        layers: grid.layers,
+        cells: nodes.to_vec(),
+    };
+
+    // Locked connections are emitted unchanged
+    let output_connections = Vec::from_iter(
+        input
+            .locked_connections
+            .iter()
+            .cloned()
+            .chain(output_connections),
+    );
+
+    // Connections in the order of their first routed branch
+    let mut routing_order = Vec::<ConnectionID>::new();
+    for &i in order.iter() {
+        let c_id = routing_connections[i].connection();
+        if !routing_order.contains(&c_id) {
+            routing_order.push(c_id);
+        }
+    }
+    let efforts = grid.efforts.take();
+    let statistics = RoutingStatistics {
+        connections: Vec::from_iter(output_connections.iter().map(|(c_id, _)| {
+            let effort = efforts.get(c_id).copied().unwrap_or_default();
+            ConnectionStatistics {
+                connection: *c_id,
+                order: routing_order.iter().position(|c| c == c_id),
+                expansions: effort.expansions,
+                time_ms: effort.time_ms,
+                ..Default::default()
+            }
+        })),
+        expansions: efforts.values().map(|effort| effort.expansions).sum(),
+        ..Default::default()
+    };
+    let mut output = BoardRouterOutputBoard {
+        channel_widths: Vec::new(),
+        lengths: Vec::new(),
+        resistances: Vec::new(),
+        bend_radii,
+        assigned_ports: Vec::new(),
+        failures,
+        setup_grid: input.grid_snapshots.then(|| snapshot(&nodes)),
+        routed_grid: input
+            .grid_snapshots
+            .then(|| snapshot(&with_paths(&nodes, &routing_connections, &paths))),
         connections: output_connections,
+        statistics,
+        layers: if grid.layers > 1 {
+            output_layers
+        } else {
+            Vec::new()
+        },
     };
+    report_connections(input, &mut output);
 
-    if succesful_routings == 0 {
-        Err(BoardRouterOutputError::NoConnectionsFound)
-    } else if succesful_routings == n_routing_connections {
+    if succesful_routings == n_routing_connections {
         Ok(output)
+    } else if succesful_routings == 0 && input.locked_connections.is_empty() {
+        Err(BoardRouterOutputError::NoConnectionsFound)
     } else {
         Err(BoardRouterOutputError::PartialResult(output))
     }
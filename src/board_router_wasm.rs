@@ -18,7 +18,22 @@ pub fn compute_ports(input: JsValue) -> JsValue {
     JsValue::from_serde(&crate::board_router::compute_ports(input.into_serde().unwrap())).unwrap()
 }
 
+#[wasm_bindgen]
+pub fn compute_port_grid(input: JsValue) -> JsValue {
+    JsValue::from_serde(&crate::board_router::compute_port_grid(input.into_serde().unwrap())).unwrap()
+}
+
 #[wasm_bindgen]
 pub fn generate_dxf(input: JsValue) -> JsValue {
     JsValue::from_serde(&crate::dxf::generate_dxf(input.into_serde().unwrap())).unwrap()
-}
\ No newline at end of file
+}
+
+#[wasm_bindgen]
+pub fn compute_resistances(input: JsValue) -> JsValue {
+    JsValue::from_serde(&crate::hydraulics::compute_resistances(input.into_serde().unwrap())).unwrap()
+}
+
+#[wasm_bindgen]
+pub fn generate_svg(input: JsValue) -> JsValue {
+    JsValue::from_serde(&crate::dxf::generate_svg(input.into_serde().unwrap())).unwrap()
+}
@@ -6,7 +6,13 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
-use crate::board_router::{BoardRouterOutputBoard, Channel, Point};
+use crate::{
+    board_router::{
+        BoardRouterOutputBoard, BoardSide, Channel, ChannelLayers, ConnectionID, EdgePort,
+        GridSnapshot, Point, PortDefinition,
+    },
+    smoothing::rounded_channel,
+};
 
 #[derive(Serialize, Deserialize, Clone, Copy)]
 pub struct ExceedBy(f64);
@@ -257,26 +263,51 @@ fn rotate_right_by(v: &[f64; 2], by: isize) -> [f64; 2] {
     r
 }
 
+// Outline of the channels as a whole; panics where try_octilinear_outline has none
 pub fn octilinear_outline(
     channels: &Vec<Channel>,
     channel_width: f64,
     channel_cap: &ChannelCap,
 ) -> Polyline {
+    try_octilinear_outline(channels, channel_width, channel_cap)
+        .expect("channels without an octilinear outline as a whole")
+}
+
+// Outline of the channels as a whole, or None if a channel has no segment, a segment is not octilinear or has no
+// length, or the channels form no tree (e.g., locked channels closing a cycle)
+pub fn try_octilinear_outline(
+    channels: &Vec<Channel>,
+    channel_width: f64,
+    channel_cap: &ChannelCap,
+) -> Option<Polyline> {
+    if channels.is_empty()
+        || channels.iter().any(|channel| channel.len() < 2)
+        || !is_octilinear(channels)
+    {
+        return None;
+    }
     if channels.len() == 1 {
-        Polyline::Closed(octilinear_outline_single_channel(
+        Some(Polyline::Closed(octilinear_outline_single_channel(
             &channels[0],
             channel_width,
             channel_cap,
-        ))
-    } else {
-        Polyline::Closed(octilinear_outline_star_shape(
+        )))
+    } else if is_star_shape(channels) {
+        Some(Polyline::Closed(octilinear_outline_star_shape(
             channels,
             channel_width,
             channel_cap,
-        ))
+        )))
+    } else {
+        octilinear_outline_tree(channels, channel_width, channel_cap).map(Polyline::Closed)
     }
 }
 
+// All channels start at the same join point
+fn is_star_shape(channels: &[Channel]) -> bool {
+    channels.iter().all(|channel| channel[0] == channels[0][0])
+}
+
 pub fn octilinear_outline_single_channel(
     channel: &Channel,
     channel_width: f64,
@@ -397,6 +428,99 @@ pub fn octilinear_outline_star_shape(
     return outline_points;
 }
 
+// Outline of channels forming a tree (e.g., a Steiner tree), where every channel starts at a point of a previous
+// channel, or None if the octilinear segments with a length do not form a tree
+pub fn octilinear_outline_tree(
+    channels: &Vec<Channel>,
+    channel_width: f64,
+    channel_cap: &ChannelCap,
+) -> Option<Vec<[f64; 2]>> {
+    if !is_octilinear(channels) {
+        return None;
+    }
+
+    // Points of the tree and their neighbors
+    let mut points: Vec<Point> = Vec::new();
+    let mut neighbors: Vec<Vec<usize>> = Vec::new();
+    let mut index_of = |point: Point, neighbors: &mut Vec<Vec<usize>>| -> usize {
+        if let Some(index) = points.iter().position(|p| *p == point) {
+            index
+        } else {
+            points.push(point);
+            neighbors.push(Vec::new());
+            points.len() - 1
+        }
+    };
+    for channel in channels {
+        for segment in channel.windows(2) {
+            let a = index_of(segment[0], &mut neighbors);
+            let b = index_of(segment[1], &mut neighbors);
+            if !neighbors[a].contains(&b) {
+                neighbors[a].push(b);
+                neighbors[b].push(a);
+            }
+        }
+    }
+    // A tree of n points has n - 1 segments and connects all points
+    let n_segments = neighbors.iter().map(Vec::len).sum::<usize>() / 2;
+    if n_segments + 1 != points.len() {
+        return None;
+    }
+    let mut reached = vec![false; points.len()];
+    let mut stack = Vec::from([0]);
+    while let Some(i) = stack.pop() {
+        if !reached[i] {
+            reached[i] = true;
+            stack.extend(neighbors[i].iter().copied());
+        }
+    }
+    if reached.contains(&false) {
+        return None;
+    }
+    // Segments have a length, so they have an orientation
+    let orientation = |from: usize, to: usize| {
+        Orientation::from_vector(points[from], points[to]).unwrap_or(Orientation::N)
+    };
+
+    // Walk around the tree, always taking the next channel clockwise, starting at a channel end
+    let start = neighbors.iter().position(|n| n.len() == 1)?;
+    let mut previous = start;
+    let mut current = neighbors[start][0];
+    let mut outline_points = Vec::from(end_points(
+        points[start],
+        orientation(start, current),
+        channel_width,
+        channel_cap,
+    ));
+    while current != start {
+        let incoming_orientation = orientation(current, previous);
+        if neighbors[current].len() == 1 {
+            outline_points.append(&mut Vec::from(end_points(
+                points[current],
+                incoming_orientation,
+                channel_width,
+                channel_cap,
+            )));
+            (previous, current) = (current, previous);
+            continue;
+        }
+
+        let next = *neighbors[current]
+            .iter()
+            .filter(|&&n| n != previous)
+            .min_by_key(|&&n| incoming_orientation.cw_get_shift(&orientation(current, n)))?;
+        let join_points =
+            next_cw_join_points(incoming_orientation, orientation(current, next), channel_width)
+                .shift_by(&points[current]);
+        if let JoinPoints::One(point) = join_points {
+            outline_points.push(point);
+        }
+        (previous, current) = (current, next);
+    }
+
+    Some(outline_points)
+}
+
 #[derive(PartialEq, Debug)]
 enum JoinPoints {
     None,
@@ -480,11 +604,7 @@ fn end_points(
     let r = wh / f64::consts::SQRT_2;
     let [px, py] = end_point;
 
-    let la = match channel_cap {
-        ChannelCap::Butt => 0.,
-        ChannelCap::Square => w / 2.,
-        ChannelCap::Custom(ExceedBy(exceed_by)) => *exceed_by,
-    };
+    let la = cap_length(channel_width, channel_cap);
 
     let lb = la / f64::consts::SQRT_2;
 
@@ -500,6 +620,158 @@ fn end_points(
     }
 }
 
+// Length by which the outline exceeds the end of the center line
+fn cap_length(channel_width: f64, channel_cap: &ChannelCap) -> f64 {
+    match channel_cap {
+        ChannelCap::Butt => 0.,
+        ChannelCap::Square => channel_width / 2.,
+        ChannelCap::Custom(ExceedBy(exceed_by)) => *exceed_by,
+    }
+}
+
+// Outline of a smoothed channel of any angle, with its fillet arcs as fine polylines
+pub fn rounded_outline(
+    channel: &Channel,
+    bend_radii: &[f64],
+    channel_width: f64,
+    channel_cap: &ChannelCap,
+) -> Polyline {
+    // Consecutive duplicate points (e.g., of locked channels) have no direction to offset or round the channel along
+    let (channel, bend_radii): (Channel, Vec<f64>) = channel
+        .iter()
+        .zip(bend_radii)
+        .enumerate()
+        .filter(|&(i, (point, _))| i == 0 || channel[i - 1] != *point)
+        .map(|(_, (&point, &radius))| (point, radius))
+        .unzip();
+    let center_line = rounded_channel(&channel, &bend_radii);
+    let n = center_line.len();
+    let wh = channel_width / 2.;
+    let directions = Vec::from_iter(center_line.windows(2).map(|w| {
+        let d = [w[1][0] - w[0][0], w[1][1] - w[0][1]];
+        let l = f64::hypot(d[0], d[1]);
+        [d[0] / l, d[1] / l]
+    }));
+    let normal = |d: [f64; 2]| [-d[1], d[0]];
+
+    // Offsets of both sides at every point of the center line, mitered at the corners
+    let la = cap_length(channel_width, channel_cap);
+    let mut left_list = Vec::with_capacity(n);
+    let mut right_list = Vec::with_capacity(n);
+    for (i, &[px, py]) in center_line.iter().enumerate() {
+        let (point, offset) = if i == 0 {
+            let d = directions[0];
+            ([px - d[0] * la, py - d[1] * la], normal(d))
+        } else if i == n - 1 {
+            let d = directions[n - 2];
+            ([px + d[0] * la, py + d[1] * la], normal(d))
+        } else {
+            let (n1, n2) = (normal(directions[i - 1]), normal(directions[i]));
+            let m = [n1[0] + n2[0], n1[1] + n2[1]];
+            let l = f64::hypot(m[0], m[1]);
+            let m = [m[0] / l, m[1] / l];
+            let miter = 1. / (m[0] * n1[0] + m[1] * n1[1]);
+            ([px, py], [m[0] * miter, m[1] * miter])
+        };
+        left_list.push([point[0] + offset[0] * wh, point[1] + offset[1] * wh]);
+        right_list.push([point[0] - offset[0] * wh, point[1] - offset[1] * wh]);
+    }
+
+    right_list.reverse();
+    left_list.append(&mut right_list);
+    Polyline::Closed(left_list)
+}
+
+// Whether all lines of the channels have a length and are horizontal, vertical or diagonal
+fn is_octilinear(channels: &[Channel]) -> bool {
+    channels.iter().flat_map(|channel| channel.windows(2)).all(|w| {
+        let [dx, dy] = [(w[1][0] - w[0][0]).abs(), (w[1][1] - w[0][1]).abs()];
+        (dx > 0. || dy > 0.) && (dx == 0. || dy == 0. || (dx - dy).abs() <= 1e-9 * dx.max(dy))
+    })
+}
+
+// Outlines of the channels of a connection: smoothed channels, channels of any angle (e.g., ending at explicit ports off
+// the grid) and channels that form no tree are outlined one by one (overlapping at join points), routed channels as a
+// whole
+fn connection_outlines(
+    connections: &BoardRouterOutputBoard,
+    c_id: ConnectionID,
+    channels: &Vec<Channel>,
+    channel_width: f64,
+    channel_cap: &ChannelCap,
+) -> Vec<Polyline> {
+    match connections.bend_radii.iter().find(|(id, _)| *id == c_id) {
+        Some((_, radii)) => channels
+            .iter()
+            .zip(radii)
+            .map(|(channel, radii)| rounded_outline(channel, radii, channel_width, channel_cap))
+            .collect(),
+        None => match try_octilinear_outline(channels, channel_width, channel_cap) {
+            Some(outline) => Vec::from([outline]),
+            None => channels
+                .iter()
+                .map(|channel| {
+                    rounded_outline(channel, &vec![0.; channel.len()], channel_width, channel_cap)
+                })
+                .collect(),
+        },
+    }
+}
+
+// Outlines of the channels of a connection with the channel layer they lie on (None for single-layer routing), split
+// into runs of segments on the same layer
+fn connection_layer_outlines(
+    connections: &BoardRouterOutputBoard,
+    c_id: ConnectionID,
+    channels: &Vec<Channel>,
+    channel_width: f64,
+    channel_cap: &ChannelCap,
+) -> Vec<(Option<usize>, Polyline)> {
+    let Some((_, channel_layers)) = connections.layers.iter().find(|(id, _)| *id == c_id) else {
+        return Vec::from_iter(
+            connection_outlines(connections, c_id, channels, channel_width, channel_cap)
+                .into_iter()
+                .map(|outline| (None, outline)),
+        );
+    };
+    let radii = connections.bend_radii.iter().find(|(id, _)| *id == c_id).map(|(_, radii)| radii);
+
+    let mut outlines = Vec::new();
+    for (k, (channel, ChannelLayers { segments, .. })) in channels.iter().zip(channel_layers).enumerate() {
+        let channel_radii = radii.map_or(vec![0.; channel.len()], |radii| radii[k].clone());
+        // Smoothing only changes channels without vias, which lie on a single layer
+        if segments.windows(2).all(|w| w[0] == w[1]) || segments.len() + 1 != channel.len() {
+            let layer = segments.first().copied().unwrap_or(0);
+            outlines.push((Some(layer), rounded_outline(channel, &channel_radii, channel_width, channel_cap)));
+            continue;
+        }
+        let mut start = 0;
+        for end in 1..=segments.len() {
+            if end < segments.len() && segments[end] == segments[start] {
+                continue;
+            }
+            // Runs end sharply at the vias
+            let mut run_radii = channel_radii[start..=end].to_vec();
+            run_radii[0] = 0.;
+            run_radii[end - start] = 0.;
+            outlines.push((
+                Some(segments[start]),
+                rounded_outline(&channel[start..=end].to_vec(), &run_radii, channel_width, channel_cap),
+            ));
+            start = end;
+        }
+    }
+    outlines
+}
+
+// Name of the DXF layer of a channel layer, the default layer for single-layer routing and the board outline
+fn dxf_layer_name(layer: Option<usize>) -> String {
+    match layer {
+        Some(layer) => format!("CHANNELS_{}", layer),
+        None => String::from("0"),
+    }
+}
+
 pub enum GeometricEntity {
     Polyline(Polyline),
     Line(Line),
@@ -550,18 +822,24 @@ pub struct Rectangle {
     dimensions: [f64; 2],
 }
 
-fn write_dxf<W: Write>(out: &mut W, entities: &[GeometricEntity]) -> Result<()> {
-    write_dxf_head(out)?;
+// Entities are drawn on the DXF layer of their channel layer
+fn write_dxf<W: Write>(out: &mut W, entities: &[(Option<usize>, GeometricEntity)]) -> Result<()> {
+    let mut layers = Vec::from_iter(entities.iter().filter_map(|(layer, _)| *layer));
+    layers.sort();
+    layers.dedup();
+    write_dxf_head(out, &layers)?;
     write_dxf_entities(out, entities)?;
     write_dxf_end(out)?;
     Ok(())
 }
 
-fn write_dxf_entities<W: Write>(out: &mut W, entities: &[GeometricEntity]) -> Result<()> {
-    for entity in entities {
+fn write_dxf_entities<W: Write>(out: &mut W, entities: &[(Option<usize>, GeometricEntity)]) -> Result<()> {
+    for (layer, entity) in entities {
+        let layer = dxf_layer_name(*layer);
         match entity {
-            GeometricEntity::Polyline(polyline) => write_dxf_polyline(out, polyline)?,
-            GeometricEntity::Line(line) => write_dxf_line(out, line)?,
+            GeometricEntity::Polyline(polyline) => write_dxf_polyline(out, &layer, polyline)?,
+            GeometricEntity::Line(line) => write_dxf_line(out, &layer, line)?,
+            GeometricEntity::Circle(circle) => write_dxf_circle(out, &layer, circle)?,
             _ => (),
         }
     }
@@ -569,13 +847,14 @@ fn write_dxf_entities<W: Write>(out: &mut W, entities: &[GeometricEntity]) -> Re
     Ok(())
 }
 
-fn write_dxf_polyline<W: Write>(out: &mut W, polyline: &Polyline) -> Result<()> {
+fn write_dxf_polyline<W: Write>(out: &mut W, layer: &str, polyline: &Polyline) -> Result<()> {
     let points = match polyline {
         Polyline::Closed(points) => {
             let n_points = points.len();
             if n_points > 0 {
                 write_dxf_line(
                     out,
+                    layer,
                     &Line {
                         from: points[0],
                         to: points[n_points - 1],
@@ -590,6 +869,7 @@ fn write_dxf_polyline<W: Write>(out: &mut W, polyline: &Polyline) -> Result<()>
     for w in points.windows(2) {
         write_dxf_line(
             out,
+            layer,
             &Line {
                 from: w[0],
                 to: w[1],
@@ -599,10 +879,10 @@ fn write_dxf_polyline<W: Write>(out: &mut W, polyline: &Polyline) -> Result<()>
     Ok(())
 }
 
-fn write_dxf_line<W: Write>(out: &mut W, line: &Line) -> Result<()> {
+fn write_dxf_line<W: Write>(out: &mut W, layer: &str, line: &Line) -> Result<()> {
     out.write_all(b"LINE\n")?;
     out.write_all(b"8\n")?;
-    out.write_all(b"0\n")?;
+    out.write_all(format!("{}\n", layer).as_bytes())?;
     out.write_all(b"10\n")?;
     out.write_all(format!("{}\n", line.from[0]).as_bytes())?;
     out.write_all(b"20\n")?;
@@ -615,7 +895,21 @@ fn write_dxf_line<W: Write>(out: &mut W, line: &Line) -> Result<()> {
     Ok(())
 }
 
-fn write_dxf_head<W: Write>(out: &mut W) -> Result<()> {
+fn write_dxf_circle<W: Write>(out: &mut W, layer: &str, circle: &Circle) -> Result<()> {
+    out.write_all(b"CIRCLE\n")?;
+    out.write_all(b"8\n")?;
+    out.write_all(format!("{}\n", layer).as_bytes())?;
+    out.write_all(b"10\n")?;
+    out.write_all(format!("{}\n", circle.center[0]).as_bytes())?;
+    out.write_all(b"20\n")?;
+    out.write_all(format!("{}\n", circle.center[1]).as_bytes())?;
+    out.write_all(b"40\n")?;
+    out.write_all(format!("{}\n", circle.radius).as_bytes())?;
+    out.write_all(b"0\n")?;
+    Ok(())
+}
+
+fn write_dxf_head<W: Write>(out: &mut W, layers: &[usize]) -> Result<()> {
     out.write_all(b"0\n")?;
     out.write_all(b"SECTION\n")?;
     out.write_all(b"2\n")?;
@@ -655,6 +949,18 @@ fn write_dxf_head<W: Write>(out: &mut W) -> Result<()> {
     out.write_all(b"2\n")?;
     out.write_all(b"LAYER\n")?;
     out.write_all(b"0\n")?;
+    for &layer in layers {
+        out.write_all(b"LAYER\n")?;
+        out.write_all(b"2\n")?;
+        out.write_all(format!("{}\n", dxf_layer_name(Some(layer))).as_bytes())?;
+        out.write_all(b"70\n")?;
+        out.write_all(b"0\n")?;
+        out.write_all(b"62\n")?;
+        out.write_all(format!("{}\n", layer + 1).as_bytes())?;
+        out.write_all(b"6\n")?;
+        out.write_all(b"CONTINUOUS\n")?;
+        out.write_all(b"0\n")?;
+    }
     out.write_all(b"ENDTAB\n")?;
     out.write_all(b"0\n")?;
     out.write_all(b"ENDSEC\n")?;
@@ -680,10 +986,68 @@ pub struct GenerateDXFInput {
     channel_cap: ChannelCap,
     board_width: f64,
     board_height: f64,
+    #[serde(default)]
+    edge_ports: Vec<EdgePort>, // openings in the board outline
+    #[serde(default)]
+    via_diameter: Option<f64>, // diameter of the vias drawn on both of their layers, the channel width if unset
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct GenerateDXFOutput(String);
+pub struct GenerateDXFOutput(pub String);
+
+// Board outline, opened at the edge ports
+fn board_outline(board_width: f64, board_height: f64, edge_ports: &[EdgePort]) -> Vec<Polyline> {
+    let corners = [[0., 0.], [board_width, 0.], [board_width, board_height], [0., board_height]];
+    if edge_ports.is_empty() {
+        return Vec::from([Polyline::Closed(Vec::from(corners))]);
+    }
+
+    // Positions along the perimeter, clockwise from the top left corner
+    let perimeter = 2. * (board_width + board_height);
+    let side_starts = [0., board_width, board_width + board_height, 2. * board_width + board_height];
+    let perimeter_position = |edge_port: &EdgePort| match edge_port.side {
+        BoardSide::Top => edge_port.position,
+        BoardSide::Right => side_starts[1] + edge_port.position,
+        BoardSide::Bottom => side_starts[2] + board_width - edge_port.position,
+        BoardSide::Left => side_starts[3] + board_height - edge_port.position,
+    };
+    let point_at = |t: f64| -> Point {
+        let t = t.rem_euclid(perimeter);
+        if t < side_starts[1] {
+            [t, 0.]
+        } else if t < side_starts[2] {
+            [board_width, t - side_starts[1]]
+        } else if t < side_starts[3] {
+            [board_width - (t - side_starts[2]), board_height]
+        } else {
+            [0., board_height - (t - side_starts[3])]
+        }
+    };
+
+    let mut openings = Vec::from_iter(edge_ports.iter().map(|edge_port| {
+        let t = perimeter_position(edge_port);
+        (t - edge_port.width / 2., t + edge_port.width / 2.)
+    }));
+    openings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    // One open polyline from the end of each opening to the start of the next one
+    Vec::from_iter((0..openings.len()).map(|i| {
+        let from = openings[i].1;
+        let mut to = openings[(i + 1) % openings.len()].0;
+        if to <= from {
+            to += perimeter;
+        }
+        let corners_between = (0..8)
+            .map(|k| side_starts[k % 4] + (k / 4) as f64 * perimeter)
+            .filter(|&t| from < t && t < to);
+        Polyline::Open(Vec::from_iter(
+            iter::once(from)
+                .chain(corners_between)
+                .chain(iter::once(to))
+                .map(point_at),
+        ))
+    }))
+}
 
 pub fn generate_dxf(input: GenerateDXFInput) -> GenerateDXFOutput {
     let mut s = Vec::new();
@@ -694,26 +1058,59 @@ pub fn generate_dxf(input: GenerateDXFInput) -> GenerateDXFOutput {
             .connections
             .connections
             .iter()
-            .map(|(_, connection)| {
-                GeometricEntity::Polyline(
-                    octilinear_outline(connection, input.channel_width, &input.channel_cap)
-                        .invert_y()
-                        .add_y(input.board_height)
-                        .to_owned(),
+            .flat_map(|(c_id, connection)| {
+                // Each connection is drawn at its own channel width
+                let channel_width = input.connections.channel_width(*c_id, input.channel_width);
+                connection_layer_outlines(
+                    &input.connections,
+                    *c_id,
+                    connection,
+                    channel_width,
+                    &input.channel_cap,
                 )
+                .into_iter()
+                .map(|(layer, mut outline)| {
+                    (
+                        layer,
+                        GeometricEntity::Polyline(
+                            outline
+                                .invert_y()
+                                .add_y(input.board_height)
+                                .to_owned(),
+                        ),
+                    )
+                })
             })
-            .chain(iter::once(GeometricEntity::Polyline(
-                Polyline::Closed(Vec::from([
-                    [0., 0.],
-                    [input.board_width, 0.],
-                    [input.board_width, input.board_height],
-                    [0., input.board_height],
-                ]))
-                .invert_y()
-                .add_y(input.board_height)
-                .to_owned(),
-            )))
-            .collect::<Vec<GeometricEntity>>(),
+            .chain(input.connections.layers.iter().flat_map(|(c_id, channel_layers)| {
+                let radius = input
+                    .via_diameter
+                    .unwrap_or(input.connections.channel_width(*c_id, input.channel_width))
+                    / 2.;
+                channel_layers
+                    .iter()
+                    .flat_map(|channel_layers| channel_layers.vias.iter())
+                    .flat_map(move |via| {
+                        let center = [via.position[0], input.board_height - via.position[1]];
+                        [via.from_layer, via.to_layer]
+                            .map(|layer| (Some(layer), GeometricEntity::Circle(Circle { center, radius })))
+                    })
+            }))
+            .chain(
+                board_outline(input.board_width, input.board_height, &input.edge_ports)
+                    .into_iter()
+                    .map(|mut outline| {
+                        (
+                            None,
+                            GeometricEntity::Polyline(
+                                outline
+                                    .invert_y()
+                                    .add_y(input.board_height)
+                                    .to_owned(),
+                            ),
+                        )
+                    }),
+            )
+            .collect::<Vec<(Option<usize>, GeometricEntity)>>(),
     );
     GenerateDXFOutput(String::from_utf8(s).unwrap())
 }
@@ -726,6 +1123,10 @@ pub struct GenerateSVGInput {
     pub board_width: f64,
     pub board_height: f64,
     pub port_diameter: Option<f64>,
+    #[serde(default)]
+    pub ports: Vec<PortDefinition>, // explicit ports, drawn instead of circles of the port diameter
+    #[serde(default)]
+    pub grid: Option<GridSnapshot>, // routing grid drawn on top of the channels for debugging
 }
 
 #[derive(Serialize, Deserialize)]
@@ -741,18 +1142,23 @@ pub fn generate_svg(input: GenerateSVGInput) -> GenerateSVGOutput {
             .connections
             .connections
             .iter()
-            .map(|(_, connection)| {
-                GeometricEntity::Polyline(octilinear_outline(
+            .flat_map(|(c_id, connection)| {
+                connection_outlines(
+                    &input.connections,
+                    *c_id,
                     connection,
-                    input.channel_width,
+                    input.connections.channel_width(*c_id, input.channel_width),
                     &input.channel_cap,
-                ))
+                )
+                .into_iter()
+                .map(GeometricEntity::Polyline)
             })
             .chain(
                 input
                     .connections
                     .connections
                     .iter()
+                    .filter(|_| input.port_diameter.is_some() && input.ports.is_empty())
                     .flat_map(|(_, connection)| {
                         if connection.len() == 1 {
                             Vec::from([
@@ -765,6 +1171,17 @@ pub fn generate_svg(input: GenerateSVGInput) -> GenerateSVGOutput {
                                     radius: input.port_diameter.unwrap() / 2.,
                                 }),
                             ])
+                        } else if !is_star_shape(connection) {
+                            // The first channel of a tree starts at a port as well
+                            iter::once(connection[0].first().unwrap())
+                                .chain(connection.iter().map(|c| c.last().unwrap()))
+                                .map(|center| {
+                                    GeometricEntity::Circle(Circle {
+                                        center: *center,
+                                        radius: input.port_diameter.unwrap() / 2.,
+                                    })
+                                })
+                                .collect::<Vec<GeometricEntity>>()
                         } else {
                             connection
                                 .iter()
@@ -777,7 +1194,26 @@ pub fn generate_svg(input: GenerateSVGInput) -> GenerateSVGOutput {
                                 .collect::<Vec<GeometricEntity>>()
                         }
                     })
-                    .take_while(|_| input.port_diameter.is_some()),
+            )
+            .chain(
+                // Explicit ports are drawn at their own diameter
+                input
+                    .ports
+                    .iter()
+                    .filter(|definition| {
+                        input.connections.connections.iter().any(|(_, connection)| {
+                            connection.iter().any(|channel| {
+                                channel.first() == Some(&definition.position)
+                                    || channel.last() == Some(&definition.position)
+                            })
+                        })
+                    })
+                    .map(|definition| {
+                        GeometricEntity::Circle(Circle {
+                            center: definition.position,
+                            radius: definition.diameter / 2.,
+                        })
+                    }),
             )
             .chain([GeometricEntity::Rectangle(Rectangle {
                 position: [0., 0.],
@@ -795,6 +1231,9 @@ fn write_svg<W: Write>(
 ) -> Result<()> {
     write_svg_head(out, input)?;
     write_svg_entities(out, input, entities)?;
+    if let Some(grid) = &input.grid {
+        write_svg_grid(out, grid)?;
+    }
     write_svg_end(out, input)?;
     Ok(())
 }
@@ -879,6 +1318,42 @@ fn write_svg_circle<W: Write>(out: &mut W, _: &GenerateSVGInput, circle: &Circle
     Ok(())
 }
 
+// Cells of all layers: free cells are outlined, blocked cells are gray, and cells reserved for or occupied by a
+// connection are drawn in the color of the connection (stronger for channels)
+fn write_svg_grid<W: Write>(out: &mut W, grid: &GridSnapshot) -> Result<()> {
+    let hue = |c_id: ConnectionID| (c_id * 137) % 360;
+    out.write_all(b"<g class=\"grid\">")?;
+    for x in 0..grid.cells_x {
+        for y in 0..grid.cells_y {
+            let cells = Vec::from_iter((0..grid.layers).map(|layer| grid.cell(layer, x, y)));
+            let fill = if let Some(c_id) = cells.iter().find_map(|cell| cell.channel) {
+                format!("hsla({}, 80%, 45%, 0.6)", hue(c_id))
+            } else if let Some(c_id) = cells.iter().find_map(|cell| cell.connection) {
+                format!("hsla({}, 80%, 45%, 0.25)", hue(c_id))
+            } else if cells.iter().any(|cell| cell.blocked) {
+                String::from("rgba(128, 128, 128, 0.5)")
+            } else {
+                String::from("none")
+            };
+            let [cx, cy] = grid.cell_position(x, y);
+            out.write_all(
+                format!(
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" stroke-width=\"{}\" stroke=\"#ccc\" fill=\"{}\" />",
+                    cx - grid.cell_size / 2.,
+                    cy - grid.cell_size / 2.,
+                    grid.cell_size,
+                    grid.cell_size,
+                    grid.cell_size / 20.,
+                    fill
+                )
+                .as_bytes(),
+            )?;
+        }
+    }
+    out.write_all(b"</g>")?;
+    Ok(())
+}
+
 fn write_svg_head<W: Write>(out: &mut W, input: &GenerateSVGInput) -> Result<()> {
     out.write_all(
         format!(
@@ -1145,4 +1620,233 @@ mod tests {
             )
         }
     }
+
+    mod octilinear_outline_tree {
+        use super::*;
+
+        const W: f64 = 2.;
+
+        #[test]
+        fn single_channel() {
+            let channel = Vec::from([[0., 0.], [10., 0.], [20., 10.], [20., 20.]]);
+            assert_eq!(
+                octilinear_outline_tree(&Vec::from([channel.clone()]), W, &ChannelCap::Butt),
+                Some(octilinear_outline_single_channel(&channel, W, &ChannelCap::Butt))
+            )
+        }
+
+        #[test]
+        fn t_shape() {
+            let channels = Vec::from([
+                Vec::from([[0., 0.], [10., 0.], [20., 0.]]),
+                Vec::from([[10., 0.], [10., 10.]]),
+            ]);
+            assert_eq!(
+                octilinear_outline_tree(&channels, W, &ChannelCap::Butt),
+                Some(Vec::from([
+                    [0., 1.],
+                    [0., -1.],
+                    [20., -1.],
+                    [20., 1.],
+                    [11., 1.],
+                    [11., 10.],
+                    [9., 10.],
+                    [9., 1.],
+                ]))
+            )
+        }
+
+        #[test]
+        fn cycle() {
+            let channels = Vec::from([
+                Vec::from([[0., 0.], [10., 0.], [10., 10.]]),
+                Vec::from([[10., 10.], [0., 10.], [0., 0.]]),
+            ]);
+            assert_eq!(octilinear_outline_tree(&channels, W, &ChannelCap::Butt), None);
+            assert!(try_octilinear_outline(&channels, W, &ChannelCap::Butt).is_none())
+        }
+
+        #[test]
+        fn segment_without_length() {
+            let channels = Vec::from([
+                Vec::from([[0., 0.], [10., 0.], [10., 0.], [20., 0.]]),
+                Vec::from([[10., 0.], [10., 10.]]),
+            ]);
+            assert_eq!(octilinear_outline_tree(&channels, W, &ChannelCap::Butt), None);
+            assert!(try_octilinear_outline(&channels, W, &ChannelCap::Butt).is_none())
+        }
+    }
+
+    mod rounded_outline {
+        use super::*;
+        use crate::board_router::segment_distance;
+
+        const W: f64 = 2.;
+
+        fn points(outline: Polyline) -> Vec<[f64; 2]> {
+            match outline {
+                Polyline::Closed(points) => points,
+                Polyline::Open(_) => panic!(),
+            }
+        }
+
+        #[test]
+        fn straight() {
+            let channel = Vec::from([[0., 0.], [10., 0.]]);
+            assert_eq!(
+                points(rounded_outline(&channel, &[0., 0.], W, &ChannelCap::Square)),
+                Vec::from([[-1., 1.], [11., 1.], [11., -1.], [-1., -1.]])
+            )
+        }
+
+        #[test]
+        fn sharp_corner() {
+            let channel = Vec::from([[0., 0.], [10., 0.], [10., 10.]]);
+            let outline = points(rounded_outline(&channel, &[0., 0., 0.], W, &ChannelCap::Butt));
+            let expected = [[0., 1.], [9., 1.], [9., 10.], [11., 10.], [11., -1.], [0., -1.]];
+            assert_eq!(outline.len(), expected.len());
+            for (point, expected) in outline.iter().zip(expected) {
+                assert!(f64::hypot(point[0] - expected[0], point[1] - expected[1]) < 1e-9);
+            }
+        }
+
+        #[test]
+        fn duplicate_points() {
+            let channel = Vec::from([[0., 0.], [10., 0.], [10., 0.], [10., 10.]]);
+            let outline = points(rounded_outline(&channel, &[0., 5., 5., 0.], W, &ChannelCap::Butt));
+            let deduplicated = Vec::from([[0., 0.], [10., 0.], [10., 10.]]);
+            let expected = points(rounded_outline(&deduplicated, &[0., 5., 0.], W, &ChannelCap::Butt));
+            assert!(outline.iter().flatten().all(|c| c.is_finite()));
+            assert_eq!(outline, expected);
+        }
+
+        #[test]
+        fn fillet() {
+            let channel = Vec::from([[0., 0.], [10., 0.], [10., 10.]]);
+            let radii = [0., 5., 0.];
+            let center_line = rounded_channel(&channel, &radii);
+            let outline = points(rounded_outline(&channel, &radii, W, &ChannelCap::Butt));
+            assert_eq!(outline.len(), 2 * center_line.len());
+            for point in outline {
+                let distance = center_line
+                    .windows(2)
+                    .map(|w| segment_distance(point, w[0], w[1]))
+                    .fold(f64::INFINITY, f64::min);
+                // Miters on the outer side of the arc exceed half the width slightly
+                assert!(distance > W / 2. - 1e-9 && distance < W / 2. * 1.001);
+            }
+        }
+    }
+
+    mod board_outline {
+        use super::*;
+
+        fn points(outline: &Polyline) -> &Vec<[f64; 2]> {
+            match outline {
+                Polyline::Closed(points) | Polyline::Open(points) => points,
+            }
+        }
+
+        #[test]
+        fn closed() {
+            let outlines = board_outline(30., 20., &[]);
+            assert_eq!(outlines.len(), 1);
+            assert!(matches!(outlines[0], Polyline::Closed(_)));
+        }
+
+        #[test]
+        fn single_opening() {
+            let edge_ports = [EdgePort {
+                port: (0, 0),
+                side: BoardSide::Left,
+                position: 10.,
+                width: 4.,
+            }];
+            let outlines = board_outline(30., 20., &edge_ports);
+            assert_eq!(outlines.len(), 1);
+            assert_eq!(
+                points(&outlines[0]),
+                &Vec::from([[0., 8.], [0., 0.], [30., 0.], [30., 20.], [0., 20.], [0., 12.]])
+            );
+        }
+
+        #[test]
+        fn two_openings() {
+            let edge_ports = [
+                EdgePort {
+                    port: (0, 0),
+                    side: BoardSide::Right,
+                    position: 10.,
+                    width: 4.,
+                },
+                EdgePort {
+                    port: (1, 0),
+                    side: BoardSide::Top,
+                    position: 10.,
+                    width: 2.,
+                },
+            ];
+            let outlines = board_outline(30., 20., &edge_ports);
+            assert_eq!(
+                outlines.iter().map(points).cloned().collect::<Vec<_>>(),
+                Vec::from([
+                    Vec::from([[11., 0.], [30., 0.], [30., 8.]]),
+                    Vec::from([[30., 12.], [30., 20.], [0., 20.], [0., 0.], [9., 0.]]),
+                ])
+            );
+        }
+    }
+
+    mod generate_dxf {
+        use super::*;
+
+        fn dxf(connections: &str) -> String {
+            generate_dxf(GenerateDXFInput {
+                connections: serde_json::from_str(connections).unwrap(),
+                channel_width: 2.,
+                channel_cap: ChannelCap::Butt,
+                board_width: 30.,
+                board_height: 20.,
+                edge_ports: Vec::new(),
+                via_diameter: Some(4.),
+            })
+            .0
+        }
+
+        // Layers of the drawn entities
+        fn entity_layers(dxf: &str, entity: &str) -> Vec<String> {
+            let lines = Vec::from_iter(dxf.lines());
+            Vec::from_iter(
+                lines
+                    .windows(3)
+                    .filter(|w| w[0] == entity && w[1] == "8")
+                    .map(|w| w[2].to_owned()),
+            )
+        }
+
+        #[test]
+        fn single_layer() {
+            let dxf = dxf(r#"{"connections": [[0, [[[0, 10], [10, 10]]]]]}"#);
+            assert!(entity_layers(&dxf, "LINE").iter().all(|layer| layer == "0"));
+            assert!(entity_layers(&dxf, "CIRCLE").is_empty());
+        }
+
+        #[test]
+        fn multi_layer() {
+            let dxf = dxf(
+                r#"{
+                    "connections": [[0, [[[0, 10], [10, 10], [20, 10], [20, 0]]]]],
+                    "layers": [[0, [{"segments": [0, 1, 1], "vias": [{"position": [10, 10], "from_layer": 0, "to_layer": 1}]}]]]
+                }"#,
+            );
+            let lines = entity_layers(&dxf, "LINE");
+            // A rectangle on the first layer, an L-shape on the second and the board outline
+            assert_eq!(lines.iter().filter(|layer| *layer == "CHANNELS_0").count(), 4);
+            assert_eq!(lines.iter().filter(|layer| *layer == "CHANNELS_1").count(), 6);
+            assert_eq!(lines.iter().filter(|layer| *layer == "0").count(), 4);
+            assert_eq!(entity_layers(&dxf, "CIRCLE"), Vec::from(["CHANNELS_0", "CHANNELS_1"]));
+            assert!(dxf.contains("CIRCLE\n8\nCHANNELS_0\n10\n10\n20\n10\n40\n2\n"));
+            assert!(dxf.contains("LAYER\n2\nCHANNELS_1\n"));
+        }
+    }
 }
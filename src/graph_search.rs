@@ -1,9 +1,14 @@
 use std::cmp::Ordering;
-use std::collections::{BinaryHeap, VecDeque};
+use std::collections::{BinaryHeap, HashMap, VecDeque};
 use core::fmt::Debug;
 use std::hash::Hash;
 
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
+
+#[cfg(not(target_family = "wasm"))]
+use std::{sync::OnceLock, time::Instant};
+#[cfg(target_family = "wasm")]
+use wasm_bindgen::JsCast;
 
 const INITIAL_PATH_CAPACITY: usize = 8;
 const DEFAULT_HEURISTIC_BIAS: f64 = 1.0;
@@ -68,13 +73,109 @@ impl<'a, N: Eq + Copy + Debug> Ord for AStarNode<N> {
     }
 }
 
+// Successors of a node with the cost of the step to them
+pub type Successors<'a, N> = dyn Fn(&AStarNode<N>) -> Vec<(N, f64)> + 'a;
+
+// Whether the edge from the first node to the second exists
+pub type IsEdge<'a, N> = dyn Fn(&N, &N) -> bool + 'a;
+
+// Unlimited searches, the router searches with a_star_limited
+#[cfg(test)]
 pub fn a_star<N: Eq + Copy + Debug + Hash>(
     start: Vec<N>,
     heuristic: &dyn Fn(&N) -> f64,
-    successors: &dyn Fn(&AStarNode<N>) -> Vec<(N, f64)>,
+    successors: &Successors<'_, N>,
     is_target: &dyn Fn(&N) -> bool,
     heuristic_bias: Option<f64>
 ) -> Option<VecDeque<N>> {
+    a_star_explored(start, heuristic, successors, is_target, heuristic_bias).0
+}
+
+// Like a_star, but also returns the nodes explored by the search (in the order of their expansion)
+#[cfg(test)]
+pub fn a_star_explored<N: Eq + Copy + Debug + Hash>(
+    start: Vec<N>,
+    heuristic: &dyn Fn(&N) -> f64,
+    successors: &Successors<'_, N>,
+    is_target: &dyn Fn(&N) -> bool,
+    heuristic_bias: Option<f64>
+) -> (Option<VecDeque<N>>, Vec<N>) {
+    let result = a_star_limited(start, heuristic, successors, is_target, heuristic_bias, None, None);
+    (result.path, result.explored)
+}
+
+pub struct SearchResult<N> {
+    pub path: Option<VecDeque<N>>,
+    pub explored: Vec<N>, // nodes expanded by the search, in the order of their expansion
+    pub limit_reached: bool, // the search stopped at its expansion limit or deadline, so a path may exist
+}
+
+// Milliseconds since the first call, from performance.now() of the window or worker in the browser (std::time::Instant
+// is not available for WebAssembly)
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn now_ms() -> f64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.
+}
+
+// The global scope is a window on the main thread and a worker scope in a Web Worker, and both have a performance
+// object; without it, the clock falls back to Date.now()
+#[cfg(target_family = "wasm")]
+pub(crate) fn now_ms() -> f64 {
+    js_sys::Reflect::get(&js_sys::global(), &"performance".into())
+        .ok()
+        .and_then(|performance| performance.dyn_into::<web_sys::Performance>().ok())
+        .map_or_else(js_sys::Date::now, |performance| performance.now())
+}
+
+// Expansions between two reads of the clock
+const DEADLINE_CHECK_INTERVAL: usize = 256;
+
+// Whether the deadline (in ms, see now_ms) has passed, read every DEADLINE_CHECK_INTERVAL expansions
+fn past_deadline(deadline: Option<f64>, expansions: usize) -> bool {
+    deadline.is_some_and(|deadline| expansions.is_multiple_of(DEADLINE_CHECK_INTERVAL) && now_ms() >= deadline)
+}
+
+// Like a_star_explored, but stops without a path after expanding the given number of nodes or after the deadline
+pub fn a_star_limited<N: Eq + Copy + Debug + Hash>(
+    start: Vec<N>,
+    heuristic: &dyn Fn(&N) -> f64,
+    successors: &Successors<'_, N>,
+    is_target: &dyn Fn(&N) -> bool,
+    heuristic_bias: Option<f64>,
+    max_expansions: Option<usize>,
+    deadline: Option<f64>,
+) -> SearchResult<N> {
+    limited_search(start, heuristic, successors, None, is_target, heuristic_bias, max_expansions, deadline)
+}
+
+// Like a_star_limited, but the successors are candidates whose edges (from the given node) are only checked when the
+// search reaches them, for graphs with many edges that are expensive to check
+#[allow(clippy::too_many_arguments)]
+pub fn lazy_a_star_limited<N: Eq + Copy + Debug + Hash>(
+    start: Vec<N>,
+    heuristic: &dyn Fn(&N) -> f64,
+    successors: &Successors<'_, N>,
+    is_edge: &IsEdge<'_, N>,
+    is_target: &dyn Fn(&N) -> bool,
+    heuristic_bias: Option<f64>,
+    max_expansions: Option<usize>,
+    deadline: Option<f64>,
+) -> SearchResult<N> {
+    limited_search(start, heuristic, successors, Some(is_edge), is_target, heuristic_bias, max_expansions, deadline)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn limited_search<N: Eq + Copy + Debug + Hash>(
+    start: Vec<N>,
+    heuristic: &dyn Fn(&N) -> f64,
+    successors: &Successors<'_, N>,
+    is_edge: Option<&IsEdge<'_, N>>,
+    is_target: &dyn Fn(&N) -> bool,
+    heuristic_bias: Option<f64>,
+    max_expansions: Option<usize>,
+    deadline: Option<f64>,
+) -> SearchResult<N> {
     let mut open = BinaryHeap::<AStarNode<N>>::new();
     let bias = heuristic_bias.unwrap_or(DEFAULT_HEURISTIC_BIAS);
     let mut closed = IndexMap::new();
@@ -85,7 +186,7 @@ pub fn a_star<N: Eq + Copy + Debug + Hash>(
             cost: Cost {
                 estimate: h,
                 cost: 0.,
-                total: h,
+                total: bias * h,
             },
             previous: None,
             prev_id: None,
@@ -93,15 +194,26 @@ pub fn a_star<N: Eq + Copy + Debug + Hash>(
     });
 
     let mut target = None;
+    let mut limit_reached = false;
 
     while !open.is_empty() {
         let candidate = open.pop().unwrap();
 
-        if is_target(&candidate.node) {
+        let rejected = || {
+            is_edge
+                .zip(candidate.previous)
+                .is_some_and(|(is_edge, previous)| !is_edge(&previous, &candidate.node))
+        };
+        if !closed.contains_key(&candidate.node) && rejected() {
+            continue;
+        } else if is_target(&candidate.node) {
             target = Some(candidate);
             break;
         } else if closed.contains_key(&candidate.node) {
             continue;
+        } else if max_expansions.is_some_and(|max| closed.len() >= max) || past_deadline(deadline, closed.len()) {
+            limit_reached = true;
+            break;
         }
 
         let ns = successors(&candidate);
@@ -114,7 +226,7 @@ pub fn a_star<N: Eq + Copy + Debug + Hash>(
                 cost: Cost {
                     estimate: h,
                     cost: nc,
-                    total: nc + h
+                    total: nc + bias * h
                 },
                 previous: Some(candidate.node),
                 prev_id: Some(i),
@@ -123,7 +235,7 @@ pub fn a_star<N: Eq + Copy + Debug + Hash>(
         closed.insert(candidate.node, candidate);
     }
 
-    match target {
+    let path = match target {
         Some(t) => {
             let mut path = VecDeque::with_capacity(INITIAL_PATH_CAPACITY);
             path.push_front(t.node);
@@ -135,6 +247,169 @@ pub fn a_star<N: Eq + Copy + Debug + Hash>(
             Some(path)
         }
         None => None,
+    };
+    SearchResult {
+        path,
+        explored: closed.into_keys().collect(),
+        limit_reached,
+    }
+}
+
+// One direction of a bidirectional search: its start nodes, the heuristic towards the start nodes of the other
+// direction, and its successors (for the backward direction, the nodes a step leads from, with the cost of that step)
+pub struct SearchDirection<'a, N: Eq + Copy + Debug> {
+    pub start: Vec<N>,
+    pub heuristic: &'a dyn Fn(&N) -> f64,
+    pub successors: &'a Successors<'a, N>,
+}
+
+// Nodes reached by one direction of a bidirectional search
+struct Frontier<N: Eq + Copy + Debug, K> {
+    open: BinaryHeap<AStarNode<N>>,
+    closed: IndexSet<N>,
+    reached: HashMap<N, (f64, Option<N>)>, // the lowest cost to the node and the node it was reached from
+    by_key: HashMap<K, Vec<N>>,
+    bias: f64, // weight of the heuristic in the estimated total cost
+}
+
+impl<N: Eq + Copy + Debug + Hash, K: Eq + Hash> Frontier<N, K> {
+    fn new(bias: f64) -> Self {
+        Frontier {
+            open: BinaryHeap::new(),
+            closed: IndexSet::new(),
+            reached: HashMap::new(),
+            by_key: HashMap::new(),
+            bias,
+        }
+    }
+
+    // Lowest estimated total cost of a path through the open nodes
+    fn min_total(&self) -> f64 {
+        self.open.peek().map_or(f64::INFINITY, |n| n.cost.total)
+    }
+
+    // Records the node if its cost is lower than before
+    fn reach(&mut self, node: N, cost: f64, previous: Option<N>, heuristic: f64, key: K) -> bool {
+        if self.reached.get(&node).is_some_and(|(c, _)| *c <= cost) {
+            return false;
+        }
+        if self.reached.insert(node, (cost, previous)).is_none() {
+            self.by_key.entry(key).or_default().push(node);
+        }
+        self.open.push(AStarNode {
+            node,
+            cost: Cost {
+                estimate: heuristic,
+                cost,
+                total: cost + self.bias * heuristic,
+            },
+            previous,
+            prev_id: None,
+        });
+        true
+    }
+
+    // Nodes from the start of this direction to the node
+    fn path(&self, node: N) -> VecDeque<N> {
+        let mut path = VecDeque::from([node]);
+        while let Some((_, Some(previous))) = self.reached.get(path.front().unwrap()) {
+            path.push_front(*previous);
+        }
+        path
+    }
+}
+
+// Bidirectional A*: the forward and the backward search expand their cheapest node in turn, and a forward and a
+// backward node of the same key that can be joined (at the cost of joining them) form a path; the search ends when no
+// path through the open nodes can be cheaper than the cheapest path found (with a heuristic bias above 1, when none is
+// estimated to be cheaper), or after expanding the given number of nodes in both directions or after the deadline. The
+// path is the forward path to the meeting node followed by the backward path from there on (without its node of the
+// meeting key), the explored nodes are those of the forward and then of the backward search.
+pub fn bidirectional_a_star<N: Eq + Copy + Debug + Hash, K: Eq + Hash>(
+    forward: SearchDirection<'_, N>,
+    backward: SearchDirection<'_, N>,
+    key: &dyn Fn(&N) -> K,
+    join_cost: &dyn Fn(&N, &N) -> Option<f64>,
+    heuristic_bias: Option<f64>,
+    max_expansions: Option<usize>,
+    deadline: Option<f64>,
+) -> SearchResult<N> {
+    let directions = [&forward, &backward];
+    let bias = heuristic_bias.unwrap_or(DEFAULT_HEURISTIC_BIAS);
+    let mut frontiers = [Frontier::<N, K>::new(bias), Frontier::<N, K>::new(bias)];
+    // Cheapest path found as its cost, forward node and backward node
+    let mut best: Option<(f64, N, N)> = None;
+
+    let meet = |frontiers: &[Frontier<N, K>; 2], best: &mut Option<(f64, N, N)>, side: usize, node: N| {
+        let other = &frontiers[1 - side];
+        let Some(candidates) = other.by_key.get(&key(&node)) else {
+            return;
+        };
+        let cost = frontiers[side].reached[&node].0;
+        for candidate in candidates.iter() {
+            let (f, b) = if side == 0 { (node, *candidate) } else { (*candidate, node) };
+            let total = cost + other.reached[candidate].0;
+            if best.is_some_and(|(c, _, _)| total >= c) {
+                continue;
+            }
+            let Some(join) = join_cost(&f, &b) else {
+                continue;
+            };
+            if best.is_none_or(|(c, _, _)| total + join < c) {
+                *best = Some((total + join, f, b));
+            }
+        }
+    };
+
+    for side in 0..2 {
+        for n in directions[side].start.iter() {
+            if frontiers[side].reach(*n, 0., None, (directions[side].heuristic)(n), key(n)) {
+                meet(&frontiers, &mut best, side, *n);
+            }
+        }
+    }
+
+    let mut limit_reached = false;
+    loop {
+        let (forward_total, backward_total) = (frontiers[0].min_total(), frontiers[1].min_total());
+        if forward_total == f64::INFINITY && backward_total == f64::INFINITY {
+            break;
+        }
+        if best.is_some_and(|(c, _, _)| c <= forward_total.max(backward_total)) {
+            break;
+        }
+        let side = if forward_total <= backward_total { 0 } else { 1 };
+        let candidate = frontiers[side].open.pop().unwrap();
+        if frontiers[side].closed.contains(&candidate.node)
+            || frontiers[side].reached[&candidate.node].0 < candidate.cost.cost
+        {
+            continue;
+        }
+        let expansions = frontiers[0].closed.len() + frontiers[1].closed.len();
+        if max_expansions.is_some_and(|max| expansions >= max) || past_deadline(deadline, expansions) {
+            limit_reached = best.is_none();
+            break;
+        }
+        frontiers[side].closed.insert(candidate.node);
+        for (n, c) in (directions[side].successors)(&candidate) {
+            let cost = candidate.cost.cost + c;
+            let heuristic = (directions[side].heuristic)(&n);
+            if frontiers[side].reach(n, cost, Some(candidate.node), heuristic, key(&n)) {
+                meet(&frontiers, &mut best, side, n);
+            }
+        }
+    }
+
+    let path = best.map(|(_, f, b)| {
+        let mut path = frontiers[0].path(f);
+        path.extend(frontiers[1].path(b).into_iter().rev().skip(1));
+        path
+    });
+    let [forward_frontier, backward_frontier] = frontiers;
+    SearchResult {
+        path,
+        explored: Vec::from_iter(forward_frontier.closed.into_iter().chain(backward_frontier.closed)),
+        limit_reached,
     }
 }
 
@@ -145,12 +420,12 @@ mod tests {
     fn dummy() {}
 
     mod a_star {
-        use super::{a_star, AStarNode};
+        use super::{a_star, a_star_explored, a_star_limited, lazy_a_star_limited, AStarNode};
 
         mod graph_1 {
             use std::collections::VecDeque;
 
-            use super::{a_star, AStarNode};
+            use super::{a_star, a_star_explored, a_star_limited, lazy_a_star_limited, AStarNode};
 
             type N = char;
 
@@ -207,6 +482,48 @@ mod tests {
                 let result = a_star(start, &heuristic, &successors, &is_target, None);
                 assert_eq!(result, None)
             }
+
+            #[test]
+            fn explored() {
+                let start = Vec::from(['C']);
+                fn is_target(n: &N) -> bool {
+                    *n == 'A'
+                }
+                let result = a_star_explored(start, &heuristic, &successors, &is_target, None);
+                assert_eq!(result, (None, Vec::from(['C', 'D'])))
+            }
+
+            #[test]
+            fn limited() {
+                fn is_target(n: &N) -> bool {
+                    *n == 'D'
+                }
+                let result = a_star_limited(Vec::from(['A']), &heuristic, &successors, &is_target, None, Some(2), None);
+                assert_eq!(result.path, None);
+                assert_eq!(result.explored, Vec::from(['A', 'B']));
+                assert!(result.limit_reached);
+
+                let result = a_star_limited(Vec::from(['A']), &heuristic, &successors, &is_target, None, Some(3), None);
+                assert_eq!(result.path, Some(VecDeque::from(['A', 'C', 'D'])));
+                assert!(!result.limit_reached);
+            }
+
+            #[test]
+            fn lazy() {
+                fn is_target(n: &N) -> bool {
+                    *n == 'D'
+                }
+                let is_edge = |a: &N, b: &N| (*a, *b) != ('A', 'B');
+                let result = lazy_a_star_limited(Vec::from(['A']), &heuristic, &successors, &is_edge, &is_target, None, None, None);
+                assert_eq!(result.path, Some(VecDeque::from(['A', 'C', 'D'])));
+                // B is never expanded, as its only edge is rejected
+                assert_eq!(result.explored, Vec::from(['A', 'C']));
+
+                let is_edge = |a: &N, b: &N| (*a, *b) != ('C', 'D');
+                let result = lazy_a_star_limited(Vec::from(['A']), &heuristic, &successors, &is_edge, &is_target, None, None, None);
+                assert_eq!(result.path, None);
+                assert!(!result.limit_reached);
+            }
         }
 
         mod graph_2 {
@@ -520,5 +837,214 @@ mod tests {
                 assert_eq!(result, Some(VecDeque::from([3, 7, 12, 17, 22])))
             }
         }
+
+        mod graph_5 {
+            use std::collections::VecDeque;
+
+            use super::{a_star, AStarNode};
+
+            type N = char;
+
+            fn heuristic(n: &N) -> f64 {
+                match n {
+                    'B' => 2.,
+                    'C' => 5.,
+                    _ => 0.,
+                }
+            }
+
+            fn successors(n: &AStarNode<N>) -> Vec<(N, f64)> {
+                match n.node {
+                    'A' => Vec::from([('B', 1.), ('C', 5.)]),
+                    'B' => Vec::from([('D', 10.)]),
+                    'C' => Vec::from([('D', 5.)]),
+                    'D' => Vec::from([]),
+                    _ => panic!(),
+                }
+            }
+
+            #[test]
+            fn bias() {
+                fn is_target(n: &N) -> bool {
+                    *n == 'D'
+                }
+                let result = a_star(Vec::from(['A']), &heuristic, &successors, &is_target, None);
+                assert_eq!(result, Some(VecDeque::from(['A', 'C', 'D'])));
+
+                // A biased search follows the heuristic to a longer path
+                let result = a_star(Vec::from(['A']), &heuristic, &successors, &is_target, Some(3.));
+                assert_eq!(result, Some(VecDeque::from(['A', 'B', 'D'])));
+            }
+        }
+    }
+
+    mod bidirectional {
+        use core::f64;
+        use std::collections::VecDeque;
+
+        use super::super::{a_star, bidirectional_a_star, AStarNode, SearchDirection};
+
+        // Cells of a 5 x 5 grid, row by row, with a wall in the middle row that leaves the rightmost cell open
+        type N = i64;
+
+        fn position(n: N) -> (i64, i64) {
+            (n % 5, n / 5)
+        }
+
+        fn successors(n: &AStarNode<N>) -> Vec<(N, f64)> {
+            let (x, y) = position(n.node);
+            let mut result = Vec::new();
+            for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                let (nx, ny) = (x + dx, y + dy);
+                if (0..5).contains(&nx) && (0..5).contains(&ny) && !(ny == 2 && nx < 4) {
+                    result.push((ny * 5 + nx, f64::hypot(dx as f64, dy as f64)));
+                }
+            }
+            result
+        }
+
+        fn distance(a: N, b: N) -> f64 {
+            let ((ax, ay), (bx, by)) = (position(a), position(b));
+            f64::hypot((ax - bx) as f64, (ay - by) as f64)
+        }
+
+        fn cost(path: &VecDeque<N>) -> f64 {
+            path.iter().zip(path.iter().skip(1)).map(|(a, b)| distance(*a, *b)).sum()
+        }
+
+        #[test]
+        fn around_wall() {
+            let forward_heuristic = |n: &N| distance(*n, 22);
+            let backward_heuristic = |n: &N| distance(*n, 2);
+            let result = bidirectional_a_star(
+                SearchDirection {
+                    start: Vec::from([2]),
+                    heuristic: &forward_heuristic,
+                    successors: &successors,
+                },
+                SearchDirection {
+                    start: Vec::from([22]),
+                    heuristic: &backward_heuristic,
+                    successors: &successors,
+                },
+                &|n| *n,
+                &|_, _| Some(0.),
+                None,
+                None,
+                None,
+            )
+            .path
+            .unwrap();
+            let expected = a_star(Vec::from([2]), &forward_heuristic, &successors, &|n| *n == 22, None).unwrap();
+            assert_eq!((result.front(), result.back()), (Some(&2), Some(&22)));
+            assert!(result.contains(&14));
+            assert!((cost(&result) - cost(&expected)).abs() < 1e-9);
+        }
+
+        #[test]
+        fn multiple_targets() {
+            // The backward search starts from all targets, the path ends at the closest one
+            let targets = [20, 24];
+            let forward_heuristic = |n: &N| targets.iter().map(|t| distance(*n, *t)).fold(f64::INFINITY, f64::min);
+            let backward_heuristic = |n: &N| distance(*n, 4);
+            let result = bidirectional_a_star(
+                SearchDirection {
+                    start: Vec::from([4]),
+                    heuristic: &forward_heuristic,
+                    successors: &successors,
+                },
+                SearchDirection {
+                    start: Vec::from(targets),
+                    heuristic: &backward_heuristic,
+                    successors: &successors,
+                },
+                &|n| *n,
+                &|_, _| Some(0.),
+                None,
+                None,
+                None,
+            );
+            assert_eq!(result.path, Some(VecDeque::from([4, 9, 14, 19, 24])));
+        }
+
+        #[test]
+        fn joins() {
+            // Paths may not meet at the cells of the wall's column
+            let forward_heuristic = |n: &N| distance(*n, 22);
+            let backward_heuristic = |n: &N| distance(*n, 2);
+            let result = bidirectional_a_star(
+                SearchDirection {
+                    start: Vec::from([2]),
+                    heuristic: &forward_heuristic,
+                    successors: &successors,
+                },
+                SearchDirection {
+                    start: Vec::from([22]),
+                    heuristic: &backward_heuristic,
+                    successors: &successors,
+                },
+                &|n| *n,
+                &|f, _| (position(*f).0 != 4).then_some(0.),
+                None,
+                None,
+                None,
+            )
+            .path
+            .unwrap();
+            assert_eq!((result.front(), result.back()), (Some(&2), Some(&22)));
+            assert!(result.iter().zip(result.iter().skip(1)).all(|(a, b)| distance(*a, *b) < 1.5));
+        }
+
+        #[test]
+        fn unreachable() {
+            let closed = |n: &AStarNode<N>| Vec::from_iter(successors(n).into_iter().filter(|(m, _)| *m != 14));
+            let forward_heuristic = |n: &N| distance(*n, 22);
+            let backward_heuristic = |n: &N| distance(*n, 2);
+            let result = bidirectional_a_star(
+                SearchDirection {
+                    start: Vec::from([2]),
+                    heuristic: &forward_heuristic,
+                    successors: &closed,
+                },
+                SearchDirection {
+                    start: Vec::from([22]),
+                    heuristic: &backward_heuristic,
+                    successors: &closed,
+                },
+                &|n| *n,
+                &|_, _| Some(0.),
+                None,
+                None,
+                None,
+            );
+            assert_eq!(result.path, None);
+            assert!(!result.limit_reached);
+        }
+
+        #[test]
+        fn limited() {
+            let forward_heuristic = |n: &N| distance(*n, 22);
+            let backward_heuristic = |n: &N| distance(*n, 2);
+            let result = bidirectional_a_star(
+                SearchDirection {
+                    start: Vec::from([2]),
+                    heuristic: &forward_heuristic,
+                    successors: &successors,
+                },
+                SearchDirection {
+                    start: Vec::from([22]),
+                    heuristic: &backward_heuristic,
+                    successors: &successors,
+                },
+                &|n| *n,
+                &|_, _| Some(0.),
+                None,
+                Some(2),
+                None,
+            );
+            assert_eq!(result.path, None);
+            assert!(result.limit_reached);
+            assert_eq!(result.explored.len(), 2);
+        }
     }
 }
@@ -0,0 +1,1188 @@
+// Partial results are returned as errors (see board_router)
+#![allow(clippy::result_large_err)]
+
+use core::f64;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+};
+
+use crate::{
+    board_router::{
+        port_point, report_connections, BoardRouterOutput, BoardRouterOutputBoard,
+        BoardRouterOutputConnection, BoardRouterOutputError, BoardSide, Channel, ConnectionID,
+        ConnectionOrdering, ConnectionStatistics, FailureReason, Heuristic, KeepOut, Layout,
+        MultiPortRouting, Point, Port, RouteInput, RouteInputConnection, RoutingFailure,
+        RoutingSetting, RoutingStatistics, SearchEffort, SearchLimits,
+    },
+    graph_search::{lazy_a_star_limited, now_ms, AStarNode},
+    smoothing::{segments_distance, simplify},
+};
+
+// Relative tolerance of the clearance checks and of the alignment of points
+const TOLERANCE: f64 = 1e-9;
+
+// Corners around obstacles lie this much (relative to their distance) outside the clearance
+const CORNER_MARGIN: f64 = 1e-6;
+
+// Routings of the connections in different orders
+const MAX_PASSES: usize = 3;
+
+// Buckets around a point (in each direction) whose corners are candidates for the next corner of a channel
+const VISIBILITY_RANGE: usize = 5;
+
+// Largest number of obstacle buckets along a side of the board
+const MAX_BUCKETS: usize = 256;
+
+// Geometry the channels keep their clearance to
+#[derive(Debug, Clone)]
+enum Shape {
+    Capsule(Point, Point, f64), // all points within the radius of the line segment (a circle if both ends coincide)
+    Zone(KeepOut, Vec<Point>),  // a keep-out zone with the corners of its outline
+}
+
+impl Shape {
+    fn circle(center: Point, radius: f64) -> Shape {
+        Shape::Capsule(center, center, radius)
+    }
+
+    fn from_keep_out(keep_out: &KeepOut) -> Shape {
+        match keep_out {
+            KeepOut::Rectangle {
+                position: [x, y],
+                dimensions: [width, height],
+            } => Shape::Zone(
+                keep_out.clone(),
+                Vec::from([
+                    [*x, *y],
+                    [x + width, *y],
+                    [x + width, y + height],
+                    [*x, y + height],
+                ]),
+            ),
+            KeepOut::Circle { center, radius } => Shape::circle(*center, *radius),
+            KeepOut::Polygon(points) => Shape::Zone(keep_out.clone(), points.clone()),
+        }
+    }
+
+    // Distance of the line segment a-b to the shape, 0 if they overlap
+    fn distance(&self, a: Point, b: Point) -> f64 {
+        match self {
+            Shape::Capsule(p, q, radius) => (segments_distance(a, b, *p, *q) - radius).max(0.),
+            Shape::Zone(keep_out, corners) => {
+                if keep_out.distance(a) == 0. || keep_out.distance(b) == 0. {
+                    return 0.;
+                }
+                (0..corners.len())
+                    .map(|i| segments_distance(a, b, corners[i], corners[(i + 1) % corners.len()]))
+                    .fold(f64::INFINITY, f64::min)
+            }
+        }
+    }
+
+    // Points (with a radius) the outline of the shape bends around
+    fn vertices(&self) -> Vec<(Point, f64)> {
+        match self {
+            Shape::Capsule(p, q, radius) if p == q => Vec::from([(*p, *radius)]),
+            Shape::Capsule(p, q, radius) => Vec::from([(*p, *radius), (*q, *radius)]),
+            Shape::Zone(_, corners) => Vec::from_iter(corners.iter().map(|corner| (*corner, 0.))),
+        }
+    }
+
+    fn bounding_box(&self) -> (Point, Point) {
+        match self {
+            Shape::Capsule(p, q, radius) => (
+                [p[0].min(q[0]) - radius, p[1].min(q[1]) - radius],
+                [p[0].max(q[0]) + radius, p[1].max(q[1]) + radius],
+            ),
+            Shape::Zone(keep_out, _) => keep_out.bounding_box(),
+        }
+    }
+}
+
+struct Obstacle {
+    shape: Shape,
+    clearance: f64, // distance the center line of the channel keeps to the shape
+    owner: Option<ConnectionID>, // the connection of a port or channel
+    bounding_box: (Point, Point), // of the shape grown by the clearance
+}
+
+impl Obstacle {
+    fn new(shape: Shape, clearance: f64, owner: Option<ConnectionID>) -> Obstacle {
+        let ([min_x, min_y], [max_x, max_y]) = shape.bounding_box();
+        Obstacle {
+            shape,
+            clearance,
+            owner,
+            bounding_box: (
+                [min_x - clearance, min_y - clearance],
+                [max_x + clearance, max_y + clearance],
+            ),
+        }
+    }
+
+    fn blocks(&self, a: Point, b: Point, tolerance: f64) -> bool {
+        let ([min_x, min_y], [max_x, max_y]) = self.bounding_box;
+        if a[0].max(b[0]) < min_x
+            || a[0].min(b[0]) > max_x
+            || a[1].max(b[1]) < min_y
+            || a[1].min(b[1]) > max_y
+        {
+            return false;
+        }
+        self.shape.distance(a, b) < self.clearance - tolerance
+    }
+}
+
+// Obstacles by the square buckets their bounding boxes overlap, so that a line only meets the obstacles of the buckets
+// it passes
+struct Buckets {
+    origin: Point,
+    size: f64,
+    buckets_x: usize,
+    buckets_y: usize,
+    obstacles: Vec<Vec<usize>>,
+}
+
+impl Buckets {
+    fn new(obstacles: &[Obstacle], (origin, [max_x, max_y]): (Point, Point)) -> Buckets {
+        let (width, height) = (max_x - origin[0], max_y - origin[1]);
+        // About one obstacle per bucket, with at most MAX_BUCKETS along each side
+        let size = (width * height / obstacles.len().max(1) as f64)
+            .sqrt()
+            .max(width.max(height) / MAX_BUCKETS as f64);
+        let buckets_x = ((width / size).ceil() as usize).max(1);
+        let buckets_y = ((height / size).ceil() as usize).max(1);
+        let mut buckets = Buckets {
+            origin,
+            size,
+            buckets_x,
+            buckets_y,
+            obstacles: vec![Vec::new(); buckets_x * buckets_y],
+        };
+        for (i, obstacle) in obstacles.iter().enumerate() {
+            let (min, max) = obstacle.bounding_box;
+            let (from_x, from_y) = buckets.bucket(min);
+            let (to_x, to_y) = buckets.bucket(max);
+            for x in from_x..=to_x {
+                for y in from_y..=to_y {
+                    buckets.obstacles[x * buckets_y + y].push(i);
+                }
+            }
+        }
+        buckets
+    }
+
+    // The bucket of the point, the closest one for points outside
+    fn bucket(&self, [x, y]: Point) -> (usize, usize) {
+        let index = |p: f64, origin: f64, buckets: usize| {
+            (((p - origin) / self.size).floor().max(0.) as usize).min(buckets - 1)
+        };
+        (
+            index(x, self.origin[0], self.buckets_x),
+            index(y, self.origin[1], self.buckets_y),
+        )
+    }
+
+    // Obstacles of the buckets the line segment a-b passes (some more than once)
+    fn obstacles(&self, a: Point, b: Point) -> impl Iterator<Item = &usize> {
+        let (mut x, mut y) = self.bucket(a);
+        let end = self.bucket(b);
+        let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+        // Distance (as a fraction of the segment) to the next bucket boundary along each axis, and between two
+        // boundaries
+        let boundary = |p: f64, d: f64, index: usize, origin: f64| {
+            if d == 0. {
+                (f64::INFINITY, f64::INFINITY)
+            } else {
+                let next = origin + (index + (d > 0.) as usize) as f64 * self.size;
+                ((next - p) / d, self.size / d.abs())
+            }
+        };
+        let (mut t_x, delta_x) = boundary(a[0], dx, x, self.origin[0]);
+        let (mut t_y, delta_y) = boundary(a[1], dy, y, self.origin[1]);
+        let mut buckets = Vec::from([(x, y)]);
+        while (x, y) != end && buckets.len() <= self.buckets_x + self.buckets_y {
+            if t_x < t_y {
+                t_x += delta_x;
+                x = if dx > 0. {
+                    (x + 1).min(self.buckets_x - 1)
+                } else {
+                    x.saturating_sub(1)
+                };
+            } else {
+                t_y += delta_y;
+                y = if dy > 0. {
+                    (y + 1).min(self.buckets_y - 1)
+                } else {
+                    y.saturating_sub(1)
+                };
+            }
+            buckets.push((x, y));
+        }
+        buckets
+            .into_iter()
+            .flat_map(move |(x, y)| self.obstacles[x * self.buckets_y + y].iter())
+    }
+}
+
+// Obstacles of the connection being routed and the visibility graph around them
+struct Search {
+    layout: Layout,
+    obstacles: Vec<Obstacle>,
+    buckets: Buckets,
+    bounds: (Point, Point), // the area of the board the channels keep to
+    tolerance: f64,
+    corners: Vec<Point>, // free points around the obstacles, the nodes of the visibility graph
+}
+
+impl Search {
+    fn new(
+        layout: Layout,
+        obstacles: Vec<Obstacle>,
+        bounds: (Point, Point),
+        board: (Point, Point),
+        tolerance: f64,
+    ) -> Search {
+        // Octagons (squares for rectilinear channels) around the vertices of the obstacles have their sides along the
+        // channel directions
+        let (sides, first_angle, stretch) = match layout {
+            Layout::Rectilinear => (4, f64::consts::FRAC_PI_4, f64::consts::SQRT_2),
+            Layout::Octilinear => (8, f64::consts::PI / 8., 1. / (f64::consts::PI / 8.).cos()),
+        };
+        let mut corners = Vec::new();
+        for obstacle in obstacles.iter() {
+            for (center, radius) in obstacle.shape.vertices() {
+                let distance =
+                    (radius + obstacle.clearance) * stretch * (1. + CORNER_MARGIN) + tolerance;
+                for k in 0..sides {
+                    let angle = first_angle + k as f64 * 2. * f64::consts::PI / sides as f64;
+                    corners.push([
+                        center[0] + distance * angle.cos(),
+                        center[1] + distance * angle.sin(),
+                    ]);
+                }
+            }
+        }
+        let mut search = Search {
+            layout,
+            buckets: Buckets::new(&obstacles, board),
+            obstacles,
+            bounds,
+            tolerance,
+            corners: Vec::new(),
+        };
+        // Joints of channel segments share their corners
+        let mut seen = HashSet::new();
+        corners.retain(|corner| seen.insert(corner.map(f64::to_bits)));
+        search.corners = Vec::from_iter(
+            corners
+                .into_iter()
+                .filter(|&corner| search.blocker(corner, corner).is_none()),
+        );
+        search
+    }
+
+    // The first obstacle (or the board edge, with the owner None) the line segment a-b comes too close to
+    fn blocker(&self, a: Point, b: Point) -> Option<Option<ConnectionID>> {
+        let ([min_x, min_y], [max_x, max_y]) = self.bounds;
+        let inside = |[x, y]: Point| {
+            min_x - self.tolerance <= x
+                && x <= max_x + self.tolerance
+                && min_y - self.tolerance <= y
+                && y <= max_y + self.tolerance
+        };
+        if !inside(a) || !inside(b) {
+            return Some(None);
+        }
+        self.inner_blocker(a, b)
+    }
+
+    // Like blocker, but ignoring the board edge (e.g., for the opening of an edge port)
+    fn inner_blocker(&self, a: Point, b: Point) -> Option<Option<ConnectionID>> {
+        self.buckets
+            .obstacles(a, b)
+            .map(|&i| &self.obstacles[i])
+            .find(|obstacle| obstacle.blocks(a, b, self.tolerance))
+            .map(|obstacle| obstacle.owner)
+    }
+
+    fn distance(&self, a: Point, b: Point) -> f64 {
+        let (dx, dy) = ((b[0] - a[0]).abs(), (b[1] - a[1]).abs());
+        match self.layout {
+            Layout::Rectilinear => dx + dy,
+            Layout::Octilinear => dx.max(dy) + (f64::consts::SQRT_2 - 1.) * dx.min(dy),
+        }
+    }
+
+    // Channels from a to b along the layout directions: a single line (no bend) where it suffices, else two lines with
+    // either of two bends
+    fn bends(&self, a: Point, b: Point) -> impl Iterator<Item = Option<Point>> {
+        let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+        let (sx, sy) = (dx.signum(), dy.signum());
+        let (adx, ady) = (dx.abs(), dy.abs());
+        let aligned = adx <= self.tolerance
+            || ady <= self.tolerance
+            || (matches!(self.layout, Layout::Octilinear) && (adx - ady).abs() <= self.tolerance);
+        let bends = match self.layout {
+            _ if aligned => [None, None],
+            Layout::Rectilinear => [Some([b[0], a[1]]), Some([a[0], b[1]])],
+            // The diagonal first or the straight line first
+            Layout::Octilinear if adx > ady => {
+                [Some([a[0] + sx * ady, b[1]]), Some([b[0] - sx * ady, a[1]])]
+            }
+            Layout::Octilinear => [Some([b[0], a[1] + sy * adx]), Some([a[0], b[1] - sy * adx])],
+        };
+        bends.into_iter().take(if aligned { 1 } else { 2 })
+    }
+
+    // Channel from a to b along the layout directions, with a bend (returned if any) where a single line does not
+    // suffice
+    fn connect(&self, a: Point, b: Point) -> Option<Option<Point>> {
+        self.bends(a, b).find(|&bend| match bend {
+            None => self.blocker(a, b).is_none(),
+            Some(bend) => self.blocker(a, bend).is_none() && self.blocker(bend, b).is_none(),
+        })
+    }
+
+    // Index of the layout direction (or opposite direction) of the line from a to b, of the signs of its components
+    fn direction(&self, a: Point, b: Point) -> usize {
+        let sign = |d: f64| {
+            if d.abs() <= self.tolerance {
+                1
+            } else {
+                (d.signum() + 1.) as usize
+            }
+        };
+        sign(b[0] - a[0]) * 3 + sign(b[1] - a[1])
+    }
+
+    // Length of the line from the point in the given direction (see direction) up to which no obstacle or the board
+    // edge blocks it, overestimated by at most a thousandth of the board
+    fn free_length(&self, p: Point, direction: usize) -> f64 {
+        let ([min_x, min_y], [max_x, max_y]) = self.bounds;
+        let (x, y) = ((direction / 3) as f64 - 1., (direction % 3) as f64 - 1.);
+        let norm = x.hypot(y);
+        let end = |length: f64| [p[0] + length * x / norm, p[1] + length * y / norm];
+        // Lines longer than the diagonal of the board leave it
+        let (mut free, mut blocked) = (0., (max_x - min_x).hypot(max_y - min_y) + self.tolerance);
+        let precision = blocked / 1000.;
+        while blocked - free > precision {
+            let length = (free + blocked) / 2.;
+            if self.blocker(p, end(length)).is_none() {
+                free = length;
+            } else {
+                blocked = length;
+            }
+        }
+        blocked
+    }
+
+    // Shortest channel from the start to any of the targets through the corners of the visibility graph, the points
+    // explored on the way, and whether the search stopped at a search limit without a channel
+    fn find_channel(
+        &self,
+        start: Point,
+        targets: &[Point],
+        settings: &SearchSettings,
+        c_id: ConnectionID,
+    ) -> (Option<Channel>, Vec<Point>, bool) {
+        let limits = settings.limits;
+        let points = Vec::from_iter(
+            [start]
+                .into_iter()
+                .chain(targets.iter().copied())
+                .chain(self.corners.iter().copied()),
+        );
+        // Corners by bucket; a point sees the corners of the buckets around it and the targets
+        let mut bucket_corners = vec![Vec::new(); self.buckets.obstacles.len()];
+        for (n, &point) in points.iter().enumerate().skip(targets.len() + 1) {
+            let (x, y) = self.buckets.bucket(point);
+            bucket_corners[x * self.buckets.buckets_y + y].push(n);
+        }
+        let bucket_corners = &bucket_corners;
+        // Corners out of the bounding box of the start and the targets (grown by the region margin) are not visited
+        let region = limits.region_margin.map(|margin| {
+            targets.iter().fold(
+                (
+                    [start[0] - margin, start[1] - margin],
+                    [start[0] + margin, start[1] + margin],
+                ),
+                |(min, max), t| {
+                    (
+                        [min[0].min(t[0] - margin), min[1].min(t[1] - margin)],
+                        [max[0].max(t[0] + margin), max[1].max(t[1] + margin)],
+                    )
+                },
+            )
+        });
+        let clipped = Cell::new(false);
+        let in_region = |p: Point| {
+            let inside = region.is_none_or(|(min, max)| {
+                p[0] >= min[0] && p[0] <= max[0] && p[1] >= min[1] && p[1] <= max[1]
+            });
+            if !inside {
+                clipped.set(true);
+            }
+            inside
+        };
+        let (buckets_x, buckets_y) = (self.buckets.buckets_x, self.buckets.buckets_y);
+        // The edges to the candidates are only checked when the search reaches them
+        let is_edge = |a: &usize, b: &usize| self.connect(points[*a], points[*b]).is_some();
+        // Free lengths by point and direction, computed once as the searches need them for many candidates
+        let free_lengths = vec![[(); 9].map(|_| Cell::new(f64::NAN)); points.len()];
+        let free_length = |n: usize, towards: Point| {
+            let free_length = &free_lengths[n][self.direction(points[n], towards)];
+            if free_length.get().is_nan() {
+                free_length.set(self.free_length(points[n], self.direction(points[n], towards)));
+            }
+            free_length.get() >= f64::hypot(towards[0] - points[n][0], towards[1] - points[n][1])
+        };
+        // Whether the lines of a channel from a to b are within the free lengths from their ends, which rules out most
+        // candidates without checking their edges
+        let may_be_edge = |a: usize, b: usize| {
+            self.bends(points[a], points[b]).any(|bend| match bend {
+                None => free_length(a, points[b]),
+                Some(bend) => free_length(a, bend) && free_length(b, bend),
+            })
+        };
+        // Estimates by point and direction, computed once as the searches below push the points many times
+        let estimates = [(); 2].map(|_| vec![Cell::new(f64::NAN); points.len()]);
+        let search = |complete: bool, backward: bool| {
+            let (ends, starts) = if backward {
+                (0..1, 1..targets.len() + 1)
+            } else {
+                (1..targets.len() + 1, 0..1)
+            };
+            let heuristic = |n: &usize| {
+                let estimate = &estimates[backward as usize][*n];
+                if estimate.get().is_nan() {
+                    estimate.set(
+                        ends.clone()
+                            .map(|end| {
+                                let [dx, dy] = [
+                                    points[end][0] - points[*n][0],
+                                    points[end][1] - points[*n][1],
+                                ];
+                                settings.heuristic.distance(self.layout, dx.abs(), dy.abs())
+                            })
+                            .fold(f64::INFINITY, f64::min),
+                    );
+                }
+                estimate.get()
+            };
+            let is_target = |n: &usize| ends.contains(n);
+            let successors = |a: &AStarNode<usize>| -> Vec<(usize, f64)> {
+                let from = points[a.node];
+                let (x, y) = self.buckets.bucket(from);
+                let (xs, ys) = if complete {
+                    (0..buckets_x, 0..buckets_y)
+                } else {
+                    (
+                        x.saturating_sub(VISIBILITY_RANGE)
+                            ..(x + VISIBILITY_RANGE + 1).min(buckets_x),
+                        y.saturating_sub(VISIBILITY_RANGE)
+                            ..(y + VISIBILITY_RANGE + 1).min(buckets_y),
+                    )
+                };
+                let nearby = xs.flat_map(|x| {
+                    ys.clone()
+                        .flat_map(move |y| bucket_corners[x * buckets_y + y].iter().copied())
+                });
+                Vec::from_iter(
+                    ends.clone()
+                        .chain(nearby)
+                        .filter(|&n| n != a.node && in_region(points[n]) && may_be_edge(a.node, n))
+                        .map(|n| (n, self.distance(from, points[n]))),
+                )
+            };
+            let max_expansions = limits.expansion_limit(settings.expansions_left.get());
+            let search_start = now_ms();
+            let result = lazy_a_star_limited(
+                Vec::from_iter(starts.clone()),
+                &heuristic,
+                &successors,
+                &is_edge,
+                &is_target,
+                settings.heuristic_bias,
+                max_expansions,
+                settings.deadline,
+            );
+            if let Some(left) = settings.expansions_left.get() {
+                settings
+                    .expansions_left
+                    .set(Some(left.saturating_sub(result.explored.len())));
+            }
+            let mut efforts = settings.efforts.borrow_mut();
+            let effort = efforts.entry(c_id).or_default();
+            effort.expansions += result.explored.len();
+            effort.time_ms += now_ms() - search_start;
+            result
+        };
+        // The corners of the nearby buckets usually suffice; when they don't, the search is repeated from the targets
+        // and then on the complete visibility graph from the side that reached fewer points, so that a connection only
+        // fails when no channel exists within the search region
+        let mut result = search(false, false);
+        if result.path.is_none() && !result.limit_reached {
+            let backward = search(false, true);
+            let mut complete = match backward.path {
+                Some(_) => backward,
+                None if backward.limit_reached => backward,
+                None => search(true, backward.explored.len() < result.explored.len()),
+            };
+            complete.explored.extend(result.explored);
+            result = complete;
+        }
+        let explored = Vec::from_iter(result.explored.into_iter().map(|n| points[n]));
+        let Some(mut path) = result.path else {
+            return (None, explored, result.limit_reached || clipped.get());
+        };
+        if path[0] != 0 {
+            path.make_contiguous().reverse();
+        }
+        let mut channel = Channel::from([start]);
+        for n in path.into_iter().skip(1) {
+            let from = *channel.last().unwrap();
+            if let Some(Some(bend)) = self.connect(from, points[n]) {
+                channel.push(bend);
+            }
+            channel.push(points[n]);
+        }
+        (Some(simplify(&channel)), explored, false)
+    }
+}
+
+// Search limits, heuristic and heuristic bias of the path searches, with the bookkeeping shared by the searches of all
+// passes
+struct SearchSettings<'a> {
+    limits: &'a SearchLimits,
+    heuristic: Heuristic,
+    heuristic_bias: Option<f64>,
+    expansions_left: Cell<Option<usize>>, // of the total budget
+    deadline: Option<f64>,                // of the path searches, see now_ms
+    efforts: RefCell<HashMap<ConnectionID, SearchEffort>>, // of the path searches by connection
+}
+
+// Where the channels of a port end: the port center, or for edge ports the point inside the board the channel leaves
+// the opening from, with the center of the opening
+fn port_ends(input: &RouteInput, port: &Port, margin: f64) -> (Point, Option<Point>) {
+    let center = port_point(input, port);
+    let Some(edge_port) = input.edge_ports.iter().find(|e| e.port == *port) else {
+        return (center, None);
+    };
+    let inside = match edge_port.side {
+        BoardSide::Top => [center[0], center[1] + margin],
+        BoardSide::Right => [center[0] - margin, center[1]],
+        BoardSide::Bottom => [center[0], center[1] - margin],
+        BoardSide::Left => [center[0] + margin, center[1]],
+    };
+    (inside, Some(center))
+}
+
+// First setting of the input this backend does not support, if any
+pub(crate) fn unsupported_setting(input: &RouteInput) -> Option<RoutingSetting> {
+    let cost_model = input.cost_model;
+    [
+        (
+            input.layers.is_some_and(|layers| layers > 1),
+            RoutingSetting::Layers,
+        ),
+        (input.rip_up_reroute.is_some(), RoutingSetting::RipUpReroute),
+        (
+            !matches!(input.ordering, ConnectionOrdering::Default),
+            RoutingSetting::Ordering,
+        ),
+        (
+            !input.length_match_groups.is_empty(),
+            RoutingSetting::LengthMatching,
+        ),
+        (
+            input
+                .connections
+                .iter()
+                .any(|c| c.target_resistance.is_some()),
+            RoutingSetting::TargetResistance,
+        ),
+        (
+            input.connections.iter().any(|c| c.serpentine.is_some()),
+            RoutingSetting::Serpentine,
+        ),
+        (input.smoothing.is_some(), RoutingSetting::Smoothing),
+        (
+            [
+                cost_model.bend_45,
+                cost_model.bend_90,
+                cost_model.direction_change,
+                cost_model.adjacency,
+            ] != [0.; 4],
+            RoutingSetting::CostModel,
+        ),
+        (input.cell_size.is_some(), RoutingSetting::CellSize),
+        (input.grid_snapshots, RoutingSetting::GridSnapshots),
+        (
+            input.bidirectional_search,
+            RoutingSetting::BidirectionalSearch,
+        ),
+    ]
+    .into_iter()
+    .find_map(|(unsupported, setting)| unsupported.then_some(setting))
+}
+
+// Connections with more than two ports or a branch port are routed as a star or a tree
+fn is_multi_port(c: &RouteInputConnection) -> bool {
+    c.ports.len() > 2 || c.branch_port.is_some()
+}
+
+// Routes the connections one by one on visibility graphs of the board geometry instead of the routing grid; every
+// channel keeps its clearance to the ports of other connections, keep-outs, blocked ports and the channels routed
+// before it
+pub fn route(input: &RouteInput, deadline: Option<f64>) -> BoardRouterOutput {
+    let port_definitions = input.port_definitions();
+    if let Some((c_id, port)) = input.undefined_port(&port_definitions) {
+        return Err(BoardRouterOutputError::UndefinedPort(c_id, port));
+    }
+    let is_locked = |c_id: ConnectionID| input.locked_connections.iter().any(|(id, _)| *id == c_id);
+    let mut connections = Vec::from_iter(input.connections.iter().filter(|c| !is_locked(c.id)));
+    if connections.is_empty() && input.locked_connections.is_empty() {
+        return Err(BoardRouterOutputError::NoInputConnections);
+    }
+
+    let port_radius = |port: &Port| {
+        port_definitions
+            .iter()
+            .find(|d| d.port == *port)
+            .map_or(input.port_diameter, |d| d.diameter)
+            / 2.
+    };
+    let span = |c: &RouteInputConnection| {
+        let [ax, ay] = port_point(input, &c.ports[0]);
+        let [bx, by] = port_point(input, &c.ports[c.ports.len() - 1]);
+        f64::hypot(bx - ax, by - ay)
+    };
+    // Multi-port connections (with the most ports first), then by ascending distance
+    connections.sort_by(|a, b| {
+        (is_multi_port(b), b.ports.len())
+            .cmp(&(is_multi_port(a), a.ports.len()))
+            .then_with(|| span(a).total_cmp(&span(b)))
+    });
+
+    let settings = SearchSettings {
+        limits: &input.search_limits,
+        heuristic: input.heuristic,
+        heuristic_bias: input.heuristic_bias,
+        expansions_left: Cell::new(input.search_limits.max_total_expansions),
+        deadline,
+        efforts: RefCell::new(HashMap::new()),
+    };
+    // Connections that fail are moved to the front of the order, and the routing with the most routed channels is kept
+    let mut best = route_in_order(input, &connections, &port_radius, &settings);
+    for _ in 1..MAX_PASSES {
+        if best.failures.is_empty() {
+            break;
+        }
+        let (mut order, rest): (Vec<&RouteInputConnection>, Vec<&RouteInputConnection>) =
+            connections
+                .iter()
+                .partition(|c| best.failures.iter().any(|f| f.connection == c.id));
+        order.extend(rest);
+        let routing = route_in_order(input, &order, &port_radius, &settings);
+        if routing.successful > best.successful {
+            best = routing;
+        }
+        connections = order;
+    }
+    let Routing {
+        connections: routed,
+        failures,
+        successful,
+        total,
+    } = best;
+
+    // Connections are routed in the order of the output, after the locked ones
+    let efforts = settings.efforts.take();
+    let n_locked = input.locked_connections.len();
+    let statistics = RoutingStatistics {
+        connections: Vec::from_iter(routed.iter().enumerate().map(|(i, (c_id, _))| {
+            let effort = efforts.get(c_id).copied().unwrap_or_default();
+            ConnectionStatistics {
+                connection: *c_id,
+                order: i.checked_sub(n_locked),
+                expansions: effort.expansions,
+                time_ms: effort.time_ms,
+                ..Default::default()
+            }
+        })),
+        expansions: efforts.values().map(|effort| effort.expansions).sum(),
+        ..Default::default()
+    };
+
+    let mut output = BoardRouterOutputBoard {
+        connections: routed,
+        layers: Vec::new(),
+        channel_widths: Vec::new(),
+        lengths: Vec::new(),
+        resistances: Vec::new(),
+        bend_radii: Vec::new(),
+        assigned_ports: Vec::new(),
+        failures,
+        setup_grid: None,
+        routed_grid: None,
+        statistics,
+    };
+    report_connections(input, &mut output);
+
+    if successful == total {
+        Ok(output)
+    } else if successful == 0 && input.locked_connections.is_empty() {
+        Err(BoardRouterOutputError::NoConnectionsFound)
+    } else {
+        Err(BoardRouterOutputError::PartialResult(output))
+    }
+}
+
+struct Routing {
+    connections: Vec<BoardRouterOutputConnection>, // the locked connections and the routed ones
+    failures: Vec<RoutingFailure>,
+    successful: usize, // routed channels
+    total: usize,
+}
+
+// Routes the connections one after the other, each around the channels routed before it
+fn route_in_order(
+    input: &RouteInput,
+    connections: &[&RouteInputConnection],
+    port_radius: &dyn Fn(&Port) -> f64,
+    settings: &SearchSettings,
+) -> Routing {
+    let tolerance = TOLERANCE * input.board_width.max(input.board_height);
+    let mut routed = Vec::from_iter(input.locked_connections.iter().cloned());
+    let mut failures = Vec::<RoutingFailure>::new();
+    let mut successful = 0;
+    let mut total = 0;
+
+    for &input_connection in connections {
+        let c_id = input_connection.id;
+        let footprint = input.footprint(c_id);
+        let keep_out_clearance = footprint.half_width + footprint.spacing;
+
+        // Foreign ports, keep-outs and blocked ports, and the channels of other connections
+        let mut obstacles = Vec::new();
+        for other in input.connections.iter().filter(|c| c.id != c_id) {
+            for port in other.ports.iter().chain(other.branch_port.iter()) {
+                let shape = Shape::circle(port_point(input, port), port_radius(port));
+                obstacles.push(Obstacle::new(shape, keep_out_clearance, Some(other.id)));
+            }
+        }
+        for keep_out in input.keep_outs.iter() {
+            obstacles.push(Obstacle::new(
+                Shape::from_keep_out(keep_out),
+                keep_out_clearance,
+                None,
+            ));
+        }
+        for port in input.blocked_ports.iter() {
+            let shape = Shape::circle(port_point(input, port), port_radius(port));
+            obstacles.push(Obstacle::new(shape, keep_out_clearance, None));
+        }
+        for (other, channels) in routed.iter().filter(|(other, _)| *other != c_id) {
+            let clearance = footprint.clearance(&input.footprint(*other));
+            for segment in channels.iter().flat_map(|channel| channel.windows(2)) {
+                obstacles.push(Obstacle::new(
+                    Shape::Capsule(segment[0], segment[1], 0.),
+                    clearance,
+                    Some(*other),
+                ));
+            }
+        }
+        // Channels keep half of their spacing to the board edge
+        let margin = footprint.half_width + footprint.spacing / 2.;
+        let bounds = (
+            [margin, margin],
+            [input.board_width - margin, input.board_height - margin],
+        );
+        let board = ([0., 0.], [input.board_width, input.board_height]);
+        let search = Search::new(input.layout, obstacles, bounds, board, tolerance);
+
+        // Channel from any of the given points (of which the first one may leave the opening of an edge port) to the
+        // port, ending at its opening if it is an edge port
+        let mut channels = Vec::<Channel>::new();
+        let mut route_branch = |from: &[Point],
+                                from_opening: Option<Point>,
+                                port: &Port,
+                                failure_port: Option<Port>| {
+            total += 1;
+            let mut failure = RoutingFailure {
+                connection: c_id,
+                port: failure_port,
+                reason: FailureReason::NoJoinNode,
+                explored: Vec::new(),
+            };
+            if from.is_empty() {
+                failures.push(failure);
+                return None;
+            }
+            let (start, opening) = port_ends(input, port, margin);
+            let blocked = search
+                .blocker(start, start)
+                .or_else(|| opening.and_then(|opening| search.inner_blocker(opening, start)));
+            if let Some(owner) = blocked {
+                failure.reason = FailureReason::StartBlocked(owner);
+                failures.push(failure);
+                return None;
+            }
+            let targets = Vec::from_iter(
+                from.iter()
+                    .copied()
+                    .filter(|&point| search.blocker(point, point).is_none()),
+            );
+            let from_blocked =
+                from_opening.and_then(|opening| search.inner_blocker(opening, from[0]));
+            if targets.is_empty() || from_blocked.is_some() {
+                let owner = from_blocked
+                    .or_else(|| search.blocker(from[0], from[0]))
+                    .unwrap();
+                failure.reason = FailureReason::TargetBlocked(owner);
+                failures.push(failure);
+                return None;
+            }
+            // The channel is searched from the port, and runs towards it
+            let (channel, explored, limit_reached) =
+                search.find_channel(start, &targets, settings, c_id);
+            let Some(mut channel) = channel else {
+                failure.reason = if limit_reached {
+                    FailureReason::SearchLimitReached
+                } else {
+                    FailureReason::EmptySearchSpace
+                };
+                failure.explored = explored;
+                failures.push(failure);
+                return None;
+            };
+            channel.reverse();
+            channel.extend(opening);
+            if channel[0] == from[0] {
+                if let Some(opening) = from_opening {
+                    channel.insert(0, opening);
+                }
+            }
+            successful += 1;
+            Some(channel)
+        };
+
+        let ports = &input_connection.ports;
+        let ends = Vec::from_iter(ports.iter().map(|port| port_ends(input, port, margin)));
+        let n = ends.len() as f64;
+        let centroid = [
+            ends.iter().map(|(p, _)| p[0]).sum::<f64>() / n,
+            ends.iter().map(|(p, _)| p[1]).sum::<f64>() / n,
+        ];
+        if !is_multi_port(input_connection) {
+            // The channel runs from the first port to the second one
+            channels.extend(route_branch(&[ends[0].0], ends[0].1, &ports[1], None));
+        } else if matches!(
+            input.multi_port_routing,
+            MultiPortRouting::Star | MultiPortRouting::OptimizedStar
+        ) {
+            // The branch port, or the free point (the centroid or a corner around the obstacles) with the shortest
+            // estimated branches joins the star
+            let branch_length = |point: Point| {
+                ends.iter()
+                    .map(|(end, _)| search.distance(point, *end))
+                    .sum::<f64>()
+            };
+            let join = match input_connection.branch_port {
+                Some(branch_port) => Some(port_ends(input, &branch_port, margin).0),
+                None => [centroid]
+                    .into_iter()
+                    .chain(search.corners.iter().copied())
+                    .filter(|&point| search.blocker(point, point).is_none())
+                    .fold(None, |best: Option<Point>, point| match best {
+                        Some(best) if branch_length(best) <= branch_length(point) + tolerance => {
+                            Some(best)
+                        }
+                        _ => Some(point),
+                    }),
+            };
+            for port in ports.iter() {
+                channels.extend(route_branch(&Vec::from_iter(join), None, port, Some(*port)));
+            }
+        } else {
+            // The tree grows from the branch port or the port closest to the centroid, and the other ports join any
+            // point of it, the closest first
+            let root_port = input_connection.branch_port.unwrap_or_else(|| {
+                *ports
+                    .iter()
+                    .zip(ends.iter())
+                    .min_by(|(_, (a, _)), (_, (b, _))| {
+                        f64::hypot(a[0] - centroid[0], a[1] - centroid[1])
+                            .total_cmp(&f64::hypot(b[0] - centroid[0], b[1] - centroid[1]))
+                    })
+                    .unwrap()
+                    .0
+            });
+            let (root, root_opening) = port_ends(input, &root_port, margin);
+            let mut branches = Vec::from_iter((0..ports.len()).filter(|&i| ports[i] != root_port));
+            branches.sort_by(|&a, &b| {
+                search
+                    .distance(root, ends[a].0)
+                    .total_cmp(&search.distance(root, ends[b].0))
+            });
+            let mut tree = Vec::from([root]);
+            for i in branches {
+                // The first channel of the tree starts at the root
+                let from_opening = root_opening.filter(|_| channels.is_empty());
+                if let Some(channel) = route_branch(&tree, from_opening, &ports[i], Some(ports[i]))
+                {
+                    tree.extend(channel.iter().copied());
+                    channels.push(channel);
+                }
+            }
+        }
+        if !channels.is_empty() {
+            routed.push((c_id, channels));
+        }
+    }
+
+    Routing {
+        connections: routed,
+        failures,
+        successful,
+        total,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod shape {
+        use crate::board_router::KeepOut;
+
+        use super::super::Shape;
+
+        #[test]
+        fn distance() {
+            let port = Shape::circle([0., 0.], 100.);
+            assert_eq!(port.distance([-500., 300.], [500., 300.]), 200.);
+            assert_eq!(port.distance([-500., 50.], [500., 50.]), 0.);
+
+            let zone = Shape::from_keep_out(&KeepOut::Rectangle {
+                position: [0., 0.],
+                dimensions: [200., 100.],
+            });
+            assert_eq!(zone.distance([-300., 0.], [-100., 0.]), 100.);
+            assert_eq!(
+                zone.distance([300., 300.], [300., 400.]),
+                f64::hypot(100., 200.)
+            );
+            // Segments crossing the zone or lying inside it overlap it
+            assert_eq!(zone.distance([-100., 50.], [300., 50.]), 0.);
+            assert_eq!(zone.distance([50., 50.], [150., 50.]), 0.);
+        }
+    }
+
+    mod buckets {
+        use super::super::{Buckets, Obstacle, Shape};
+
+        #[test]
+        fn obstacles() {
+            // Ports on a pitch of 500 and a few channels across the board
+            let mut obstacles = Vec::new();
+            for x in 0..8 {
+                for y in 0..8 {
+                    let port =
+                        Shape::circle([500. + x as f64 * 1000., 500. + y as f64 * 1000.], 100.);
+                    obstacles.push(Obstacle::new(port, 150., None));
+                }
+            }
+            let channels = [
+                ([0., 2000.], [8000., 2000.]),
+                ([1000., 0.], [7000., 6000.]),
+                ([6000., 8000.], [6000., 1000.]),
+            ];
+            for (a, b) in channels {
+                obstacles.push(Obstacle::new(Shape::Capsule(a, b, 0.), 200., None));
+            }
+            let buckets = Buckets::new(&obstacles, ([0., 0.], [8000., 8000.]));
+            assert!(buckets.buckets_x > 1 && buckets.buckets_y > 1);
+
+            // Every obstacle a line comes too close to is among the obstacles of the buckets it passes
+            let points =
+                Vec::from_iter((0..=16).flat_map(|x| {
+                    (0..=16).map(move |y| [x as f64 * 500. + 7., y as f64 * 500. + 3.])
+                }));
+            for &a in points.iter().step_by(5) {
+                for &b in points.iter().step_by(3) {
+                    let candidates = Vec::from_iter(buckets.obstacles(a, b).copied());
+                    for (i, obstacle) in obstacles.iter().enumerate() {
+                        assert!(
+                            !obstacle.blocks(a, b, 0.) || candidates.contains(&i),
+                            "{:?} {:?} {}",
+                            a,
+                            b,
+                            i
+                        );
+                    }
+                }
+            }
+
+            // A short line only meets the obstacles around it
+            assert!(buckets.obstacles([450., 450.], [550., 550.]).count() < obstacles.len() / 4);
+        }
+    }
+
+    mod search {
+        use std::{
+            cell::{Cell, RefCell},
+            collections::HashMap,
+        };
+
+        use crate::board_router::{Heuristic, KeepOut, Layout, SearchLimits};
+
+        use super::super::{Obstacle, Search, SearchSettings, Shape, VISIBILITY_RANGE};
+
+        const TOLERANCE: f64 = 1e-6;
+
+        fn search(obstacles: Vec<Obstacle>, size: f64) -> Search {
+            Search::new(
+                Layout::Octilinear,
+                obstacles,
+                ([0., 0.], [size, size]),
+                ([0., 0.], [size, size]),
+                TOLERANCE,
+            )
+        }
+
+        fn settings(limits: &SearchLimits) -> SearchSettings<'_> {
+            SearchSettings {
+                limits,
+                heuristic: Heuristic::Layout,
+                heuristic_bias: None,
+                expansions_left: Cell::new(None),
+                deadline: None,
+                efforts: RefCell::new(HashMap::new()),
+            }
+        }
+
+        #[test]
+        fn free_length() {
+            let search = search(
+                Vec::from([Obstacle::new(Shape::circle([500., 500.], 50.), 50., None)]),
+                1000.,
+            );
+            let precision = f64::hypot(1000., 1000.) / 1000.;
+            let end = |p: [f64; 2], [dx, dy]: [f64; 2], length: f64| {
+                let norm = f64::hypot(dx, dy);
+                [p[0] + length * dx / norm, p[1] + length * dy / norm]
+            };
+
+            // Up to the clearance around the port, to the board edge, and along the diagonal to the port; the line
+            // is free up to the free length and blocked a little beyond it, so longer lines are not candidates
+            for (p, d, length) in [
+                ([100., 500.], [1., 0.], 300.),
+                ([100., 500.], [0., 1.], 500.),
+                ([100., 100.], [1., 1.], f64::hypot(400., 400.) - 100.),
+            ] {
+                let free_length = search.free_length(p, search.direction(p, end(p, d, 1.)));
+                assert!(
+                    free_length >= length && free_length <= length + precision,
+                    "{} {}",
+                    free_length,
+                    length
+                );
+                assert!(search
+                    .blocker(p, end(p, d, free_length - precision))
+                    .is_none());
+                assert!(search
+                    .blocker(p, end(p, d, free_length + precision))
+                    .is_some());
+            }
+        }
+
+        #[test]
+        fn complete_search() {
+            // A wall from the bottom edge separates the start from the target, and many small obstacles in the top
+            // right corner make the buckets small, so the channel around the wall leaves the visibility range of both
+            let mut obstacles = Vec::from([Obstacle::new(
+                Shape::from_keep_out(&KeepOut::Rectangle {
+                    position: [4900., 0.],
+                    dimensions: [200., 9000.],
+                }),
+                100.,
+                None,
+            )]);
+            for x in 0..20 {
+                for y in 0..20 {
+                    let shape = Shape::circle([9000. + x as f64 * 50., 9000. + y as f64 * 50.], 1.);
+                    obstacles.push(Obstacle::new(shape, 1., None));
+                }
+            }
+            let search = search(obstacles, 10000.);
+            let (start, target) = ([4000., 1000.], [6000., 1000.]);
+            let limits = SearchLimits::default();
+            let (channel, explored, limit_reached) =
+                search.find_channel(start, &[target], &settings(&limits), 0);
+            let channel = channel.unwrap();
+            assert!(!limit_reached);
+            assert_eq!((channel[0], channel[channel.len() - 1]), (start, target));
+            assert!(channel.iter().any(|p| p[1] > 9000.));
+            let range = (VISIBILITY_RANGE + 1) as f64 * search.buckets.size;
+            assert!(channel.iter().any(|p| [start, target]
+                .iter()
+                .all(|end| (p[1] - end[1]).abs() > range)));
+            assert!(!explored.is_empty());
+
+            // Within a region around the start and the target, there is no channel
+            let limits = SearchLimits {
+                region_margin: Some(2000.),
+                ..Default::default()
+            };
+            let (channel, _, limit_reached) =
+                search.find_channel(start, &[target], &settings(&limits), 0);
+            assert!(channel.is_none() && limit_reached);
+        }
+    }
+
+    mod port_ends {
+        use crate::board_router::{BoardSide, EdgePort, RouteInput};
+        use crate::tests::route_input;
+
+        use super::super::{port_ends, Obstacle, Search, Shape};
+
+        #[test]
+        fn edge_ports() {
+            let input = RouteInput {
+                board_width: 3000.,
+                board_height: 2000.,
+                pitch: 500.,
+                pitch_offset_x: 500.,
+                pitch_offset_y: 0.,
+                edge_ports: Vec::from([EdgePort {
+                    port: (9, 9),
+                    side: BoardSide::Top,
+                    position: 1500.,
+                    width: 300.,
+                }]),
+                ..route_input()
+            };
+
+            // Channels of a port end at its center, and those of an edge port leave its opening at the board edge
+            // from a point inside the board
+            assert_eq!(port_ends(&input, &(1, 2), 100.), ([1000., 1000.], None));
+            let (inside, opening) = port_ends(&input, &(9, 9), 100.);
+            assert_eq!((inside, opening), ([1500., 100.], Some([1500., 0.])));
+
+            // The line through the opening is outside the area the channels keep to, but may not cross an obstacle
+            let bounds = ([100., 100.], [2900., 1900.]);
+            let board = ([0., 0.], [3000., 2000.]);
+            let search = Search::new(input.layout, Vec::new(), bounds, board, 1e-6);
+            assert_eq!(search.blocker(opening.unwrap(), inside), Some(None));
+            assert_eq!(search.inner_blocker(opening.unwrap(), inside), None);
+            let obstacles =
+                Vec::from([Obstacle::new(Shape::circle([1500., 0.], 10.), 50., Some(1))]);
+            let search = Search::new(input.layout, obstacles, bounds, board, 1e-6);
+            assert_eq!(
+                search.inner_blocker(opening.unwrap(), inside),
+                Some(Some(1))
+            );
+        }
+    }
+}
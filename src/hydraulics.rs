@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+
+use crate::board_router::{BoardRouterOutputBoard, Channel, ConnectionID};
+
+// Hydraulic resistance of a straight rectangular channel with laminar flow (approximation for the shallow side h <= w,
+// error below 13% for square channels)
+pub fn rectangular_channel_resistance(length: f64, width: f64, height: f64, viscosity: f64) -> f64 {
+    let (w, h) = (width.max(height), width.min(height));
+    12. * viscosity * length / (w * h.powi(3) * (1. - 0.63 * h / w))
+}
+
+// Length of the channel along its center line
+pub fn channel_length(channel: &Channel) -> f64 {
+    channel
+        .windows(2)
+        .map(|w| f64::hypot(w[1][0] - w[0][0], w[1][1] - w[0][1]))
+        .sum()
+}
+
+pub fn channel_resistance(channel: &Channel, width: f64, height: f64, viscosity: f64) -> f64 {
+    rectangular_channel_resistance(channel_length(channel), width, height, viscosity)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ComputeResistancesInput {
+    pub connections: BoardRouterOutputBoard,
+    pub channel_width: f64, // width of connections without their own channel width
+    pub channel_height: f64,
+    pub viscosity: f64,
+}
+
+// Resistance of each channel of the connections, i.e., of each branch of multi-port connections
+#[derive(Serialize, Deserialize)]
+pub struct ComputeResistancesOutput(pub Vec<(ConnectionID, Vec<f64>)>);
+
+pub fn compute_resistances(input: ComputeResistancesInput) -> ComputeResistancesOutput {
+    ComputeResistancesOutput(
+        input
+            .connections
+            .connections
+            .iter()
+            .map(|(c_id, channels)| {
+                let width = input.connections.channel_width(*c_id, input.channel_width);
+                (
+                    *c_id,
+                    input
+                        .connections
+                        .center_lines(*c_id, channels)
+                        .iter()
+                        .map(|channel| {
+                            channel_resistance(channel, width, input.channel_height, input.viscosity)
+                        })
+                        .collect(),
+                )
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    mod rectangular_channel_resistance {
+        use crate::hydraulics::rectangular_channel_resistance;
+
+        #[test]
+        fn square() {
+            let resistance = rectangular_channel_resistance(1., 1., 1., 1.);
+            assert!((resistance - 12. / 0.37).abs() < 1e-9);
+        }
+
+        #[test]
+        fn shallow_side() {
+            assert_eq!(
+                rectangular_channel_resistance(1000., 100., 50., 1e-3),
+                rectangular_channel_resistance(1000., 50., 100., 1e-3)
+            );
+        }
+
+        #[test]
+        fn proportional_to_length() {
+            let short = rectangular_channel_resistance(1000., 100., 50., 1e-3);
+            let long = rectangular_channel_resistance(3000., 100., 50., 1e-3);
+            assert!((long - 3. * short).abs() < 1e-9 * long);
+        }
+    }
+
+    mod compute_resistances {
+        use crate::{
+            board_router::BoardRouterOutputBoard,
+            hydraulics::{
+                compute_resistances, rectangular_channel_resistance, ComputeResistancesInput,
+            },
+        };
+
+        #[test]
+        fn per_branch() {
+            let output = compute_resistances(ComputeResistancesInput {
+                connections: BoardRouterOutputBoard {
+                    connections: Vec::from([
+                        (0, Vec::from([Vec::from([[0., 0.], [300., 0.], [300., 400.]])])),
+                        (
+                            1,
+                            Vec::from([
+                                Vec::from([[0., 0.], [100., 0.]]),
+                                Vec::from([[0., 0.], [0., 200.]]),
+                            ]),
+                        ),
+                    ]),
+                    layers: Vec::new(),
+                    channel_widths: Vec::from([(1, 200.)]),
+                    lengths: Vec::new(),
+                    resistances: Vec::new(),
+                    bend_radii: Vec::new(),
+                    assigned_ports: Vec::new(),
+                    failures: Vec::new(),
+                    setup_grid: None,
+                    routed_grid: None,
+                    statistics: Default::default(),
+                },
+                channel_width: 100.,
+                channel_height: 50.,
+                viscosity: 1e-3,
+            });
+            let expected = [
+                (0, Vec::from([rectangular_channel_resistance(700., 100., 50., 1e-3)])),
+                (
+                    1,
+                    Vec::from([
+                        rectangular_channel_resistance(100., 200., 50., 1e-3),
+                        rectangular_channel_resistance(200., 200., 50., 1e-3),
+                    ]),
+                ),
+            ];
+            assert_eq!(output.0, expected);
+        }
+    }
+}
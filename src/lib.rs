@@ -2,7 +2,10 @@ pub mod board_router;
 mod board_router_wasm;
 pub mod dxf;
 mod graph_search;
+mod gridless_router;
+pub mod hydraulics;
 mod port_nomenclature;
+pub mod smoothing;
 mod validation;
 pub mod utils;
 
@@ -10,13 +13,73 @@ pub mod utils;
 #[cfg(test)]
 mod tests {
 
-    use board_router::{route, Layout, RouteInput};
+    use board_router::{
+        route, BendCounts, BoardRouterOutputBoard, BoardRouterOutputError, BoardSide, ConnectionOrdering, CostModel, EdgePort,
+        FailureReason, Heuristic, KeepOut, Layout,
+        LengthMatchGroup, MultiPortRouting, Port, PortDefinition, RipUpReroute, RouteInput, RoutingBackend, RoutingSetting,
+        SearchLimits, Serpentine, Smoothing,
+    };
+    use hydraulics::rectangular_channel_resistance;
     use validation::{validate, ValidateInput};
 
     use crate::board_router::RouteInputConnection;
 
     use super::*;
 
+    // Input without connections or board geometry, tests set the fields they route with
+    pub(crate) fn route_input() -> RouteInput {
+        RouteInput {
+            channel_width: 0.,
+            channel_spacing: 0.,
+            layout: Layout::Octilinear,
+            board_width: 0.,
+            board_height: 0.,
+            pitch: 0.,
+            pitch_offset_x: 0.,
+            pitch_offset_y: 0.,
+            port_diameter: 0.,
+            max_ports: 0,
+            connections: Vec::new(),
+            rip_up_reroute: None,
+            ordering: ConnectionOrdering::default(),
+            layers: None,
+            via_cost: None,
+            multi_port_routing: MultiPortRouting::default(),
+            keep_outs: Vec::new(),
+            blocked_ports: Vec::new(),
+            locked_connections: Vec::new(),
+            length_match_groups: Vec::new(),
+            channel_height: None,
+            viscosity: None,
+            smoothing: None,
+            ports: Vec::new(),
+            edge_ports: Vec::new(),
+            cell_size: None,
+            grid_snapshots: false,
+            backend: RoutingBackend::default(),
+            bidirectional_search: false,
+            search_limits: SearchLimits::default(),
+            cost_model: CostModel::default(),
+            heuristic: Heuristic::default(),
+            heuristic_bias: None,
+        }
+    }
+
+    // Connection without ports or overrides
+    pub(crate) fn route_input_connection() -> RouteInputConnection {
+        RouteInputConnection {
+            id: 0,
+            ports: Vec::new(),
+            branch_port: None,
+            priority: None,
+            channel_width: None,
+            channel_spacing: None,
+            target_resistance: None,
+            serpentine: None,
+            port_candidates: Vec::new(),
+        }
+    }
+
     #[test]
     fn it_validates() {
         let result = validate(ValidateInput {
@@ -31,6 +94,8 @@ mod tests {
             max_ports: Some(20000),
             layout: Some(Layout::Rectilinear),
             connections: Some(Vec::new()),
+            ports: None,
+            edge_ports: None,
         });
 
         println!("{:?}", result)
@@ -55,33 +120,40 @@ mod tests {
                     id: 0,
                     ports: vec![(5, 5), (5, 7)],
                     branch_port: None,
+                    ..route_input_connection()
                 },
                 RouteInputConnection {
                     id: 1,
                     ports: vec![(6, 5), (6, 7)],
                     branch_port: None,
+                    ..route_input_connection()
                 },
                 RouteInputConnection {
                     id: 2,
                     ports: vec![(7, 5), (7, 7)],
                     branch_port: None,
+                    ..route_input_connection()
                 },
                 RouteInputConnection {
                     id: 3,
                     ports: vec![(8, 5), (8, 7)],
                     branch_port: None,
+                    ..route_input_connection()
                 },
                 RouteInputConnection {
                     id: 4,
                     ports: vec![(9, 5), (9, 7)],
                     branch_port: None,
+                    ..route_input_connection()
                 },
                 RouteInputConnection {
                     id: 0,
                     ports: vec![(4, 6), (10, 6)],
                     branch_port: None,
+                    ..route_input_connection()
                 },
             ]),
+            ..route_input()
         });
 
         println!("{:?}", result);
@@ -114,13 +186,16 @@ mod tests {
                     id: 0,
                     ports: vec![(2, 0), (1, 1)],
                     branch_port: None,
+                    ..route_input_connection()
                 },
                 RouteInputConnection {
                     id: 1,
                     ports: vec![(0, 0), (2, 2)],
                     branch_port: None,
+                    ..route_input_connection()
                 },
             ]),
+            ..route_input()
         });
 
         println!("{:?}", result);
@@ -152,7 +227,9 @@ mod tests {
                 id: 0,
                 ports: vec![(5, 5), (11, 5), (8, 2), (8, 8)],
                 branch_port: None,
+                ..route_input_connection()
             }]),
+            ..route_input()
         });
     }
 
@@ -173,7 +250,9 @@ mod tests {
                 id: 0,
                 ports: vec![(5, 5), (11, 5), (8, 2), (8, 8)],
                 branch_port: None,
+                ..route_input_connection()
             }]),
+            ..route_input()
         });
     }
 
@@ -194,7 +273,9 @@ mod tests {
                 id: 0,
                 ports: vec![(0, 0), (5, 5)],
                 branch_port: None,
+                ..route_input_connection()
             }]),
+            ..route_input()
         });
     }
 
@@ -215,7 +296,2310 @@ mod tests {
                 id: 0,
                 ports: vec![(19, 4), (27, 5)],
                 branch_port: Some((23, 0)),
+                ..route_input_connection()
+            }]),
+            ..route_input()
+        });
+    }
+
+    #[test]
+    fn rip_up_reroute() {
+        let mut input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 3500.,
+            board_height: 2500.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Rectilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(1, 0), (5, 3)],
+                    branch_port: None,
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(2, 3), (4, 1)],
+                    branch_port: None,
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 2,
+                    ports: vec![(0, 0), (2, 2)],
+                    branch_port: None,
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 3,
+                    ports: vec![(4, 0), (5, 0)],
+                    branch_port: None,
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 4,
+                    ports: vec![(3, 1), (0, 1)],
+                    branch_port: None,
+                    ..route_input_connection()
+                },
+            ]),
+            ..route_input()
+        };
+
+        assert!(route(&input).is_err());
+
+        input.rip_up_reroute = Some(RipUpReroute {
+            max_iterations: 20,
+            history_increment: 1.,
+        });
+        let result = route(&input);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap().connections.len(), 5);
+    }
+
+    #[test]
+    fn connection_ordering() {
+        let mut input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 3500.,
+            board_height: 2500.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Rectilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(1, 0), (5, 3)],
+                    branch_port: None,
+                    priority: Some(1),
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(2, 3), (4, 1)],
+                    branch_port: None,
+                    priority: Some(1),
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 2,
+                    ports: vec![(0, 0), (2, 2)],
+                    branch_port: None,
+                    priority: None,
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 3,
+                    ports: vec![(4, 0), (5, 0)],
+                    branch_port: None,
+                    priority: None,
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 4,
+                    ports: vec![(3, 1), (0, 1)],
+                    branch_port: None,
+                    priority: Some(2),
+                    ..route_input_connection()
+                },
+            ]),
+            ..route_input()
+        };
+
+        for (ordering, routable) in [
+            (ConnectionOrdering::Default, false),
+            (ConnectionOrdering::ShortestFirst, false),
+            (ConnectionOrdering::LongestFirst, true),
+            (ConnectionOrdering::MostConstrainedFirst, true),
+            (ConnectionOrdering::Priority, true),
+            (
+                ConnectionOrdering::BestOf(Vec::from([
+                    ConnectionOrdering::ShortestFirst,
+                    ConnectionOrdering::MostConstrainedFirst,
+                ])),
+                true,
+            ),
+            (
+                ConnectionOrdering::RandomRestarts {
+                    seed: 0,
+                    restarts: 10,
+                },
+                true,
+            ),
+        ] {
+            input.ordering = ordering;
+            assert_eq!(route(&input).is_ok(), routable, "{:?}", input.ordering);
+        }
+    }
+
+    #[test]
+    fn multi_layer_routing() {
+        let mut input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 5000.,
+            board_height: 4000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(0, 3), (8, 3)],
+                    branch_port: None,
+                    priority: Some(3),
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(4, 0), (4, 6)],
+                    branch_port: None,
+                    priority: Some(2),
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 2,
+                    ports: vec![(3, 2), (5, 4)],
+                    branch_port: None,
+                    priority: Some(1),
+                    ..route_input_connection()
+                },
+            ]),
+            ordering: ConnectionOrdering::Priority,
+            via_cost: Some(2.),
+            ..route_input()
+        };
+
+        // In a single plane, connection 1 and 2 have to detour around the channels they cannot cross
+        let Ok(single_layer) = route(&input) else {
+            panic!()
+        };
+        assert!(single_layer.layers.is_empty());
+
+        input.layers = Some(2);
+        let Ok(BoardRouterOutputBoard {
+            connections,
+            layers,
+            ..
+        }) = route(&input)
+        else {
+            panic!()
+        };
+        assert_eq!(connections.len(), 3);
+        assert_eq!(layers.len(), 3);
+        for ((c_id, channels), (l_id, channel_layers)) in connections.iter().zip(layers.iter()) {
+            assert_eq!(c_id, l_id);
+            for (channel, channel_layers) in channels.iter().zip(channel_layers.iter()) {
+                assert_eq!(channel_layers.segments.len(), channel.len() - 1);
+            }
+        }
+
+        // Connection 2 crosses both other connections and needs to change the layer in between
+        let (_, channels) = connections.iter().find(|c| c.0 == 2).unwrap();
+        let (_, channel_layers) = layers.iter().find(|c| c.0 == 2).unwrap();
+        assert_eq!(channel_layers[0].vias.len(), 1);
+        let via = &channel_layers[0].vias[0];
+        assert_ne!(via.from_layer, via.to_layer);
+        assert!(channels[0].contains(&via.position));
+
+        let (_, single_layer_channels) = single_layer.connections.iter().find(|c| c.0 == 2).unwrap();
+        assert!(channels[0].len() < single_layer_channels[0].len());
+    }
+
+    #[test]
+    fn steiner_tree() {
+        let mut input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 5000.,
+            board_height: 4000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(0, 0), (0, 5), (6, 0), (6, 5)],
+                    branch_port: None,
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(2, 2), (3, 4), (8, 1)],
+                    branch_port: None,
+                    ..route_input_connection()
+                },
+            ]),
+            ..route_input()
+        };
+
+        // The star join node of connection 0 blocks connection 1, which is routed with another join node
+        assert!(route(&input).is_ok());
+
+        // The optimised join node of connection 0 avoids the ports of connection 1
+        input.multi_port_routing = MultiPortRouting::OptimizedStar;
+        assert!(route(&input).is_ok());
+
+        input.multi_port_routing = MultiPortRouting::SteinerTree;
+        let Ok(BoardRouterOutputBoard { connections, .. }) = route(&input) else {
+            panic!()
+        };
+        for (c_id, channels) in connections.iter() {
+            let n_ports = input.connections[*c_id].ports.len();
+            assert_eq!(channels.len(), n_ports - 1);
+            // Every channel ends at a port and starts on a previously routed channel of the tree
+            for (i, channel) in channels.iter().enumerate().skip(1) {
+                assert!(channels[..i].iter().any(|c| c.contains(&channel[0])));
+            }
+        }
+    }
+
+    #[test]
+    fn branch_point_optimisation() {
+        // The best join cell by branch length lies inside a closed locked channel
+        let mut input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 5000.,
+            board_height: 5000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([RouteInputConnection {
+                id: 0,
+                ports: vec![(0, 0), (0, 8), (8, 0), (8, 8)],
+                branch_port: None,
+                ..route_input_connection()
+            }]),
+            locked_connections: Vec::from([(
+                1,
+                Vec::from([Vec::from([
+                    [1750., 1750.],
+                    [3250., 1750.],
+                    [3250., 3250.],
+                    [1750., 3250.],
+                    [1750., 1750.],
+                ])]),
+            )]),
+            ..route_input()
+        };
+
+        // The failed branches are retried with another join cell outside the locked channel
+        let Ok(BoardRouterOutputBoard { connections, .. }) = route(&input) else {
+            panic!()
+        };
+        let (_, channels) = connections.iter().find(|c| c.0 == 0).unwrap();
+        assert_eq!(channels.len(), 4);
+        let join = channels[0][0];
+        assert!(channels.iter().all(|channel| channel[0] == join));
+        assert!(!(1750. ..=3250.).contains(&join[0]) || !(1750. ..=3250.).contains(&join[1]));
+
+        // The optimised join cell avoids the locked channel from the start
+        let Ok(BoardRouterOutputBoard { connections, .. }) = route(&RouteInput {
+            multi_port_routing: MultiPortRouting::OptimizedStar,
+            ..input.clone()
+        }) else {
+            panic!()
+        };
+        let (_, channels) = connections.iter().find(|c| c.0 == 0).unwrap();
+        assert_eq!(channels.len(), 4);
+        let join = channels[0][0];
+        assert!(!(1750. ..=3250.).contains(&join[0]) || !(1750. ..=3250.).contains(&join[1]));
+
+        // A branch_port is kept even if the branches fail
+        input.connections[0].branch_port = Some((4, 4));
+        assert!(route(&input).is_err());
+    }
+
+    #[test]
+    fn keep_outs() {
+        let input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 3000.,
+            board_height: 2000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([RouteInputConnection {
+                id: 0,
+                ports: vec![(0, 1), (4, 1)],
+                branch_port: None,
+                ..route_input_connection()
+            }]),
+            ..route_input()
+        };
+
+        // Without keep-outs, the channel is a straight line through (1250, 1000)
+        let Ok(BoardRouterOutputBoard { connections, .. }) = route(&input) else {
+            panic!()
+        };
+        assert!(connections[0].1[0].contains(&[1250., 1000.]));
+
+        let keep_outs = [
+            KeepOut::Rectangle {
+                position: [1200., 900.],
+                dimensions: [100., 200.],
+            },
+            KeepOut::Circle {
+                center: [1250., 1000.],
+                radius: 100.,
+            },
+            KeepOut::Polygon(Vec::from([[1150., 1100.], [1350., 1100.], [1250., 900.]])),
+        ];
+        for keep_out in keep_outs {
+            let Ok(BoardRouterOutputBoard { connections, .. }) = route(&RouteInput {
+                keep_outs: Vec::from([keep_out.clone()]),
+                ..input.clone()
+            }) else {
+                panic!("{:?}", keep_out)
+            };
+            // Channel center lines keep the spacing and half the channel width away from the zone
+            assert!(connections[0].1[0]
+                .iter()
+                .all(|p| p[0] <= 1000. || p[0] >= 1500. || p[1] <= 750. || p[1] >= 1250.));
+        }
+
+        let Ok(BoardRouterOutputBoard { connections, .. }) = route(&RouteInput {
+            blocked_ports: Vec::from([(2, 1)]),
+            ..input.clone()
+        }) else {
+            panic!()
+        };
+        assert!(!connections[0].1[0].contains(&[1500., 1000.]));
+
+        // Keep-outs across the whole board make the connection unroutable
+        assert!(route(&RouteInput {
+            keep_outs: Vec::from([KeepOut::Rectangle {
+                position: [1200., 0.],
+                dimensions: [100., 2000.],
             }]),
+            ..input.clone()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn locked_connections() {
+        let mut input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 3000.,
+            board_height: 2500.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(0, 0), (3, 2)],
+                    branch_port: None,
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(0, 3), (4, 3)],
+                    branch_port: None,
+                    ..route_input_connection()
+                },
+            ]),
+            ..route_input()
+        };
+        let Ok(BoardRouterOutputBoard { connections, .. }) = route(&input) else {
+            panic!()
+        };
+
+        // Feed connection 0 back in and only route the new connection 2 around it
+        let locked = connections.iter().find(|c| c.0 == 0).unwrap().clone();
+        input.locked_connections = Vec::from([locked.clone()]);
+        input.connections[1] = RouteInputConnection {
+            id: 2,
+            ports: vec![(0, 2), (4, 0)],
+            branch_port: None,
+            ..route_input_connection()
+        };
+        let Ok(BoardRouterOutputBoard { connections, .. }) = route(&input) else {
+            panic!()
+        };
+        assert_eq!(connections.len(), 2);
+        assert!(connections.contains(&locked));
+        let (_, channels) = connections.iter().find(|c| c.0 == 2).unwrap();
+        assert!(channels[0].iter().all(|p| !locked.1[0].contains(p)));
+
+        // A locked channel across the board cannot be crossed
+        input.locked_connections = Vec::from([(0, Vec::from([Vec::from([[1250., 0.], [1250., 2500.]])]))]);
+        let Err(BoardRouterOutputError::PartialResult(BoardRouterOutputBoard { connections, .. })) =
+            route(&input)
+        else {
+            panic!()
+        };
+        assert_eq!(connections, input.locked_connections);
+    }
+
+    #[test]
+    fn failure_diagnostics() {
+        // Two channels across the whole board, from edge to edge
+        let mut input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 3000.,
+            board_height: 3000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(10, 0), (11, 0)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(12, 0), (13, 0)],
+                    ..route_input_connection()
+                },
+            ]),
+            edge_ports: Vec::from([
+                (10, BoardSide::Left),
+                (11, BoardSide::Right),
+                (12, BoardSide::Top),
+                (13, BoardSide::Bottom),
+            ]
+            .map(|(x, side)| EdgePort {
+                port: (x, 0),
+                side,
+                position: 1500.,
+                width: 200.,
+            })),
+            ..route_input()
+        };
+        let Err(BoardRouterOutputError::PartialResult(BoardRouterOutputBoard { failures, .. })) = route(&input)
+        else {
+            panic!()
+        };
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].connection, 1);
+        assert_eq!(failures[0].port, None);
+        assert_eq!(failures[0].reason, FailureReason::Enclosed(vec![0]));
+        assert!(!failures[0].explored.is_empty());
+
+        // A port shared with another connection and a port behind a locked channel
+        input.edge_ports = Vec::new();
+        input.connections = Vec::from([
+            RouteInputConnection {
+                id: 0,
+                ports: vec![(0, 0), (2, 0)],
+                ..route_input_connection()
+            },
+            RouteInputConnection {
+                id: 1,
+                ports: vec![(0, 0), (0, 2)],
+                ..route_input_connection()
+            },
+            RouteInputConnection {
+                id: 2,
+                ports: vec![(4, 4), (2, 2)],
+                ..route_input_connection()
+            },
+        ]);
+        input.locked_connections = Vec::from([(3, Vec::from([Vec::from([[0., 1750.], [3000., 1750.]])]))]);
+        let Err(BoardRouterOutputError::PartialResult(BoardRouterOutputBoard { failures, .. })) = route(&input)
+        else {
+            panic!()
+        };
+        let reasons = Vec::from_iter(failures.iter().map(|f| (f.connection, f.reason.clone())));
+        assert_eq!(
+            reasons,
+            [
+                (1, FailureReason::StartBlocked(Some(0))),
+                (2, FailureReason::EmptySearchSpace)
+            ]
+        );
+        assert!(failures[0].explored.is_empty());
+        // The search explored the cells below the locked channel only
+        assert!(failures[1].explored.iter().all(|p| p[1] > 1750.));
+    }
+
+    #[test]
+    fn grid_snapshots() {
+        let mut input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 3000.,
+            board_height: 3000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(0, 0), (4, 0)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(0, 4), (4, 4), (2, 2)],
+                    ..route_input_connection()
+                },
+            ]),
+            ..route_input()
+        };
+        let Ok(board) = route(&input) else { panic!() };
+        assert!(board.setup_grid.is_none() && board.routed_grid.is_none());
+
+        input.grid_snapshots = true;
+        let Ok(board) = route(&input) else { panic!() };
+        let setup_grid = board.setup_grid.clone().unwrap();
+        let routed_grid = board.routed_grid.clone().unwrap();
+        assert_eq!(setup_grid.cells.len(), setup_grid.layers * setup_grid.cells_x * setup_grid.cells_y);
+        assert_eq!(routed_grid.cells.len(), setup_grid.cells.len());
+
+        // The cell of the port (0, 0) is reserved for connection 0 after setup and occupied by its channel after
+        // routing
+        let (x, y) = (0..setup_grid.cells_x)
+            .flat_map(|x| (0..setup_grid.cells_y).map(move |y| (x, y)))
+            .find(|&(x, y)| setup_grid.cell_position(x, y) == [500., 500.])
+            .unwrap();
+        assert_eq!(setup_grid.cell(0, x, y).connection, Some(0));
+        assert!(setup_grid.cells.iter().all(|cell| cell.channel.is_none()));
+        assert_eq!(routed_grid.cell(0, x, y).channel, Some(0));
+        assert!(routed_grid.cells.iter().any(|cell| cell.channel == Some(1)));
+
+        // The overlay draws every cell on top of the channels and the board
+        let dxf::GenerateSVGOutput(svg) = dxf::generate_svg(dxf::GenerateSVGInput {
+            connections: board,
+            channel_width: 100.,
+            channel_cap: dxf::ChannelCap::Butt,
+            board_width: 3000.,
+            board_height: 3000.,
+            port_diameter: Some(200.),
+            ports: Vec::new(),
+            grid: Some(routed_grid.clone()),
         });
+        let overlay = &svg[svg.find("<g class=\"grid\">").unwrap()..];
+        assert!(!overlay.contains("<path"));
+        assert_eq!(overlay.matches("<rect").count(), routed_grid.cells_x * routed_grid.cells_y);
+    }
+
+    #[test]
+    fn channel_width_overrides() {
+        let input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 3000.,
+            board_height: 3000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(0, 0), (4, 4)],
+                    channel_width: Some(300.),
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(0, 1), (3, 4)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 2,
+                    ports: vec![(1, 0), (4, 3)],
+                    channel_spacing: Some(150.),
+                    ..route_input_connection()
+                },
+            ]),
+            ..route_input()
+        };
+        let Ok(BoardRouterOutputBoard {
+            connections,
+            channel_widths,
+            ..
+        }) = route(&input)
+        else {
+            panic!()
+        };
+        assert_eq!(channel_widths, Vec::from([(0, 300.)]));
+
+        // Center lines keep half of both widths plus the larger spacing apart
+        let footprint = |c_id: usize| match c_id {
+            0 => (150., 100.),
+            2 => (50., 150.),
+            _ => (50., 100.),
+        };
+        let segment_distance = |[px, py]: [f64; 2], [ax, ay]: [f64; 2], [bx, by]: [f64; 2]| {
+            let (dx, dy) = (bx - ax, by - ay);
+            let t = (((px - ax) * dx + (py - ay) * dy) / (dx * dx + dy * dy)).clamp(0., 1.);
+            f64::hypot(px - (ax + t * dx), py - (ay + t * dy))
+        };
+        for (a_id, a_channels) in connections.iter() {
+            for (b_id, b_channels) in connections.iter().filter(|(b_id, _)| b_id != a_id) {
+                let ((a_half_width, a_spacing), (b_half_width, b_spacing)) =
+                    (footprint(*a_id), footprint(*b_id));
+                let clearance = a_half_width + b_half_width + f64::max(a_spacing, b_spacing);
+                for point in a_channels.iter().flatten() {
+                    for segment in b_channels.iter().flat_map(|c| c.windows(2)) {
+                        assert!(segment_distance(*point, segment[0], segment[1]) >= clearance - 1e-6);
+                    }
+                }
+            }
+        }
+
+        // A wider channel does not fit between the other connections
+        let mut wide_input = input.clone();
+        wide_input.connections[0].channel_width = Some(500.);
+        let Err(BoardRouterOutputError::PartialResult(BoardRouterOutputBoard { connections, .. })) =
+            route(&wide_input)
+        else {
+            panic!()
+        };
+        assert!(connections.iter().all(|(c_id, _)| *c_id != 0));
+    }
+
+    #[test]
+    fn fine_grid() {
+        // The pitch is just under two channel distances, so the default grid has a single cell per pitch
+        let mut input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 160.,
+            board_width: 3000.,
+            board_height: 3000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 80.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(1, 0), (1, 2)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(2, 0), (2, 2)],
+                    ..route_input_connection()
+                },
+            ]),
+            blocked_ports: Vec::from_iter((0..5).map(|x| (x, 1))),
+            ..route_input()
+        };
+        assert!(matches!(route(&input), Err(BoardRouterOutputError::NoConnectionsFound)));
+
+        // Between the blocked ports, a channel keeps the clearance on a grid of four cells per pitch
+        input.cell_size = Some(125.);
+        let Ok(BoardRouterOutputBoard { connections, .. }) = route(&input) else {
+            panic!()
+        };
+        assert_eq!(connections.len(), 2);
+        for (a_id, a_channels) in connections.iter() {
+            for segment in a_channels.iter().flat_map(|c| c.windows(2)) {
+                for x in 0..5 {
+                    let port = [500. + x as f64 * 500., 1000.];
+                    assert!(smoothing::segments_distance(segment[0], segment[1], port, port) >= 250. - 1e-6);
+                }
+                for (_, b_channels) in connections.iter().filter(|(b_id, _)| b_id != a_id) {
+                    for other in b_channels.iter().flat_map(|c| c.windows(2)) {
+                        assert!(smoothing::segments_distance(segment[0], segment[1], other[0], other[1]) >= 260. - 1e-6);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fine_grid_benchmark_boards() {
+        // The first boards of two octilinear benchmark groups, on grids with a fractional number of cells per
+        // channel distance, where diagonal steps pass closer to foreign channels than the cells at their ends
+        for group in ["02_30x15_10_0.4_O", "04_30x15_20_0.2_O"] {
+            let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/cases").join(group);
+            let mut files = Vec::from_iter(
+                std::fs::read_dir(dir)
+                    .unwrap()
+                    .map(|entry| entry.unwrap().path())
+                    .filter(|path| path.extension().is_some_and(|extension| extension == "json")),
+            );
+            files.sort();
+            for file in files.iter().take(2) {
+                for cells in [2.2, 2.5] {
+                    let mut input = utils::read_input_from_file(file).unwrap();
+                    let clearance = input.channel_width + input.channel_spacing;
+                    input.cell_size = Some(clearance / cells);
+                    let board = match route(&input) {
+                        Ok(board) | Err(BoardRouterOutputError::PartialResult(board)) => board,
+                        Err(e) => panic!("{:?} {:?}", file, e),
+                    };
+                    for (a_id, a_channels) in board.connections.iter() {
+                        for segment in a_channels.iter().flat_map(|c| c.windows(2)) {
+                            for (_, b_channels) in board.connections.iter().filter(|(b_id, _)| b_id != a_id) {
+                                for other in b_channels.iter().flat_map(|c| c.windows(2)) {
+                                    let distance =
+                                        smoothing::segments_distance(segment[0], segment[1], other[0], other[1]);
+                                    assert!(distance >= clearance - 1e-6, "{:?} {} {}", file, cells, distance);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gridless_backend() {
+        for layout in [Layout::Rectilinear, Layout::Octilinear] {
+            let input = RouteInput {
+                channel_width: 100.,
+                channel_spacing: 100.,
+                board_width: 3000.,
+                board_height: 3000.,
+                pitch: 500.,
+                pitch_offset_x: 500.,
+                pitch_offset_y: 500.,
+                port_diameter: 200.,
+                max_ports: 20000,
+                layout,
+                connections: Vec::from([
+                    RouteInputConnection {
+                        id: 0,
+                        ports: vec![(0, 2), (4, 2)],
+                        ..route_input_connection()
+                    },
+                    RouteInputConnection {
+                        id: 1,
+                        ports: vec![(1, 0), (3, 0), (2, 4)],
+                        ..route_input_connection()
+                    },
+                ]),
+                keep_outs: Vec::from([KeepOut::Rectangle {
+                    position: [1200., 900.],
+                    dimensions: [100., 200.],
+                }]),
+                backend: RoutingBackend::Gridless,
+                ..route_input()
+            };
+            let Ok(BoardRouterOutputBoard { connections, .. }) = route(&input) else {
+                panic!("{:?}", layout)
+            };
+            assert_eq!(connections.len(), 2);
+            for (a_id, a_channels) in connections.iter() {
+                let ports = &input.connections.iter().find(|c| c.id == *a_id).unwrap().ports;
+                for (x, y) in ports.iter() {
+                    let port = [500. + *x as f64 * 500., 500. + *y as f64 * 500.];
+                    assert!(a_channels.iter().any(|c| c[0] == port || c[c.len() - 1] == port));
+                }
+                for segment in a_channels.iter().flat_map(|c| c.windows(2)) {
+                    // Channels run along the layout directions and keep their clearance to the keep-out
+                    let [dx, dy] = [segment[1][0] - segment[0][0], segment[1][1] - segment[0][1]];
+                    assert!(dx.abs() < 1e-6 || dy.abs() < 1e-6 || (matches!(layout, Layout::Octilinear) && (dx.abs() - dy.abs()).abs() < 1e-6));
+                    for corner in [[1200., 900.], [1300., 900.], [1200., 1100.], [1300., 1100.]] {
+                        assert!(smoothing::segments_distance(segment[0], segment[1], corner, corner) >= 150. - 1e-6);
+                    }
+                    for (_, b_channels) in connections.iter().filter(|(b_id, _)| b_id != a_id) {
+                        for other in b_channels.iter().flat_map(|c| c.windows(2)) {
+                            assert!(smoothing::segments_distance(segment[0], segment[1], other[0], other[1]) >= 200. - 1e-6);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gridless_benchmark_boards() {
+        // The first boards of the sparse benchmark groups (rectilinear and octilinear), which the gridless backend
+        // routes completely but for a port sealed off by the channels routed before it
+        let mut complete = 0;
+        for group in ["01_30x15_10_0.4_R", "02_30x15_10_0.4_O"] {
+            let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/cases").join(group);
+            let mut files = Vec::from_iter(
+                std::fs::read_dir(dir)
+                    .unwrap()
+                    .map(|entry| entry.unwrap().path())
+                    .filter(|path| path.extension().is_some_and(|extension| extension == "json")),
+            );
+            files.sort();
+            assert!(files.len() >= 10);
+            for file in files.iter().take(10) {
+                let mut input = utils::read_input_from_file(file).unwrap();
+                input.backend = RoutingBackend::Gridless;
+                let board = match route(&input) {
+                    Ok(board) => {
+                        complete += 1;
+                        board
+                    }
+                    Err(BoardRouterOutputError::PartialResult(board)) => board,
+                    Err(e) => panic!("{:?} {:?}", file, e),
+                };
+                // Every connection is routed or reported as a failure
+                for c in input.connections.iter() {
+                    let routed = board.connections.iter().any(|(c_id, _)| *c_id == c.id);
+                    let failed = board.failures.iter().any(|f| f.connection == c.id);
+                    assert!(routed != failed, "{:?} {}", file, c.id);
+                }
+                let clearance = input.channel_width + input.channel_spacing;
+                for (a_id, a_channels) in board.connections.iter() {
+                    let ports = &input.connections.iter().find(|c| c.id == *a_id).unwrap().ports;
+                    for (x, y) in ports.iter() {
+                        let port = [
+                            input.pitch_offset_x + *x as f64 * input.pitch,
+                            input.pitch_offset_y + *y as f64 * input.pitch,
+                        ];
+                        assert!(a_channels.iter().any(|c| c[0] == port || c[c.len() - 1] == port));
+                    }
+                    for segment in a_channels.iter().flat_map(|c| c.windows(2)) {
+                        for (_, b_channels) in board.connections.iter().filter(|(b_id, _)| b_id != a_id) {
+                            for other in b_channels.iter().flat_map(|c| c.windows(2)) {
+                                let distance = smoothing::segments_distance(segment[0], segment[1], other[0], other[1]);
+                                assert!(distance >= clearance - 1e-6, "{:?}", file);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        assert!(complete >= 19, "{}", complete);
+    }
+
+    #[test]
+    fn gridless_unsupported_settings() {
+        let input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 3000.,
+            board_height: 3000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Rectilinear,
+            connections: Vec::from([RouteInputConnection {
+                id: 0,
+                ports: vec![(0, 0), (4, 2)],
+                ..route_input_connection()
+            }]),
+            backend: RoutingBackend::Gridless,
+            ..route_input()
+        };
+        assert!(route(&input).is_ok());
+        assert!(route(&RouteInput { layers: Some(1), ..input.clone() }).is_ok());
+
+        let with_connection = |connection: RouteInputConnection| RouteInput {
+            connections: Vec::from([connection]),
+            channel_height: Some(50.),
+            viscosity: Some(1e-3),
+            ..input.clone()
+        };
+        let unsupported = [
+            (RouteInput { layers: Some(2), ..input.clone() }, RoutingSetting::Layers),
+            (
+                RouteInput {
+                    rip_up_reroute: Some(RipUpReroute {
+                        max_iterations: 5,
+                        history_increment: 1.,
+                    }),
+                    ..input.clone()
+                },
+                RoutingSetting::RipUpReroute,
+            ),
+            (
+                RouteInput {
+                    ordering: ConnectionOrdering::LongestFirst,
+                    ..input.clone()
+                },
+                RoutingSetting::Ordering,
+            ),
+            (
+                RouteInput {
+                    length_match_groups: Vec::from([LengthMatchGroup {
+                        connections: Vec::from([0]),
+                        tolerance: 10.,
+                        style: None,
+                    }]),
+                    ..input.clone()
+                },
+                RoutingSetting::LengthMatching,
+            ),
+            (
+                with_connection(RouteInputConnection {
+                    target_resistance: Some(1e12),
+                    ..input.connections[0].clone()
+                }),
+                RoutingSetting::TargetResistance,
+            ),
+            (
+                with_connection(RouteInputConnection {
+                    serpentine: Some(Serpentine {
+                        amplitude: 200.,
+                        turns: 2,
+                        segment: None,
+                    }),
+                    ..input.connections[0].clone()
+                }),
+                RoutingSetting::Serpentine,
+            ),
+            (
+                RouteInput {
+                    smoothing: Some(Smoothing {
+                        min_bend_radius: 50.,
+                        any_angle: false,
+                    }),
+                    ..input.clone()
+                },
+                RoutingSetting::Smoothing,
+            ),
+            (
+                RouteInput {
+                    cost_model: CostModel {
+                        adjacency: 1.,
+                        ..Default::default()
+                    },
+                    ..input.clone()
+                },
+                RoutingSetting::CostModel,
+            ),
+            (
+                RouteInput {
+                    cell_size: Some(100.),
+                    ..input.clone()
+                },
+                RoutingSetting::CellSize,
+            ),
+            (
+                RouteInput {
+                    grid_snapshots: true,
+                    ..input.clone()
+                },
+                RoutingSetting::GridSnapshots,
+            ),
+            (
+                RouteInput {
+                    bidirectional_search: true,
+                    ..input.clone()
+                },
+                RoutingSetting::BidirectionalSearch,
+            ),
+        ];
+        for (input, setting) in unsupported {
+            let Err(BoardRouterOutputError::UnsupportedSetting(unsupported)) = route(&input) else {
+                panic!("{:?}", setting)
+            };
+            assert_eq!(unsupported, setting);
+        }
+    }
+
+    #[test]
+    fn gridless_heuristic() {
+        // Connection 0 has to detour around the wall, connection 1 is routed directly
+        let input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 4000.,
+            board_height: 4000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(0, 0), (5, 0)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(0, 3), (0, 5)],
+                    ..route_input_connection()
+                },
+            ]),
+            keep_outs: Vec::from([KeepOut::Rectangle {
+                position: [1700., 0.],
+                dimensions: [200., 3200.],
+            }]),
+            backend: RoutingBackend::Gridless,
+            ..route_input()
+        };
+        let statistics = |heuristic: Heuristic| {
+            let Ok(board) = route(&RouteInput { heuristic, ..input.clone() }) else {
+                panic!()
+            };
+            board.statistics
+        };
+
+        // The octile distance guides the search with fewer expansions than the straight-line distance to a channel of
+        // the same length, and the Manhattan distance overestimates diagonal channels
+        let (euclidean, layout, manhattan) = (
+            statistics(Heuristic::Euclidean),
+            statistics(Heuristic::Layout),
+            statistics(Heuristic::Manhattan),
+        );
+        assert!(layout.expansions < euclidean.expansions);
+        assert!((layout.length - euclidean.length).abs() < 1e-6);
+        assert!(manhattan.length > layout.length);
+        assert!(matches!(manhattan.heuristic, Heuristic::Manhattan));
+    }
+
+    #[test]
+    fn independent_clusters() {
+        let input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 8000.,
+            board_height: 2500.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(0, 0), (3, 2)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(10, 0), (13, 3)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 2,
+                    ports: vec![(0, 3), (3, 0)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 3,
+                    ports: vec![(10, 3), (13, 0)],
+                    ..route_input_connection()
+                },
+            ]),
+            ..route_input()
+        };
+        let Ok(BoardRouterOutputBoard { connections, .. }) = route(&input) else {
+            panic!()
+        };
+
+        // The connections at both ends of the board are routed as two clusters on their part of the grid, each as long
+        // as if it was alone
+        let cluster = |ids: [usize; 2]| {
+            let Ok(BoardRouterOutputBoard { connections, .. }) = route(&RouteInput {
+                connections: Vec::from_iter(input.connections.iter().filter(|c| ids.contains(&c.id)).cloned()),
+                ..input.clone()
+            }) else {
+                panic!()
+            };
+            connections
+        };
+        let alone = [cluster([0, 2]), cluster([1, 3])].concat();
+        assert_eq!(connections.len(), alone.len());
+        for ((c_id, channels), (alone_id, alone_channels)) in connections.iter().zip(alone.iter()) {
+            assert_eq!(c_id, alone_id);
+            let length = |channels: &Vec<Vec<[f64; 2]>>| channels.iter().map(hydraulics::channel_length).sum::<f64>();
+            assert!((length(channels) - length(alone_channels)).abs() < 1e-6);
+            let left = [0, 2].contains(c_id);
+            assert!(channels.iter().flatten().all(|p| (p[0] < 4000.) == left));
+        }
+
+        // The result does not depend on which cluster finishes first
+        for _ in 0..5 {
+            let Ok(BoardRouterOutputBoard { connections: again, .. }) = route(&input) else {
+                panic!()
+            };
+            assert_eq!(again, connections);
+        }
+
+        // A locked channel between the ports makes the channel detour beyond the bounding box of its ports
+        let detour_input = RouteInput {
+            board_height: 4000.,
+            connections: Vec::from_iter(input.connections.iter().filter(|c| [0, 1].contains(&c.id)).cloned()),
+            locked_connections: Vec::from([(4, Vec::from([Vec::from([[1250., 0.], [1250., 2750.]])]))]),
+            ..input.clone()
+        };
+        let Ok(BoardRouterOutputBoard { connections, .. }) = route(&detour_input) else {
+            panic!()
+        };
+        let (_, channels) = connections.iter().find(|(c_id, _)| *c_id == 0).unwrap();
+        assert!(channels[0].iter().any(|p| p[1] > 2750.));
+    }
+
+    #[test]
+    fn ordering_across_clusters() {
+        let input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 8000.,
+            board_height: 2500.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(0, 0), (3, 2)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(10, 0), (13, 3)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 2,
+                    ports: vec![(0, 3), (3, 0)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 3,
+                    ports: vec![(10, 3), (13, 0)],
+                    ..route_input_connection()
+                },
+            ]),
+            ..route_input()
+        };
+
+        // Random orders and the best of several orders are the same as on the grid of the whole board (which grid
+        // snapshots need), and the same in every run
+        let orderings = [
+            ConnectionOrdering::Random { seed: 3 },
+            ConnectionOrdering::RandomRestarts { seed: 3, restarts: 2 },
+            ConnectionOrdering::BestOf(Vec::from([
+                ConnectionOrdering::LongestFirst,
+                ConnectionOrdering::Random { seed: 5 },
+            ])),
+        ];
+        for ordering in orderings {
+            let input = RouteInput { ordering, ..input.clone() };
+            let result = |input: &RouteInput| {
+                let Ok(BoardRouterOutputBoard {
+                    connections,
+                    statistics,
+                    ..
+                }) = route(input)
+                else {
+                    panic!()
+                };
+                let order = Vec::from_iter(statistics.connections.iter().map(|c| (c.connection, c.order)));
+                (connections, order)
+            };
+            let clustered = result(&input);
+            assert_eq!(clustered, result(&RouteInput { grid_snapshots: true, ..input.clone() }));
+            assert_eq!(clustered, result(&input));
+        }
+    }
+
+    #[test]
+    fn blocked_port_outside_cluster() {
+        let connection = |id: usize, ports: [Port; 2]| RouteInputConnection {
+            id,
+            ports: Vec::from(ports),
+            ..route_input_connection()
+        };
+        let input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 12000.,
+            board_height: 8000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([
+                connection(0, [(5, 5), (8, 8)]),
+                connection(1, [(5, 8), (8, 5)]),
+                connection(2, [(6, 5), (7, 8)]),
+                connection(3, [(5, 6), (8, 7)]),
+                connection(4, [(20, 5), (22, 8)]),
+            ]),
+            ..route_input()
+        };
+        let Ok(BoardRouterOutputBoard { connections, .. }) = route(&input) else {
+            panic!()
+        };
+
+        // A blocked port left of the window of the first cluster is too far away to affect its channels
+        let Ok(BoardRouterOutputBoard {
+            connections: blocked, ..
+        }) = route(&RouteInput {
+            blocked_ports: Vec::from([(1, 6)]),
+            ..input.clone()
+        })
+        else {
+            panic!()
+        };
+        assert_eq!(blocked, connections);
+    }
+
+    #[test]
+    fn rerouted_cluster() {
+        let connection = |id: usize, ports: [Port; 2]| RouteInputConnection {
+            id,
+            ports: Vec::from(ports),
+            ..route_input_connection()
+        };
+        let input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 12000.,
+            board_height: 6000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([
+                connection(0, [(0, 0), (7, 3)]),
+                connection(1, [(0, 1), (7, 2)]),
+                connection(2, [(0, 2), (7, 1)]),
+                connection(3, [(0, 3), (7, 0)]),
+                connection(4, [(20, 0), (22, 3)]),
+            ]),
+            rip_up_reroute: Some(RipUpReroute {
+                max_iterations: 20,
+                history_increment: 1.,
+            }),
+            ..route_input()
+        };
+        let Ok(BoardRouterOutputBoard {
+            connections,
+            statistics,
+            ..
+        }) = route(&input)
+        else {
+            panic!()
+        };
+
+        // The crossing connections do not fit the window of their cluster, so they are rerouted on the whole board
+        // after the other cluster, whose channel is kept
+        let Ok(BoardRouterOutputBoard { connections: alone, .. }) = route(&RouteInput {
+            connections: Vec::from([connection(4, [(20, 0), (22, 3)])]),
+            ..input.clone()
+        }) else {
+            panic!()
+        };
+        assert_eq!(connections[0], alone[0]);
+        assert_eq!(connections.len(), 5);
+        let order = |c_id: usize| statistics.connections.iter().find(|c| c.connection == c_id).unwrap().order;
+        assert_eq!(order(4), Some(0));
+        assert!((0..4).all(|c_id| order(c_id) > Some(0)));
+
+        // The result does not depend on which cluster finishes first
+        for _ in 0..5 {
+            let Ok(BoardRouterOutputBoard { connections: again, .. }) = route(&input) else {
+                panic!()
+            };
+            assert_eq!(again, connections);
+        }
+    }
+
+    #[test]
+    fn bidirectional_search() {
+        for layout in [Layout::Rectilinear, Layout::Octilinear] {
+            let input = RouteInput {
+                channel_width: 100.,
+                channel_spacing: 100.,
+                board_width: 20000.,
+                board_height: 3000.,
+                pitch: 500.,
+                pitch_offset_x: 500.,
+                pitch_offset_y: 500.,
+                port_diameter: 200.,
+                max_ports: 20000,
+                layout,
+                connections: Vec::from([
+                    RouteInputConnection {
+                        id: 0,
+                        ports: vec![(0, 0), (37, 2)],
+                        ..route_input_connection()
+                    },
+                    RouteInputConnection {
+                        id: 1,
+                        ports: vec![(0, 4), (10, 4), (20, 4), (30, 4)],
+                        ..route_input_connection()
+                    },
+                ]),
+                keep_outs: Vec::from([KeepOut::Rectangle {
+                    position: [9000., 0.],
+                    dimensions: [200., 1200.],
+                }]),
+                multi_port_routing: MultiPortRouting::SteinerTree,
+                ..route_input()
+            };
+            let Ok(forward) = route(&input) else { panic!() };
+            let Ok(bidirectional) = route(&RouteInput {
+                bidirectional_search: true,
+                ..input.clone()
+            }) else {
+                panic!()
+            };
+
+            // The long connection is as long as with the forward search
+            let length = |output: &BoardRouterOutputBoard| {
+                let (_, channels) = output.connections.iter().find(|c| c.0 == 0).unwrap();
+                channels.iter().map(hydraulics::channel_length).sum::<f64>()
+            };
+            assert!((length(&forward) - length(&bidirectional)).abs() < 1e-6);
+            let (_, tree) = bidirectional.connections.iter().find(|c| c.0 == 1).unwrap();
+            assert_eq!(tree.len(), 3);
+        }
+    }
+
+    #[test]
+    fn search_limits() {
+        for backend in [RoutingBackend::Grid, RoutingBackend::Gridless] {
+            // Connection 0 has to detour around the wall, connection 1 is routed directly
+            let input = RouteInput {
+                channel_width: 100.,
+                channel_spacing: 100.,
+                board_width: 4000.,
+                board_height: 4000.,
+                pitch: 500.,
+                pitch_offset_x: 500.,
+                pitch_offset_y: 500.,
+                port_diameter: 200.,
+                max_ports: 20000,
+                layout: Layout::Rectilinear,
+                connections: Vec::from([
+                    RouteInputConnection {
+                        id: 0,
+                        ports: vec![(0, 0), (5, 0)],
+                        ..route_input_connection()
+                    },
+                    RouteInputConnection {
+                        id: 1,
+                        ports: vec![(0, 3), (0, 5)],
+                        ..route_input_connection()
+                    },
+                ]),
+                keep_outs: Vec::from([KeepOut::Rectangle {
+                    position: [1700., 0.],
+                    dimensions: [200., 3200.],
+                }]),
+                backend,
+                ..route_input()
+            };
+            assert!(route(&input).is_ok());
+
+            let limited_failure = |search_limits: SearchLimits| {
+                let Err(BoardRouterOutputError::PartialResult(board)) = route(&RouteInput {
+                    search_limits,
+                    ..input.clone()
+                }) else {
+                    panic!()
+                };
+                assert!(board.connections.iter().any(|c| c.0 == 1));
+                assert_eq!(board.failures.len(), 1);
+                assert_eq!(board.failures[0].connection, 0);
+                board.failures[0].reason.clone()
+            };
+
+            // The detour leaves the region around the ports
+            let reason = limited_failure(SearchLimits {
+                region_margin: Some(500.),
+                ..Default::default()
+            });
+            assert_eq!(reason, FailureReason::SearchLimitReached);
+
+            // The detour takes more expansions than a direct channel (through fewer nodes on the visibility graph)
+            let max_expansions = match backend {
+                RoutingBackend::Grid => 30,
+                RoutingBackend::Gridless => 2,
+            };
+            let reason = limited_failure(SearchLimits {
+                max_expansions: Some(max_expansions),
+                ..Default::default()
+            });
+            assert_eq!(reason, FailureReason::SearchLimitReached);
+
+            // The total budget runs out in the detour; the search that diagnoses the failure is not limited by it and
+            // does not count towards the expansions
+            let Err(BoardRouterOutputError::PartialResult(board)) = route(&RouteInput {
+                search_limits: SearchLimits {
+                    max_total_expansions: Some(max_expansions),
+                    ..Default::default()
+                },
+                ..input.clone()
+            }) else {
+                panic!()
+            };
+            assert_eq!(board.failures[0].reason, FailureReason::SearchLimitReached);
+            assert!(board.statistics.expansions <= max_expansions);
+            if backend == RoutingBackend::Grid {
+                assert!(board.failures[0].explored.len() > max_expansions);
+            }
+
+            // Nothing is routed without a budget
+            let result = route(&RouteInput {
+                search_limits: SearchLimits {
+                    max_total_expansions: Some(0),
+                    ..Default::default()
+                },
+                ..input.clone()
+            });
+            assert!(matches!(result, Err(BoardRouterOutputError::NoConnectionsFound)));
+
+            // Nor without time
+            let result = route(&RouteInput {
+                search_limits: SearchLimits {
+                    max_time_ms: Some(0.),
+                    ..Default::default()
+                },
+                ..input.clone()
+            });
+            assert!(matches!(result, Err(BoardRouterOutputError::NoConnectionsFound)));
+
+            // A generous time limit leaves the routing unchanged
+            let limited = route(&RouteInput {
+                search_limits: SearchLimits {
+                    max_time_ms: Some(60000.),
+                    ..Default::default()
+                },
+                ..input.clone()
+            });
+            assert_eq!(limited.unwrap().connections, route(&input).unwrap().connections);
+        }
+    }
+
+    #[test]
+    fn statistics() {
+        for backend in [RoutingBackend::Grid, RoutingBackend::Gridless] {
+            let input = RouteInput {
+                channel_width: 100.,
+                channel_spacing: 100.,
+                board_width: 4000.,
+                board_height: 4000.,
+                pitch: 500.,
+                pitch_offset_x: 500.,
+                pitch_offset_y: 500.,
+                port_diameter: 200.,
+                max_ports: 20000,
+                layout: Layout::Rectilinear,
+                connections: Vec::from([
+                    RouteInputConnection {
+                        id: 0,
+                        ports: vec![(0, 0), (5, 0)],
+                        ..route_input_connection()
+                    },
+                    RouteInputConnection {
+                        id: 1,
+                        ports: vec![(0, 2), (4, 5)],
+                        ..route_input_connection()
+                    },
+                ]),
+                locked_connections: Vec::from([(2, Vec::from([Vec::from([[3250., 3250.], [3250., 3750.]])]))]),
+                channel_height: Some(50.),
+                // The gridless backend has no cost model
+                cost_model: CostModel {
+                    bend_90: if backend == RoutingBackend::Grid { 1. } else { 0. },
+                    ..Default::default()
+                },
+                backend,
+                ..route_input()
+            };
+            let Ok(BoardRouterOutputBoard { connections, statistics, .. }) = route(&input) else {
+                panic!()
+            };
+
+            assert_eq!(statistics.connections.len(), 3);
+            for (c, (c_id, channels)) in statistics.connections.iter().zip(connections.iter()) {
+                assert_eq!(c.connection, *c_id);
+                let length = channels.iter().map(hydraulics::channel_length).sum::<f64>();
+                assert!((c.length - length).abs() < 1e-6);
+                assert!((c.area - length * 100.).abs() < 1e-6);
+                assert!((c.volume.unwrap() - length * 100. * 50.).abs() < 1e-3);
+            }
+            let connection = |c_id: usize| statistics.connections.iter().find(|c| c.connection == c_id).unwrap();
+            let bends_90 = |bends_90: usize| BendCounts {
+                bends_90,
+                ..Default::default()
+            };
+            assert_eq!(connection(0).bends, bends_90(0));
+            assert_eq!(connection(1).bends, bends_90(1));
+            assert_eq!(statistics.bends, bends_90(1));
+            let length = statistics.connections.iter().map(|c| c.length).sum::<f64>();
+            assert!((statistics.length - length).abs() < 1e-6);
+
+            // The straight and the single-bend channel are as short as their ports allow
+            assert!((connection(0).min_length - 2500.).abs() < 1e-6);
+            assert!((connection(1).min_length - 3500.).abs() < 1e-6);
+            assert!(statistics.connections.iter().all(|c| (c.length - c.min_length).abs() < 1e-6));
+            let min_length = statistics.connections.iter().map(|c| c.min_length).sum::<f64>();
+            assert!((statistics.min_length - min_length).abs() < 1e-6);
+
+            // The locked connection was not routed
+            assert_eq!(connection(2).order, None);
+            assert_eq!(connection(2).expansions, 0);
+            let mut orders = Vec::from_iter([0, 1].map(|c_id| connection(c_id).order.unwrap()));
+            orders.sort();
+            assert_eq!(orders, Vec::from([0, 1]));
+            assert!([0, 1].iter().all(|&c_id| connection(c_id).expansions > 0));
+            assert!(statistics.expansions >= connection(0).expansions + connection(1).expansions);
+            assert!(statistics.time_ms >= 0.);
+        }
+    }
+
+    #[test]
+    fn heuristics() {
+        for layout in [Layout::Rectilinear, Layout::Octilinear] {
+            let input = RouteInput {
+                channel_width: 100.,
+                channel_spacing: 100.,
+                board_width: 4000.,
+                board_height: 4000.,
+                pitch: 500.,
+                pitch_offset_x: 500.,
+                pitch_offset_y: 500.,
+                port_diameter: 200.,
+                max_ports: 20000,
+                layout,
+                connections: Vec::from([RouteInputConnection {
+                    id: 0,
+                    ports: vec![(0, 0), (5, 1)],
+                    ..route_input_connection()
+                }]),
+                keep_outs: Vec::from([KeepOut::Rectangle {
+                    position: [1700., 0.],
+                    dimensions: [200., 3200.],
+                }]),
+                ..route_input()
+            };
+            let length = |heuristic: Heuristic, heuristic_bias: Option<f64>| {
+                let Ok(BoardRouterOutputBoard { connections, statistics, .. }) = route(&RouteInput {
+                    heuristic,
+                    heuristic_bias,
+                    ..input.clone()
+                }) else {
+                    panic!()
+                };
+                assert_eq!(statistics.heuristic_bias, heuristic_bias);
+                let length = connections[0].1.iter().map(hydraulics::channel_length).sum::<f64>();
+                assert!(statistics.connections[0].min_length <= length + 1e-6);
+                length
+            };
+
+            // Heuristics that never overestimate find paths of the same length
+            let shortest = length(Heuristic::Euclidean, None);
+            assert!((length(Heuristic::Layout, None) - shortest).abs() < 1e-6);
+            assert!((length(Heuristic::Octile, None) - shortest).abs() < 1e-6);
+
+            // A biased search still finds a path, which may be longer
+            assert!(length(Heuristic::Layout, Some(3.)) >= shortest - 1e-6);
+        }
+    }
+
+    #[test]
+    fn cost_model() {
+        for layout in [Layout::Rectilinear, Layout::Octilinear] {
+            for bidirectional_search in [false, true] {
+                let input = RouteInput {
+                    channel_width: 100.,
+                    channel_spacing: 100.,
+                    board_width: 4000.,
+                    board_height: 4000.,
+                    pitch: 500.,
+                    pitch_offset_x: 500.,
+                    pitch_offset_y: 500.,
+                    port_diameter: 200.,
+                    max_ports: 20000,
+                    layout,
+                    connections: Vec::from([RouteInputConnection {
+                        id: 0,
+                        ports: vec![(0, 0), (6, 4)],
+                        ..route_input_connection()
+                    }]),
+                    bidirectional_search,
+                    ..route_input()
+                };
+                let bends = |cost_model: CostModel| {
+                    let Ok(BoardRouterOutputBoard { connections, .. }) = route(&RouteInput {
+                        cost_model,
+                        ..input.clone()
+                    }) else {
+                        panic!()
+                    };
+                    let channel = &connections[0].1[0];
+                    let direction = |a: [f64; 2], b: [f64; 2]| {
+                        let length = f64::hypot(b[0] - a[0], b[1] - a[1]);
+                        [(b[0] - a[0]) / length, (b[1] - a[1]) / length]
+                    };
+                    (1..channel.len() - 1)
+                        .filter(|&i| direction(channel[i - 1], channel[i]) != direction(channel[i], channel[i + 1]))
+                        .count()
+                };
+
+                // Among the paths of the same length, the one with a single bend is chosen
+                assert!(bends(CostModel::default()) > 1);
+                let cost_model = match layout {
+                    Layout::Rectilinear => CostModel {
+                        bend_90: 1.,
+                        ..Default::default()
+                    },
+                    Layout::Octilinear => CostModel {
+                        bend_45: 1.,
+                        ..Default::default()
+                    },
+                };
+                assert_eq!(bends(cost_model), 1);
+                assert_eq!(
+                    bends(CostModel {
+                        direction_change: 1.,
+                        ..Default::default()
+                    }),
+                    1
+                );
+            }
+        }
+
+        // Connection 1 stays away from the end of connection 0 as long as it can
+        let input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 4000.,
+            board_height: 4000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Rectilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(0, 0), (4, 0)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(0, 1), (6, 0)],
+                    ..route_input_connection()
+                },
+            ]),
+            cell_size: Some(125.),
+            ..route_input()
+        };
+        let start_row_length = |adjacency: f64| {
+            let Ok(BoardRouterOutputBoard { connections, .. }) = route(&RouteInput {
+                cost_model: CostModel {
+                    adjacency,
+                    ..Default::default()
+                },
+                ..input.clone()
+            }) else {
+                panic!()
+            };
+            let (_, channels) = connections.iter().find(|c| c.0 == 1).unwrap();
+            channels[0]
+                .windows(2)
+                .filter(|segment| segment[0][1] == 1000. && segment[1][1] == 1000.)
+                .map(|segment| (segment[1][0] - segment[0][0]).abs())
+                .sum::<f64>()
+        };
+        assert!(start_row_length(1.) > start_row_length(0.));
+    }
+
+    #[test]
+    fn length_matching() {
+        for layout in [Layout::Rectilinear, Layout::Octilinear] {
+            let input = RouteInput {
+                channel_width: 100.,
+                channel_spacing: 100.,
+                board_width: 4000.,
+                board_height: 4000.,
+                pitch: 500.,
+                pitch_offset_x: 500.,
+                pitch_offset_y: 500.,
+                port_diameter: 200.,
+                max_ports: 20000,
+                layout,
+                connections: Vec::from([
+                    RouteInputConnection {
+                        id: 0,
+                        ports: vec![(0, 0), (6, 0)],
+                        ..route_input_connection()
+                    },
+                    RouteInputConnection {
+                        id: 1,
+                        ports: vec![(0, 2), (4, 2)],
+                        ..route_input_connection()
+                    },
+                    RouteInputConnection {
+                        id: 2,
+                        ports: vec![(0, 5), (4, 5)],
+                        ..route_input_connection()
+                    },
+                ]),
+                length_match_groups: Vec::from([LengthMatchGroup {
+                    connections: vec![0, 1, 2],
+                    tolerance: 100.,
+                    style: None,
+                }]),
+                ..route_input()
+            };
+            let Ok(BoardRouterOutputBoard {
+                connections,
+                lengths,
+                ..
+            }) = route(&input)
+            else {
+                panic!()
+            };
+
+            // The longest connection is not changed, the others are lengthened to match it
+            let length = |c_id: usize| lengths.iter().find(|l| l.0 == c_id).unwrap().1;
+            assert_eq!(lengths.len(), 3);
+            assert_eq!(length(0), 3000.);
+            assert!((length(0) - length(1)).abs() <= 100.);
+            assert!((length(0) - length(2)).abs() <= 100.);
+
+            // Meanders do not overlap other channels
+            for (c_id, channels) in connections.iter() {
+                for point in channels[0].iter() {
+                    assert!(connections
+                        .iter()
+                        .filter(|(other, _)| other != c_id)
+                        .all(|(_, other_channels)| !other_channels[0].contains(point)));
+                }
+            }
+
+            // Without the group, the connections are routed directly
+            let Ok(BoardRouterOutputBoard { lengths, .. }) = route(&RouteInput {
+                length_match_groups: Vec::new(),
+                ..input.clone()
+            }) else {
+                panic!()
+            };
+            assert!(lengths.is_empty());
+        }
+    }
+
+    #[test]
+    fn target_resistance() {
+        let input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 3000.,
+            board_height: 3000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Rectilinear,
+            connections: Vec::from([RouteInputConnection {
+                id: 0,
+                ports: vec![(0, 1), (4, 1)],
+                target_resistance: Some(rectangular_channel_resistance(3000., 100., 50., 1e-3)),
+                ..route_input_connection()
+            }]),
+            channel_height: Some(50.),
+            viscosity: Some(1e-3),
+            ..route_input()
+        };
+        let Ok(BoardRouterOutputBoard { resistances, .. }) = route(&input) else {
+            panic!()
+        };
+        let [(0, ref channel_resistances)] = resistances[..] else {
+            panic!()
+        };
+        let target = input.connections[0].target_resistance.unwrap();
+        assert!(channel_resistances[0] <= target * (1. + 1e-9));
+        assert!(channel_resistances[0] > rectangular_channel_resistance(2500., 100., 50., 1e-3));
+
+        // Without a target, the channel is routed directly
+        let Ok(BoardRouterOutputBoard { resistances, .. }) = route(&RouteInput {
+            connections: Vec::from([RouteInputConnection {
+                target_resistance: None,
+                ..input.connections[0].clone()
+            }]),
+            ..input.clone()
+        }) else {
+            panic!()
+        };
+        assert_eq!(
+            resistances,
+            Vec::from([(0, Vec::from([rectangular_channel_resistance(2000., 100., 50., 1e-3)]))])
+        );
+
+        // The target is not split across the branches of a star
+        let star = RouteInputConnection {
+            ports: vec![(0, 1), (4, 1), (2, 4)],
+            ..input.connections[0].clone()
+        };
+        assert!(matches!(
+            route(&RouteInput {
+                connections: Vec::from([star.clone()]),
+                ..input.clone()
+            }),
+            Err(BoardRouterOutputError::InvalidTargetResistance(0))
+        ));
+        let errors = serde_json::to_string(
+            &validate(ValidateInput {
+                channel_width: Some(100.),
+                channel_spacing: Some(100.),
+                board_width: Some(3000.),
+                board_height: Some(3000.),
+                pitch: Some(500.),
+                pitch_offset_x: Some(500.),
+                pitch_offset_y: Some(500.),
+                port_diameter: Some(200.),
+                max_ports: Some(20000),
+                layout: Some(Layout::Rectilinear),
+                connections: Some(Vec::from([star])),
+                ports: None,
+                edge_ports: None,
+            })
+            .unwrap_err(),
+        )
+        .unwrap();
+        assert!(errors.contains("InvalidConnectionTargetResistance"));
+    }
+
+    #[test]
+    fn serpentine() {
+        let input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 4000.,
+            board_height: 3000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Rectilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(0, 2), (6, 2)],
+                    priority: Some(1),
+                    serpentine: Some(Serpentine {
+                        amplitude: 500.,
+                        turns: 4,
+                        segment: None,
+                    }),
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(1, 1), (5, 1)],
+                    ..route_input_connection()
+                },
+            ]),
+            ordering: ConnectionOrdering::Priority,
+            ..route_input()
+        };
+        let Ok(BoardRouterOutputBoard { connections, .. }) = route(&input) else {
+            panic!()
+        };
+
+        // Every turn adds twice the amplitude
+        let (_, channels) = connections.iter().find(|c| c.0 == 0).unwrap();
+        let channel = &channels[0];
+        assert_eq!(hydraulics::channel_length(channel), 3000. + 4. * 2. * 500.);
+        assert!(channel.iter().all(|p| (p[1] - 1500.).abs() <= 500.));
+        assert!(matches!(
+            dxf::octilinear_outline(channels, 100., &dxf::ChannelCap::Butt),
+            dxf::Polyline::Closed(_)
+        ));
+
+        // The other connection is routed around the serpentine
+        let (_, other_channels) = connections.iter().find(|c| c.0 == 1).unwrap();
+        assert!(other_channels[0].iter().all(|p| !channel.contains(p)));
+
+        // Without space for the serpentine, the connection fails
+        let mut narrow_input = input.clone();
+        narrow_input.connections[0].serpentine = Some(Serpentine {
+            amplitude: 500.,
+            turns: 20,
+            segment: None,
+        });
+        let Err(BoardRouterOutputError::PartialResult(BoardRouterOutputBoard { connections, .. })) =
+            route(&narrow_input)
+        else {
+            panic!()
+        };
+        assert!(connections.iter().all(|c| c.0 != 0));
+    }
+
+    #[test]
+    fn smoothing() {
+        for layout in [Layout::Rectilinear, Layout::Octilinear] {
+            let input = RouteInput {
+                channel_width: 100.,
+                channel_spacing: 100.,
+                board_width: 3000.,
+                board_height: 3000.,
+                pitch: 500.,
+                pitch_offset_x: 500.,
+                pitch_offset_y: 500.,
+                port_diameter: 200.,
+                max_ports: 20000,
+                layout,
+                connections: Vec::from([
+                    RouteInputConnection {
+                        id: 0,
+                        ports: vec![(0, 0), (4, 3)],
+                        ..route_input_connection()
+                    },
+                    RouteInputConnection {
+                        id: 1,
+                        ports: vec![(0, 1), (3, 4)],
+                        ..route_input_connection()
+                    },
+                    RouteInputConnection {
+                        id: 2,
+                        ports: vec![(1, 0), (4, 2), (2, 4)],
+                        ..route_input_connection()
+                    },
+                ]),
+                ..route_input()
+            };
+            let Ok(routed) = route(&input) else { panic!() };
+            assert!(routed.bend_radii.is_empty());
+
+            let mut smoothed_input = input.clone();
+            smoothed_input.smoothing = Some(Smoothing {
+                min_bend_radius: 100.,
+                any_angle: true,
+            });
+            let Ok(smoothed) = route(&smoothed_input) else {
+                panic!()
+            };
+
+            // The channels keep their ends, and corners are either rounded with the minimum bend radius or sharp
+            for ((c_id, channels), (_, routed_channels)) in
+                smoothed.connections.iter().zip(routed.connections.iter())
+            {
+                let (_, radii) = smoothed.bend_radii.iter().find(|(id, _)| id == c_id).unwrap();
+                for ((channel, radii), routed_channel) in
+                    channels.iter().zip(radii).zip(routed_channels)
+                {
+                    assert_eq!(channel.first(), routed_channel.first());
+                    assert_eq!(channel.last(), routed_channel.last());
+                    assert!(channel.len() <= routed_channel.len());
+                    assert_eq!(radii.len(), channel.len());
+                    assert_eq!(radii[0], 0.);
+                    assert!(radii.iter().all(|&r| r == 0. || r == 100.));
+                }
+            }
+            assert!(smoothed
+                .bend_radii
+                .iter()
+                .any(|(_, radii)| radii.iter().flatten().any(|&r| r > 0.)));
+
+            // Center lines, including the fillet arcs, keep the clearance
+            for (a_id, a_channels) in smoothed.connections.iter() {
+                let a_lines = smoothed.center_lines(*a_id, a_channels);
+                for (b_id, b_channels) in smoothed.connections.iter().filter(|(b_id, _)| b_id != a_id) {
+                    let b_lines = smoothed.center_lines(*b_id, b_channels);
+                    for a in a_lines.iter().flat_map(|c| c.windows(2)) {
+                        for b in b_lines.iter().flat_map(|c| c.windows(2)) {
+                            let distance = smoothing::segments_distance(a[0], a[1], b[0], b[1]);
+                            assert!(distance >= 200. - 1e-6);
+                        }
+                    }
+                }
+            }
+
+            let dxf::GenerateSVGOutput(svg) = dxf::generate_svg(dxf::GenerateSVGInput {
+                connections: smoothed,
+                channel_width: 100.,
+                channel_cap: dxf::ChannelCap::Butt,
+                board_width: 3000.,
+                board_height: 3000.,
+                port_diameter: Some(200.),
+                ports: Vec::new(),
+                grid: None,
+            });
+            assert_eq!(svg.matches("<path").count(), 5);
+        }
+    }
+
+    #[test]
+    fn explicit_ports() {
+        let definitions = [
+            ((0, 0), [400., 450.], 200.),
+            ((1, 0), [2600., 600.], 300.),
+            ((2, 0), [450., 2550.], 150.),
+            ((3, 0), [2550., 2400.], 250.),
+            ((4, 0), [1200., 1300.], 250.),
+            ((5, 0), [1900., 1700.], 400.),
+        ];
+        let input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 3000.,
+            board_height: 3000.,
+            layout: Layout::Octilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(0, 0), (1, 0)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(2, 0), (3, 0)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 2,
+                    ports: vec![(4, 0), (5, 0)],
+                    ..route_input_connection()
+                },
+            ]),
+            ports: Vec::from_iter(definitions.iter().map(|&(port, position, diameter)| {
+                PortDefinition {
+                    port,
+                    name: format!("P{}", port.0 + 1),
+                    position,
+                    diameter,
+                }
+            })),
+            ..route_input()
+        };
+        let Ok(BoardRouterOutputBoard { connections, .. }) = route(&input) else {
+            panic!()
+        };
+
+        // Channels end at the port positions and keep away from foreign ports
+        for (c_id, channels) in connections.iter() {
+            let channel = &channels[0];
+            assert_eq!(channel.first(), Some(&definitions[2 * c_id].1));
+            assert_eq!(channel.last(), Some(&definitions[2 * c_id + 1].1));
+            for (i, (_, [x, y], diameter)) in definitions.iter().enumerate() {
+                if i / 2 != *c_id {
+                    assert!(channel
+                        .iter()
+                        .all(|p| f64::hypot(p[0] - x, p[1] - y) >= diameter / 2. + 150.));
+                }
+            }
+        }
+
+        // Connections must refer to defined ports
+        let mut undefined_input = input.clone();
+        undefined_input.connections[0].ports[1] = (9, 9);
+        assert!(matches!(
+            route(&undefined_input),
+            Err(BoardRouterOutputError::UndefinedPort(0, (9, 9)))
+        ));
+
+        // The pitch grid is one generator of explicit ports
+        let board_router::ComputePortGridOutput { ports } =
+            board_router::compute_port_grid(board_router::ComputePortGridInput {
+                board_width: 3000.,
+                board_height: 3000.,
+                pitch: 500.,
+                pitch_offset_x: 500.,
+                pitch_offset_y: 500.,
+                port_diameter: 200.,
+            });
+        assert_eq!(ports.len(), 25);
+        assert_eq!((ports[0].port, ports[0].name.as_str(), ports[0].position), ((0, 0), "A1", [500., 500.]));
+        assert_eq!((ports[24].port, ports[24].name.as_str(), ports[24].position), ((4, 4), "E5", [2500., 2500.]));
+
+        // Validation needs no pitch for explicit ports, but checks the definitions
+        let validate_ports = |ports: Vec<PortDefinition>| {
+            validate(ValidateInput {
+                channel_width: Some(100.),
+                channel_spacing: Some(100.),
+                board_width: Some(3000.),
+                board_height: Some(3000.),
+                pitch: None,
+                pitch_offset_x: None,
+                pitch_offset_y: None,
+                port_diameter: None,
+                max_ports: Some(20000),
+                layout: Some(Layout::Octilinear),
+                connections: Some(input.connections.clone()),
+                ports: Some(ports),
+                edge_ports: None,
+            })
+        };
+        assert!(validate_ports(input.ports.clone()).is_ok());
+        let mut invalid_ports = input.ports.clone();
+        invalid_ports[1].port = (0, 0);
+        invalid_ports[2].position = [50., 2550.];
+        let errors = serde_json::to_string(&validate_ports(invalid_ports).unwrap_err()).unwrap();
+        assert!(errors.contains("Duplicate"));
+        assert!(errors.contains("OutsideBoard"));
+        assert!(errors.contains("UndefinedConnectionPort"));
+    }
+
+    #[test]
+    fn edge_ports() {
+        let input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 3000.,
+            board_height: 3000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(2, 2), (10, 0)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(0, 0), (4, 0)],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 2,
+                    ports: vec![(2, 4), (11, 0)],
+                    ..route_input_connection()
+                },
+            ]),
+            edge_ports: Vec::from([
+                EdgePort {
+                    port: (10, 0),
+                    side: BoardSide::Left,
+                    position: 1500.,
+                    width: 300.,
+                },
+                EdgePort {
+                    port: (11, 0),
+                    side: BoardSide::Right,
+                    position: 2250.,
+                    width: 200.,
+                },
+            ]),
+            ..route_input()
+        };
+        let Ok(output) = route(&input) else { panic!() };
+
+        // Channels end at the center of the openings, and other channels keep away from them
+        let channel = |c_id: usize| &output.connections.iter().find(|c| c.0 == c_id).unwrap().1[0];
+        assert_eq!(channel(0).first(), Some(&[1500., 1500.]));
+        assert_eq!(channel(0).last(), Some(&[0., 1500.]));
+        assert_eq!(channel(2).last(), Some(&[3000., 2250.]));
+        for (c_id, [x, y], width) in [(0, [0., 1500.], 300.), (2, [3000., 2250.], 200.)] {
+            for (_, channels) in output.connections.iter().filter(|c| c.0 != c_id) {
+                assert!(channels
+                    .iter()
+                    .flatten()
+                    .all(|p| f64::hypot(p[0] - x, p[1] - y) >= width / 2. + 150.));
+            }
+        }
+
+        // The board outline is opened at the edge ports
+        let dxf_lines = |edge_ports: &Vec<EdgePort>| {
+            let dxf::GenerateDXFOutput(dxf) = dxf::generate_dxf(
+                serde_json::from_value(serde_json::json!({
+                    "connections": output,
+                    "channel_width": 100.,
+                    "channel_cap": "Butt",
+                    "board_width": 3000.,
+                    "board_height": 3000.,
+                    "edge_ports": edge_ports,
+                }))
+                .unwrap(),
+            );
+            dxf.matches("LINE").count()
+        };
+        assert_eq!(dxf_lines(&input.edge_ports), dxf_lines(&Vec::new()) + 2);
+    }
+
+    #[test]
+    fn port_candidates() {
+        let input = RouteInput {
+            channel_width: 100.,
+            channel_spacing: 100.,
+            board_width: 3000.,
+            board_height: 3000.,
+            pitch: 500.,
+            pitch_offset_x: 500.,
+            pitch_offset_y: 500.,
+            port_diameter: 200.,
+            max_ports: 20000,
+            layout: Layout::Octilinear,
+            connections: Vec::from([
+                RouteInputConnection {
+                    id: 0,
+                    ports: vec![(0, 2), (4, 2)],
+                    port_candidates: vec![vec![], vec![(1, 4), (0, 4), (2, 4)]],
+                    ..route_input_connection()
+                },
+                RouteInputConnection {
+                    id: 1,
+                    ports: vec![(2, 0), (2, 4)],
+                    ..route_input_connection()
+                },
+            ]),
+            ..route_input()
+        };
+
+        // The closest free candidate gives the shortest channel, (2, 4) is taken by the other connection
+        let Ok(BoardRouterOutputBoard {
+            connections,
+            assigned_ports,
+            ..
+        }) = route(&input)
+        else {
+            panic!()
+        };
+        assert_eq!(assigned_ports, Vec::from([(0, vec![(0, 2), (0, 4)])]));
+        let (_, channels) = connections.iter().find(|c| c.0 == 0).unwrap();
+        assert_eq!(channels[0].last(), Some(&[500., 2500.]));
+
+        // Without candidates, the given ports are kept and no assignment is reported
+        let mut fixed_input = input.clone();
+        fixed_input.connections[0].port_candidates = Vec::new();
+        let (Ok(BoardRouterOutputBoard { assigned_ports, .. })
+        | Err(BoardRouterOutputError::PartialResult(BoardRouterOutputBoard { assigned_ports, .. }))) =
+            route(&fixed_input)
+        else {
+            panic!()
+        };
+        assert!(assigned_ports.is_empty());
     }
 }
@@ -0,0 +1,275 @@
+use core::f64;
+
+use crate::board_router::{segment_distance, Channel, Point};
+
+// Largest angle covered by a single line of the polylines approximating fillet arcs
+const ARC_ANGLE_STEP: f64 = f64::consts::PI / 36.;
+
+fn difference(a: Point, b: Point) -> Point {
+    [b[0] - a[0], b[1] - a[1]]
+}
+
+fn length(v: Point) -> f64 {
+    f64::hypot(v[0], v[1])
+}
+
+fn cross(u: Point, v: Point) -> f64 {
+    u[0] * v[1] - u[1] * v[0]
+}
+
+// Distance between the line segments a-b and c-d
+pub fn segments_distance(a: Point, b: Point, c: Point, d: Point) -> f64 {
+    let (ab, cd) = (difference(a, b), difference(c, d));
+    let crosses = cross(ab, difference(a, c)) * cross(ab, difference(a, d)) < 0.
+        && cross(cd, difference(c, a)) * cross(cd, difference(c, b)) < 0.;
+    if crosses {
+        0.
+    } else {
+        segment_distance(a, c, d)
+            .min(segment_distance(b, c, d))
+            .min(segment_distance(c, a, b))
+            .min(segment_distance(d, a, b))
+    }
+}
+
+// Removes points on the straight line between their neighbors
+pub fn simplify(channel: &Channel) -> Channel {
+    let mut simplified = Channel::new();
+    for (i, &point) in channel.iter().enumerate() {
+        if simplified.last() == Some(&point) {
+            continue;
+        }
+        if i + 1 < channel.len() && !simplified.is_empty() {
+            let previous = *simplified.last().unwrap();
+            let next = channel[i + 1];
+            let (u, v) = (difference(previous, point), difference(point, next));
+            if cross(u, v).abs() <= 1e-9 * length(u) * length(v) && u[0] * v[0] + u[1] * v[1] > 0. {
+                continue;
+            }
+        }
+        simplified.push(point);
+    }
+    simplified
+}
+
+// Whether the channel runs in a single direction on both axes
+fn is_monotone(points: &[Point]) -> bool {
+    let steps = Vec::from_iter(points.windows(2).map(|w| difference(w[0], w[1])));
+    [0, 1]
+        .iter()
+        .all(|&axis| steps.iter().all(|s| s[axis] >= 0.) || steps.iter().all(|s| s[axis] <= 0.))
+}
+
+// Replaces staircases by straight lines of any angle, as long as the skipped corners are at most max_deviation off the
+// line and the line is clear
+pub fn shortcut(
+    channel: &Channel,
+    max_deviation: f64,
+    clear: impl Fn(Point, Point) -> bool,
+) -> Channel {
+    if channel.len() < 3 {
+        return channel.clone();
+    }
+    let mut shortened = Channel::from([channel[0]]);
+    let mut i = 0;
+    while i < channel.len() - 1 {
+        let mut j = i + 1;
+        for k in i + 2..channel.len() {
+            let close = channel[i + 1..k]
+                .iter()
+                .all(|&p| segment_distance(p, channel[i], channel[k]) <= max_deviation);
+            if !close || !is_monotone(&channel[i..=k]) || !clear(channel[i], channel[k]) {
+                break;
+            }
+            j = k;
+        }
+        shortened.push(channel[j]);
+        i = j;
+    }
+    shortened
+}
+
+// Polyline along the fillet arc of the given radius that replaces the corner, from the tangent point on the previous
+// line to the one on the next line
+pub fn fillet_arc(previous: Point, corner: Point, next: Point, radius: f64) -> Vec<Point> {
+    let (u, v) = (difference(previous, corner), difference(corner, next));
+    let (u, v) = (
+        [u[0] / length(u), u[1] / length(u)],
+        [v[0] / length(v), v[1] / length(v)],
+    );
+    let deflection = (u[0] * v[0] + u[1] * v[1]).clamp(-1., 1.).acos();
+    let tangent = radius * (deflection / 2.).tan();
+    let start = [corner[0] - u[0] * tangent, corner[1] - u[1] * tangent];
+    // The center lies on the inner side of the turn
+    let side = cross(u, v).signum();
+    let center = [
+        start[0] - side * u[1] * radius,
+        start[1] + side * u[0] * radius,
+    ];
+    let start_angle = f64::atan2(start[1] - center[1], start[0] - center[0]);
+    let steps = ((deflection / ARC_ANGLE_STEP).ceil() as usize).max(1);
+    Vec::from_iter((0..=steps).map(|k| {
+        let angle = start_angle + side * deflection * k as f64 / steps as f64;
+        [
+            center[0] + radius * angle.cos(),
+            center[1] + radius * angle.sin(),
+        ]
+    }))
+}
+
+// Fillet radius of each channel point: the minimum bend radius if the corner has room for the arc and the arc is clear,
+// 0 otherwise (and at the channel ends)
+pub fn fillet_radii(
+    channel: &Channel,
+    min_bend_radius: f64,
+    clear: impl Fn(Point, Point) -> bool,
+) -> Vec<f64> {
+    let n = channel.len();
+    let mut radii = vec![0.; n];
+    for i in 1..n.saturating_sub(1) {
+        let (u, v) = (
+            difference(channel[i - 1], channel[i]),
+            difference(channel[i], channel[i + 1]),
+        );
+        let deflection = ((u[0] * v[0] + u[1] * v[1]) / (length(u) * length(v)))
+            .clamp(-1., 1.)
+            .acos();
+        if deflection <= 1e-9 {
+            continue;
+        }
+        // Lines between two corners are shared by both fillets
+        let tangent = min_bend_radius * (deflection / 2.).tan();
+        let room_before = if i == 1 { length(u) } else { length(u) / 2. };
+        let room_after = if i == n - 2 {
+            length(v)
+        } else {
+            length(v) / 2.
+        };
+        let tolerance = 1e-9 * (length(u) + length(v));
+        if tangent > room_before + tolerance || tangent > room_after + tolerance {
+            continue;
+        }
+        let arc = fillet_arc(channel[i - 1], channel[i], channel[i + 1], min_bend_radius);
+        if arc.windows(2).all(|w| clear(w[0], w[1])) {
+            radii[i] = min_bend_radius;
+        }
+    }
+    radii
+}
+
+// Center line of the channel with its corners replaced by the fillet arcs
+pub fn rounded_channel(channel: &Channel, radii: &[f64]) -> Channel {
+    let mut rounded = Channel::new();
+    for (i, &point) in channel.iter().enumerate() {
+        let corner = i > 0 && i + 1 < channel.len() && radii.get(i).is_some_and(|&r| r > 0.);
+        let points = if corner {
+            fillet_arc(channel[i - 1], point, channel[i + 1], radii[i])
+        } else {
+            Vec::from([point])
+        };
+        for point in points {
+            if rounded
+                .last()
+                .is_none_or(|&last| length(difference(last, point)) > 1e-9)
+            {
+                rounded.push(point);
+            }
+        }
+    }
+    rounded
+}
+
+#[cfg(test)]
+mod tests {
+    mod simplify {
+        use crate::smoothing::simplify;
+
+        #[test]
+        fn collinear_points() {
+            assert_eq!(
+                simplify(&Vec::from([
+                    [0., 0.],
+                    [1., 0.],
+                    [2., 0.],
+                    [2., 1.],
+                    [2., 2.]
+                ])),
+                Vec::from([[0., 0.], [2., 0.], [2., 2.]])
+            );
+        }
+    }
+
+    mod shortcut {
+        use crate::smoothing::{shortcut, simplify};
+
+        #[test]
+        fn staircase() {
+            let staircase = Vec::from([[0., 0.], [1., 0.], [1., 1.], [2., 1.], [2., 2.], [3., 2.]]);
+            assert_eq!(
+                shortcut(&staircase, 0.75, |_, _| true),
+                Vec::from([[0., 0.], [3., 2.]])
+            );
+        }
+
+        #[test]
+        fn keeps_meanders() {
+            let meander = simplify(&Vec::from([
+                [0., 0.],
+                [1., 0.],
+                [1., 2.],
+                [2., 2.],
+                [2., 0.],
+                [3., 0.],
+            ]));
+            assert_eq!(shortcut(&meander, 0.75, |_, _| true), meander);
+        }
+
+        #[test]
+        fn single_cell_bump() {
+            // Only the monotone parts of the bump are shortcut
+            let bump = Vec::from([[0., 0.], [1., 0.], [1., 1.], [2., 1.], [2., 0.], [3., 0.]]);
+            assert_eq!(
+                shortcut(&bump, 0.75, |_, _| true),
+                Vec::from([[0., 0.], [2., 1.], [3., 0.]])
+            );
+        }
+
+        #[test]
+        fn blocked() {
+            let staircase = Vec::from([[0., 0.], [1., 0.], [1., 1.]]);
+            assert_eq!(shortcut(&staircase, 0.75, |_, _| false), staircase);
+        }
+    }
+
+    mod fillet_radii {
+        use crate::smoothing::{fillet_arc, fillet_radii, rounded_channel};
+
+        #[test]
+        fn room_for_the_arc() {
+            let channel = Vec::from([[0., 0.], [2., 0.], [2., 2.], [4., 2.]]);
+            assert_eq!(fillet_radii(&channel, 1., |_, _| true), [0., 1., 1., 0.]);
+            assert_eq!(fillet_radii(&channel, 1.5, |_, _| true), [0., 0., 0., 0.]);
+            assert_eq!(fillet_radii(&channel, 1., |_, _| false), [0., 0., 0., 0.]);
+        }
+
+        #[test]
+        fn tangent_arc() {
+            let arc = fillet_arc([0., 0.], [2., 0.], [2., 2.], 1.);
+            let [start, end] = [arc[0], *arc.last().unwrap()];
+            assert!(f64::hypot(start[0] - 1., start[1]) < 1e-9);
+            assert!(f64::hypot(end[0] - 2., end[1] - 1.) < 1e-9);
+            assert!(arc
+                .iter()
+                .all(|p| (f64::hypot(p[0] - 1., p[1] - 1.) - 1.).abs() < 1e-9));
+        }
+
+        #[test]
+        fn rounded() {
+            let channel = Vec::from([[0., 0.], [2., 0.], [2., 2.]]);
+            let rounded = rounded_channel(&channel, &[0., 1., 0.]);
+            assert_eq!(rounded[0], [0., 0.]);
+            assert_eq!(*rounded.last().unwrap(), [2., 2.]);
+            assert!(rounded.len() > 4);
+        }
+    }
+}
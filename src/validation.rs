@@ -1,8 +1,8 @@
 use serde::{Deserialize, Serialize};
 
 use crate::board_router::{
-    compute_ports, ComputePortsInput, ComputePortsOutput, ConnectionID, Layout, Port,
-    RouteInputConnections,
+    compute_ports, BoardSide, ComputePortsInput, ComputePortsOutput, ConnectionID, EdgePort,
+    Layout, Port, PortDefinition, RouteInputConnections,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -18,6 +18,8 @@ pub struct ValidateInput {
     pub max_ports: Option<usize>,
     pub layout: Option<Layout>,
     pub connections: Option<RouteInputConnections>,
+    pub ports: Option<Vec<PortDefinition>>, // explicit ports, replacing the pitch grid and the port diameter
+    pub edge_ports: Option<Vec<EdgePort>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -48,6 +50,12 @@ pub enum ValidationError {
     MaxPortsExceeded(ActualPorts, MaxPorts),
     InvalidConnectionPortX(ConnectionID, Port),
     InvalidConnectionPortY(ConnectionID, Port),
+    InvalidConnectionChannelWidth(ConnectionID),
+    InvalidConnectionChannelSpacing(ConnectionID),
+    InvalidConnectionTargetResistance(ConnectionID), // not positive or not on a 2-port connection
+    PortDefinitionError(Port, PortDefinitionError),
+    UndefinedConnectionPort(ConnectionID, Port),
+    EdgePortError(Port, EdgePortError),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -100,6 +108,20 @@ pub enum ChannelSpacingError {
     NotPositive,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub enum PortDefinitionError {
+    DiameterNotPositive,
+    OutsideBoard,
+    Duplicate,
+    Overlapping(Port),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum EdgePortError {
+    WidthNotPositive,
+    OutsideSide,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ValidationWarning {
     PitchNotMultiple(f64),
@@ -121,6 +143,8 @@ macro_rules! some {
 pub fn validate(input: ValidateInput) -> Result<ValidationOk, ValidationErr> {
     let mut errors = Vec::new();
     let mut warnings = Vec::new();
+    // The pitch grid and the port diameter are only needed without explicit ports
+    let explicit_ports = input.ports.as_ref().is_some_and(|ports| !ports.is_empty());
 
     if let Some(board_width) = input.board_width {
         if board_width <= 0. {
@@ -158,7 +182,7 @@ pub fn validate(input: ValidateInput) -> Result<ValidationOk, ValidationErr> {
                 PortDiameterError::NotPositive,
             ));
         }
-    } else {
+    } else if !explicit_ports {
         errors.push(ValidationError::PortDiameterError(
             PortDiameterError::Undefined,
         ));
@@ -172,7 +196,7 @@ pub fn validate(input: ValidateInput) -> Result<ValidationOk, ValidationErr> {
                 1.5 * (pitch / 1.5).round(),
             ));
         }
-    } else {
+    } else if !explicit_ports {
         errors.push(ValidationError::PitchError(PitchError::Undefined));
     }
 
@@ -182,7 +206,7 @@ pub fn validate(input: ValidateInput) -> Result<ValidationOk, ValidationErr> {
                 PitchOffsetXError::NotPositive,
             ));
         }
-    } else {
+    } else if !explicit_ports {
         errors.push(ValidationError::PitchOffsetXError(
             PitchOffsetXError::Undefined,
         ));
@@ -194,7 +218,7 @@ pub fn validate(input: ValidateInput) -> Result<ValidationOk, ValidationErr> {
                 PitchOffsetYError::NotPositive,
             ));
         }
-    } else {
+    } else if !explicit_ports {
         errors.push(ValidationError::PitchOffsetYError(
             PitchOffsetYError::Undefined,
         ));
@@ -240,7 +264,8 @@ pub fn validate(input: ValidateInput) -> Result<ValidationOk, ValidationErr> {
         }
     });
 
-    if input.board_width.is_some()
+    if !explicit_ports
+        && input.board_width.is_some()
         && input.board_height.is_some()
         && input.pitch.is_some()
         && input.pitch_offset_x.is_some()
@@ -263,6 +288,81 @@ pub fn validate(input: ValidateInput) -> Result<ValidationOk, ValidationErr> {
         }
     }
 
+    if let Some(ports) = input.ports.as_ref().filter(|_| explicit_ports) {
+        if let Some(max_ports) = input.max_ports {
+            if ports.len() > max_ports {
+                errors.push(ValidationError::MaxPortsExceeded(ports.len(), max_ports));
+            }
+        }
+        for (i, definition) in ports.iter().enumerate() {
+            let port_error = |error| ValidationError::PortDefinitionError(definition.port, error);
+            let radius = definition.diameter / 2.;
+            let [x, y] = definition.position;
+            if definition.diameter <= 0. {
+                errors.push(port_error(PortDefinitionError::DiameterNotPositive));
+            }
+            let outside = |position: f64, size: Option<f64>| {
+                position - radius < 0. || size.is_some_and(|size| position + radius > size)
+            };
+            if outside(x, input.board_width) || outside(y, input.board_height) {
+                errors.push(port_error(PortDefinitionError::OutsideBoard));
+            }
+            for other in ports[..i].iter() {
+                if other.port == definition.port {
+                    errors.push(port_error(PortDefinitionError::Duplicate));
+                } else if f64::hypot(other.position[0] - x, other.position[1] - y)
+                    < radius + other.diameter / 2.
+                {
+                    errors.push(port_error(PortDefinitionError::Overlapping(other.port)));
+                }
+            }
+        }
+        let edge_ports = input.edge_ports.iter().flatten();
+        for connection in input.connections.iter().flatten() {
+            let connection_ports = connection.ports.iter().chain(connection.branch_port.iter());
+            for port in connection_ports.chain(connection.port_candidates.iter().flatten()) {
+                if !ports.iter().any(|definition| definition.port == *port)
+                    && !edge_ports.clone().any(|edge_port| edge_port.port == *port)
+                {
+                    errors.push(ValidationError::UndefinedConnectionPort(connection.id, *port));
+                }
+            }
+        }
+    }
+
+    for edge_port in input.edge_ports.iter().flatten() {
+        let edge_port_error = |error| ValidationError::EdgePortError(edge_port.port, error);
+        if edge_port.width <= 0. {
+            errors.push(edge_port_error(EdgePortError::WidthNotPositive));
+        }
+        let side_length = match edge_port.side {
+            BoardSide::Top | BoardSide::Bottom => input.board_width,
+            BoardSide::Left | BoardSide::Right => input.board_height,
+        };
+        let half_width = edge_port.width / 2.;
+        if edge_port.position - half_width < 0.
+            || side_length.is_some_and(|length| edge_port.position + half_width > length)
+        {
+            errors.push(edge_port_error(EdgePortError::OutsideSide));
+        }
+    }
+
+    if let Some(connections) = &input.connections {
+        for connection in connections {
+            if connection.channel_width.is_some_and(|width| width <= 0.) {
+                errors.push(ValidationError::InvalidConnectionChannelWidth(connection.id));
+            }
+            if connection.channel_spacing.is_some_and(|spacing| spacing <= 0.) {
+                errors.push(ValidationError::InvalidConnectionChannelSpacing(connection.id));
+            }
+            if connection.target_resistance.is_some_and(|resistance| {
+                resistance <= 0. || connection.ports.len() != 2 || connection.branch_port.is_some()
+            }) {
+                errors.push(ValidationError::InvalidConnectionTargetResistance(connection.id));
+            }
+        }
+    }
+
     if errors.len() > 0 {
         Err(ValidationErr { warnings, errors })
     } else {